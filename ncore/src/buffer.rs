@@ -0,0 +1,126 @@
+/*! Buffer modes and the frame index math the swapchain hook runs */
+
+/// Current swapchain buffer mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BufferMode {
+    /// lower latency
+    Double = 2,
+    /// default SSBU behaviour, higher latency.
+    Triple = 3,
+}
+
+impl BufferMode {
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            2 => Some(Self::Double),
+            3 => Some(Self::Triple),
+            _ => None,
+        }
+    }
+
+    /// Number of active textures for this mode.
+    pub fn texture_count(self) -> i32 {
+        self as i32
+    }
+}
+
+// FRAMES IN FLIGHT MANAGEMENT:
+// SSBU default path is effectively (+2) over a triple-buffered ring.
+// Console double-buffer mode uses (+1) % 2.
+
+/// How the frame index hook picks the texture the frame renders to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameIndexPolicy {
+    /// SSBU default, `(index + 2) % 3`
+    Default = 0,
+    /// Console triple buffer, `(index + 1) % 3`
+    Triple = 1,
+    /// Console double buffer, `(index + 1) % 2`
+    Double = 2,
+    /// Emulator, `index % 2`
+    EmulatorImmediate = 3,
+}
+
+impl FrameIndexPolicy {
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Self::Default),
+            1 => Some(Self::Triple),
+            2 => Some(Self::Double),
+            3 => Some(Self::EmulatorImmediate),
+            _ => None,
+        }
+    }
+
+    /// The console policy that matches a buffer mode.
+    pub fn for_buffer_mode(mode: BufferMode) -> Self {
+        match mode {
+            BufferMode::Double => Self::Double,
+            BufferMode::Triple => Self::Triple,
+        }
+    }
+}
+
+/// Computes the texture index the frame renders to under `policy`. Reduces before adding so no index overflows.
+pub const fn compute_frame_index(policy: FrameIndexPolicy, index: u64) -> u64 {
+    match policy {
+        FrameIndexPolicy::Default => (index % 3 + 2) % 3,
+        FrameIndexPolicy::Triple => (index % 3 + 1) % 3,
+        FrameIndexPolicy::Double => (index % 2 + 1) % 2,
+        FrameIndexPolicy::EmulatorImmediate => index % 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indices(policy: FrameIndexPolicy) -> Vec<u64> {
+        (0..6).map(|index| compute_frame_index(policy, index)).collect()
+    }
+
+    #[test]
+    fn default_renders_two_ahead_in_a_triple_ring() {
+        assert_eq!(indices(FrameIndexPolicy::Default), vec![2, 0, 1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn triple_renders_one_ahead_in_a_triple_ring() {
+        assert_eq!(indices(FrameIndexPolicy::Triple), vec![1, 2, 0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn double_renders_one_ahead_in_a_double_ring() {
+        assert_eq!(indices(FrameIndexPolicy::Double), vec![1, 0, 1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn emulator_renders_to_the_current_texture() {
+        assert_eq!(indices(FrameIndexPolicy::EmulatorImmediate), vec![0, 1, 0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn wraps_around_at_the_largest_index() {
+        // u64::MAX is 0 mod 3 and 1 mod 2; adding to it must not overflow.
+        let last = u64::MAX;
+        assert_eq!(compute_frame_index(FrameIndexPolicy::Default, last), 2);
+        assert_eq!(compute_frame_index(FrameIndexPolicy::Triple, last), 1);
+        assert_eq!(compute_frame_index(FrameIndexPolicy::Double, last), 0);
+        assert_eq!(compute_frame_index(FrameIndexPolicy::EmulatorImmediate, last), 1);
+    }
+
+    #[test]
+    fn policy_round_trips_through_its_discriminant() {
+        for policy in [
+            FrameIndexPolicy::Default,
+            FrameIndexPolicy::Triple,
+            FrameIndexPolicy::Double,
+            FrameIndexPolicy::EmulatorImmediate,
+        ] {
+            assert_eq!(FrameIndexPolicy::from_u8(policy as u8), Some(policy));
+        }
+        assert_eq!(FrameIndexPolicy::from_u8(4), None);
+    }
+}
@@ -0,0 +1,146 @@
+/*! The config every plugin hands to `Install_SSBU_Sync`, as stored in a `ssbusync.toml` profile */
+use serde::{Deserialize, Serialize};
+
+/// Where the `profiling` stream goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfilingSink {
+    /// Serve it on TCP port 1776 for `nprof connect`.
+    #[default]
+    Tcp,
+    /// Write it to `sd:/ultimate/ssbusync/captures/` for `nprof replay`.
+    File,
+    Both,
+}
+
+/// Which tracer `profiling` feeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfilingBackend {
+    /// The binary span stream read by `nprof`, sent to `profiling_sink`.
+    #[default]
+    Nprof,
+    /// JSON lines of frame history entries and markers, one file per boot in `[Frametracer] dir`.
+    Frametracer,
+    Both,
+}
+
+impl ProfilingBackend {
+    pub fn nprof(self) -> bool {
+        matches!(self, Self::Nprof | Self::Both)
+    }
+
+    pub fn frametracer(self) -> bool {
+        matches!(self, Self::Frametracer | Self::Both)
+    }
+}
+
+/// The most verbose log level written; `off` silences ssbusync entirely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Where ssbusync's logs go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogOutput {
+    /// The skyline log, as before.
+    #[default]
+    Skyline,
+    /// `sd:/ultimate/ssbusync/ssbusync.log`, rotated at 1 MB.
+    File,
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct SsbuSyncConfig {
+    pub disable_vsync: bool,
+    pub disable_pacer: bool,
+    pub slow_pacer_bias: bool,
+    pub pacer_bias_double: u16,
+    pub pacer_bias_triple: u16,
+    pub enable_triple_buffer: bool,
+    pub allow_buffer_swap: bool,
+    pub smooth_ffa: bool,
+    pub auto_buffer_policy: bool,
+    pub triple_fighter_threshold: u8,
+    pub double_fighter_threshold: u8,
+    pub heavy_fighter_weight: u8,
+    pub buffer_policy_hold_frames: u16,
+    pub online_only: bool,
+    pub watchdog: bool,
+    pub watchdog_window_frames: u16,
+    pub watchdog_skip_threshold: u16,
+    pub watchdog_cooldown_frames: u16,
+    /// Keep present latency statistics from the frame history; see `latency::summary`.
+    pub latency_stats: bool,
+    pub latency_window_frames: u16,
+    pub profiling: bool,
+    pub profiling_backend: ProfilingBackend,
+    pub profiling_sink: ProfilingSink,
+    /// Start a new capture file once the current one reaches this size.
+    pub profiling_capture_rotate_mb: u16,
+    /// Capture files kept per session; older ones are deleted.
+    pub profiling_capture_max_files: u16,
+    /// Stop writing capture files (and frametracer sessions) after this long, 0 for no limit.
+    pub profiling_capture_max_seconds: u32,
+    /// Frames kept for the next TCP client while nobody is connected.
+    pub profiling_backlog_frames: u16,
+    pub log_level: LogLevel,
+    pub log_output: LogOutput,
+    /// Re-read the profile when `ssbusync.toml` changes and apply what can change without a restart; see `reload`.
+    pub hot_reload: bool,
+    #[serde(skip)]
+    pub emulator_check: bool,
+    #[serde(skip)]
+    pub override_config: bool,
+}
+
+impl Default for SsbuSyncConfig {
+    fn default() -> Self {
+        Self {
+            disable_vsync: true,
+            disable_pacer: false,
+            slow_pacer_bias: false,
+            pacer_bias_double: 1,
+            pacer_bias_triple: 1,
+            enable_triple_buffer: true,
+            allow_buffer_swap: false,
+            smooth_ffa: false,
+            auto_buffer_policy: false,
+            triple_fighter_threshold: 4,
+            double_fighter_threshold: 2,
+            heavy_fighter_weight: 1,
+            buffer_policy_hold_frames: 30,
+            online_only: false,
+            watchdog: false,
+            watchdog_window_frames: 120,
+            watchdog_skip_threshold: 6,
+            watchdog_cooldown_frames: 600,
+            latency_stats: false,
+            latency_window_frames: 600,
+            profiling: false,
+            profiling_backend: ProfilingBackend::Nprof,
+            profiling_sink: ProfilingSink::Tcp,
+            profiling_capture_rotate_mb: 32,
+            profiling_capture_max_files: 8,
+            profiling_capture_max_seconds: 600,
+            profiling_backlog_frames: 600,
+            log_level: LogLevel::Info,
+            log_output: LogOutput::Skyline,
+            hot_reload: false,
+            emulator_check: false,
+            override_config: false,
+        }
+    }
+}
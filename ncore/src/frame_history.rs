@@ -0,0 +1,20 @@
+/*! Frame history records as `nn::vi::ListFrameHistory` writes them */
+
+#[repr(u64)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, PartialOrd, Ord)]
+pub enum FrameStatus {
+    #[default]
+    Unknown,
+    Enqueued,
+    Presented,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FrameInfo {
+    pub status: FrameStatus,
+    pub frame_number: u64,
+    pub enqueue_time: i64,
+    pub present_time: i64,
+    pub vblank_time: i64,
+}
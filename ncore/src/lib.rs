@@ -0,0 +1,18 @@
+/*! The parts of ssbusync that don't touch the game
+ *
+ * Config types, the signature scanner, patch verification and rollback, the instruction encoders, the frame index
+ * math, the buffer policy, the watchdog's state machine, the override resolver, config validation and profile
+ * migrations. None of it needs skyline, so it builds and its tests run on the host with `cargo test -p ncore`; the
+ * plugin re-exports it from the modules that apply it.
+ */
+pub mod buffer;
+pub mod config;
+pub mod frame_history;
+pub mod migrate;
+pub mod overrides;
+pub mod pacer;
+pub mod patches;
+pub mod policy;
+pub mod sigscan;
+pub mod validation;
+pub mod watchdog;
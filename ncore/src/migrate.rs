@@ -0,0 +1,320 @@
+/** Profile versions and field migrations for `ssbusync.toml`
+ *
+ * Profiles store `profile_version` as a semver string (`"1.2.0"`). When a plugin asks for a newer version than the
+ * one on disk, the profile's TOML table runs through every `Migration` between the two, oldest first, and then gets
+ * whatever fields it still lacks from the plugin's defaults. Values already in the table are kept unless a migration
+ * explicitly renames or retypes them.
+ *
+ * Files written before this stored the version as a float. A float `x.y` reads as `x.(y * 1000).0`, so `1.1` is
+ * `1.100.0` and `0.15` is `0.150.0`; that keeps `0.15 < 0.2` true the way the old float comparison had it. Floats with
+ * more than three significant decimals (`0.1234`) can't be mapped that way and are rejected rather than truncated.
+ *
+ * A plugin moving from floats to semver has to keep counting from the mapped version: after `1.2` (`1.200.0`) comes
+ * `1.201.0` or `2.0.0`, not `1.3.0`. `below_legacy` catches the ones that don't, so they can be told why nothing
+ * upgrades.
+ */
+use semver::Version;
+use toml::{Table, Value};
+
+/// A profile version as a plugin passes it: a semver string, a `semver::Version`, or the old float.
+pub trait ProfileVersion {
+    /// `None` if it isn't a valid version.
+    fn profile_version(&self) -> Option<Version>;
+}
+
+impl ProfileVersion for Version {
+    fn profile_version(&self) -> Option<Version> {
+        Some(self.clone())
+    }
+}
+
+impl ProfileVersion for &str {
+    fn profile_version(&self) -> Option<Version> {
+        Version::parse(self.trim()).ok()
+    }
+}
+
+impl ProfileVersion for String {
+    fn profile_version(&self) -> Option<Version> {
+        self.as_str().profile_version()
+    }
+}
+
+impl ProfileVersion for f32 {
+    fn profile_version(&self) -> Option<Version> {
+        // From the f32's own decimal text; widening to f64 first would turn 1.1 into 1.100000023841858.
+        from_decimal(&self.to_string())
+    }
+}
+
+impl ProfileVersion for f64 {
+    fn profile_version(&self) -> Option<Version> {
+        from_decimal(&self.to_string())
+    }
+}
+
+/// `x.y` as `x.(y * 1000).0`. Negative, infinite and NaN floats don't parse, and neither do ones with a fourth
+/// significant decimal.
+fn from_decimal(text: &str) -> Option<Version> {
+    let (major, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if !fraction.bytes().all(|digit| digit.is_ascii_digit()) {
+        return None;
+    }
+    let (kept, rest) = fraction.split_at(fraction.len().min(3));
+    if rest.bytes().any(|digit| digit != b'0') {
+        return None;
+    }
+    let thousandths: String = kept.chars().chain("000".chars()).take(3).collect();
+    Some(Version::new(major.parse().ok()?, thousandths.parse().ok()?, 0))
+}
+
+/// Reads a stored `profile_version`, either format.
+pub fn stored_version(value: &Value) -> Option<Version> {
+    match value {
+        Value::String(version) => version.as_str().profile_version(),
+        Value::Float(version) => version.profile_version(),
+        Value::Integer(version) => u64::try_from(*version).ok().map(|major| Version::new(major, 0, 0)),
+        _ => None,
+    }
+}
+
+/// The stored version when `stored` is an old float (or integer) version newer than `requested`, i.e. a plugin moved to
+/// semver and restarted its numbering below what its float version maps to.
+pub fn below_legacy(stored: &Value, requested: &Version) -> Option<Version> {
+    if matches!(stored, Value::String(_)) {
+        return None;
+    }
+    stored_version(stored).filter(|stored| stored > requested)
+}
+
+/// One field-level change made by a `Migration`.
+#[derive(Debug, Clone)]
+pub enum FieldChange {
+    /// Adds `field` with `value` unless the profile already has it.
+    Add { field: String, value: Value },
+    /// Moves the user's value from `from` to `to`. If `to` is already set, the old field is just dropped.
+    Rename { from: String, to: String },
+    /// Rewrites `field` through `convert`. Returning `None` drops the field so the default applies.
+    Retype {
+        field: String,
+        convert: fn(&Value) -> Option<Value>,
+    },
+}
+
+/** The changes that bring a profile up to `to`
+ *
+ * ```ignore
+ * let migrations = [
+ *     Migration::to("1.1.0").rename("doubles_fix", "smooth_ffa"),
+ *     Migration::to("1.2.0")
+ *         .add("watchdog", true)
+ *         .retype("pacer_bias_double", |value| value.as_bool().map(|bias| Value::Integer(bias as i64))),
+ * ];
+ * ```
+ */
+#[derive(Debug, Clone)]
+pub struct Migration {
+    pub to: Version,
+    pub changes: Vec<FieldChange>,
+}
+
+impl Migration {
+    /// Panics if `version` isn't semver; migrations are written by hand, so that's a bug in the plugin.
+    pub fn to(version: &str) -> Self {
+        Self {
+            to: Version::parse(version).expect("Migration::to needs a semver version"),
+            changes: Vec::new(),
+        }
+    }
+
+    pub fn add(mut self, field: &str, value: impl Into<Value>) -> Self {
+        self.changes.push(FieldChange::Add {
+            field: field.to_string(),
+            value: value.into(),
+        });
+        self
+    }
+
+    pub fn rename(mut self, from: &str, to: &str) -> Self {
+        self.changes.push(FieldChange::Rename {
+            from: from.to_string(),
+            to: to.to_string(),
+        });
+        self
+    }
+
+    pub fn retype(mut self, field: &str, convert: fn(&Value) -> Option<Value>) -> Self {
+        self.changes.push(FieldChange::Retype {
+            field: field.to_string(),
+            convert,
+        });
+        self
+    }
+
+    fn apply(&self, profile: &str, table: &mut Table) {
+        for change in &self.changes {
+            match change {
+                FieldChange::Add { field, value } => {
+                    if !table.contains_key(field) {
+                        table.insert(field.clone(), value.clone());
+                    }
+                }
+                FieldChange::Rename { from, to } => {
+                    if let Some(value) = table.remove(from) {
+                        if !table.contains_key(to) {
+                            table.insert(to.clone(), value);
+                        }
+                    }
+                }
+                FieldChange::Retype { field, convert } => {
+                    let Some(value) = table.get(field) else {
+                        continue;
+                    };
+                    match convert(value) {
+                        Some(value) => {
+                            table.insert(field.clone(), value);
+                        }
+                        None => {
+                            tracing::warn!(
+                                target: "config",
+                                "profile {}: could not convert {} = {} for {}; using the default",
+                                profile, field, value, self.to
+                            );
+                            table.remove(field);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Runs the migrations newer than `from` and no newer than `to`, in version order. Returns how many ran.
+pub fn migrate(profile: &str, table: &mut Table, from: &Version, to: &Version, migrations: &[Migration]) -> usize {
+    let mut pending: Vec<&Migration> = migrations
+        .iter()
+        .filter(|migration| &migration.to > from && &migration.to <= to)
+        .collect();
+    pending.sort_by(|a, b| a.to.cmp(&b.to));
+    for migration in &pending {
+        migration.apply(profile, table);
+    }
+    pending.len()
+}
+
+/// Adds every field of `defaults` that `table` doesn't have.
+pub fn fill_defaults(table: &mut Table, defaults: &Table) {
+    for (field, value) in defaults {
+        if !table.contains_key(field) {
+            table.insert(field.clone(), value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(text: &str) -> Version {
+        Version::parse(text).unwrap()
+    }
+
+    fn table(text: &str) -> Table {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn reads_float_versions_as_thousandths() {
+        assert_eq!(from_decimal("1"), Some(version("1.0.0")));
+        assert_eq!(from_decimal("1.1"), Some(version("1.100.0")));
+        assert_eq!(from_decimal("0.15"), Some(version("0.150.0")));
+        assert_eq!(from_decimal("0.125"), Some(version("0.125.0")));
+        assert_eq!(from_decimal("2.5000"), Some(version("2.500.0")));
+        assert_eq!(0.15f32.profile_version(), Some(version("0.150.0")));
+        assert_eq!(1.1f64.profile_version(), Some(version("1.100.0")));
+        assert!(from_decimal("0.15") < from_decimal("0.2"));
+    }
+
+    #[test]
+    fn rejects_floats_it_cant_map() {
+        assert_eq!(from_decimal("0.1234"), None);
+        assert_eq!(0.1234f64.profile_version(), None);
+        assert_eq!(from_decimal("-1.5"), None);
+        assert_eq!(from_decimal("1e-7"), None);
+        assert_eq!(f64::NAN.profile_version(), None);
+        assert_eq!(f64::INFINITY.profile_version(), None);
+    }
+
+    #[test]
+    fn reads_stored_versions_in_either_format() {
+        assert_eq!(stored_version(&Value::from("1.2.0")), Some(version("1.2.0")));
+        assert_eq!(stored_version(&Value::Float(0.15)), Some(version("0.150.0")));
+        assert_eq!(stored_version(&Value::Integer(2)), Some(version("2.0.0")));
+        assert_eq!(stored_version(&Value::Integer(-1)), None);
+        assert_eq!(stored_version(&Value::Boolean(true)), None);
+    }
+
+    #[test]
+    fn flags_semver_requests_below_a_float_version() {
+        assert_eq!(below_legacy(&Value::Float(1.2), &version("1.3.0")), Some(version("1.200.0")));
+        assert_eq!(below_legacy(&Value::Integer(2), &version("1.9.0")), Some(version("2.0.0")));
+        assert_eq!(below_legacy(&Value::Float(1.2), &version("1.201.0")), None);
+        assert_eq!(below_legacy(&Value::Float(1.2), &version("1.200.0")), None);
+        // Semver on both sides is an ordinary downgrade.
+        assert_eq!(below_legacy(&Value::from("1.200.0"), &version("1.3.0")), None);
+    }
+
+    fn migrations() -> Vec<Migration> {
+        // Out of order on purpose; the runner sorts them.
+        vec![
+            Migration::to("1.2.0")
+                .add("watchdog", true)
+                .retype("pacer_bias_double", |value| value.as_bool().map(|bias| Value::Integer(bias as i64))),
+            Migration::to("1.1.0").rename("doubles_fix", "smooth_ffa"),
+            Migration::to("2.0.0").add("latency_stats", true),
+        ]
+    }
+
+    #[test]
+    fn runs_the_migrations_between_two_versions_in_order() {
+        let mut profile = table("doubles_fix = true\npacer_bias_double = true\n");
+        let ran = migrate("Default", &mut profile, &version("1.0.0"), &version("1.2.0"), &migrations());
+        assert_eq!(ran, 2);
+        assert_eq!(
+            profile,
+            table("smooth_ffa = true\npacer_bias_double = 1\nwatchdog = true\n")
+        );
+    }
+
+    #[test]
+    fn skips_migrations_the_profile_already_has() {
+        let mut profile = table("smooth_ffa = false\nwatchdog = false\n");
+        let ran = migrate("Default", &mut profile, &version("1.2.0"), &version("1.2.0"), &migrations());
+        assert_eq!(ran, 0);
+        assert_eq!(profile, table("smooth_ffa = false\nwatchdog = false\n"));
+    }
+
+    #[test]
+    fn keeps_user_values_over_renames_and_adds() {
+        let mut profile = table("doubles_fix = true\nsmooth_ffa = false\nwatchdog = false\n");
+        migrate("Default", &mut profile, &version("1.0.0"), &version("2.0.0"), &migrations());
+        assert_eq!(
+            profile,
+            table("smooth_ffa = false\nwatchdog = false\nlatency_stats = true\n")
+        );
+    }
+
+    #[test]
+    fn drops_values_a_retype_cant_convert() {
+        let mut profile = table("pacer_bias_double = \"fast\"\n");
+        migrate("Default", &mut profile, &version("1.1.0"), &version("1.2.0"), &migrations());
+        assert!(!profile.contains_key("pacer_bias_double"));
+    }
+
+    #[test]
+    fn fills_missing_fields_from_defaults() {
+        let mut profile = table("watchdog = false\n");
+        fill_defaults(&mut profile, &table("watchdog = true\nlatency_stats = false\n"));
+        assert_eq!(profile, table("watchdog = false\nlatency_stats = false\n"));
+    }
+}
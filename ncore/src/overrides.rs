@@ -0,0 +1,323 @@
+/** Per-stage, per-mode and per-scene override tables
+ *
+ * Some stages, the results screen and final-kill sparks skip frames under settings that are fine everywhere else. A
+ * profile can carry override tables for them:
+ *
+ * ```toml
+ * [SsbuSync.HDR.overrides.scene.results]
+ * enable_triple_buffer = true
+ *
+ * [SsbuSync.HDR.overrides.mode.online]
+ * slow_pacer_bias = true
+ * pacer_bias_triple = 2
+ *
+ * [SsbuSync.HDR.overrides.stage.118]
+ * enable_triple_buffer = true
+ * ```
+ *
+ * `mode` tables only apply once a plugin calls `report_mode`: ssbusync detects the scene and stage itself, not the
+ * game mode.
+ *
+ * The matching tables are layered onto the base profile broadest first (scene, then mode, then stage, so a stage
+ * table beats a mode table for the same setting). Only settings that can change live can be overridden; a table that
+ * sets anything else is skipped with an error when the profile is loaded.
+ */
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+use crate::config::{LogLevel, SsbuSyncConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GameMode {
+    Training,
+    Online,
+    LocalBattle,
+}
+
+impl GameMode {
+    pub const ALL: [Self; 3] = [Self::Training, Self::Online, Self::LocalBattle];
+
+    /// The key used for this mode's table.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Training => "training",
+            Self::Online => "online",
+            Self::LocalBattle => "local_battle",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+}
+
+/// What the game is showing right now; `None` where it isn't known (or doesn't apply, like the stage in a menu).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Context {
+    pub scene: Option<String>,
+    pub mode: Option<GameMode>,
+    pub stage: Option<u32>,
+}
+
+impl Context {
+    /// `self` with the fields it doesn't know taken from `fallback`.
+    pub fn or(self, fallback: Context) -> Context {
+        Context {
+            scene: self.scene.or(fallback.scene),
+            mode: self.mode.or(fallback.mode),
+            stage: self.stage.or(fallback.stage),
+        }
+    }
+}
+
+/// Sets `field` when the override has a value for it.
+fn overlay<T>(field: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *field = value;
+    }
+}
+
+macro_rules! config_patch {
+    ($($field:ident: $ty:ty),+ $(,)?) => {
+        /// The settings an override table can change; unset ones keep the profile's value.
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+        #[serde(default, deny_unknown_fields)]
+        pub struct ConfigPatch {
+            $(pub $field: Option<$ty>,)+
+        }
+
+        impl ConfigPatch {
+            pub fn apply_to(&self, config: &mut SsbuSyncConfig) {
+                $(overlay(&mut config.$field, self.$field);)+
+            }
+        }
+    };
+}
+
+config_patch! {
+    enable_triple_buffer: bool,
+    online_only: bool,
+    slow_pacer_bias: bool,
+    pacer_bias_double: u16,
+    pacer_bias_triple: u16,
+    auto_buffer_policy: bool,
+    triple_fighter_threshold: u8,
+    double_fighter_threshold: u8,
+    heavy_fighter_weight: u8,
+    buffer_policy_hold_frames: u16,
+    log_level: LogLevel,
+}
+
+/// Override tables, each keyed by what it applies to.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub scene: BTreeMap<String, ConfigPatch>,
+    pub mode: BTreeMap<GameMode, ConfigPatch>,
+    pub stage: BTreeMap<u32, ConfigPatch>,
+}
+
+impl Overrides {
+    pub fn is_empty(&self) -> bool {
+        self.scene.is_empty() && self.mode.is_empty() && self.stage.is_empty()
+    }
+
+    /// The tables matching `context`, in the order they are layered.
+    pub fn layers(&self, context: &Context) -> Vec<&ConfigPatch> {
+        let scene = context.scene.as_ref().and_then(|scene| self.scene.get(scene));
+        let mode = context.mode.and_then(|mode| self.mode.get(&mode));
+        let stage = context.stage.and_then(|stage| self.stage.get(&stage));
+        [scene, mode, stage].into_iter().flatten().collect()
+    }
+
+    /// `base` with every matching table layered on.
+    pub fn resolve(&self, base: &SsbuSyncConfig, context: &Context) -> SsbuSyncConfig {
+        let mut config = *base;
+        for layer in self.layers(context) {
+            layer.apply_to(&mut config);
+        }
+        config
+    }
+}
+
+fn insert(overrides: &mut Overrides, kind: &str, key: &str, value: &Value) -> Result<(), String> {
+    let patch: ConfigPatch = value.clone().try_into().map_err(|err: toml::de::Error| err.to_string())?;
+    match kind {
+        "scene" => {
+            overrides.scene.insert(key.to_string(), patch);
+        }
+        "mode" => {
+            let mode = GameMode::from_name(key).ok_or("not a game mode (training, online or local_battle)")?;
+            overrides.mode.insert(mode, patch);
+        }
+        "stage" => {
+            let stage = key.parse().map_err(|_| "stage overrides are keyed by stage ID")?;
+            overrides.stage.insert(stage, patch);
+        }
+        _ => return Err("overrides are keyed by scene, mode or stage".to_string()),
+    }
+    Ok(())
+}
+
+/// Reads a profile's `overrides` table, logging and skipping the entries that can't be used.
+pub fn parse(profile: &str, table: &Table) -> Overrides {
+    let mut overrides = Overrides::default();
+    for (kind, entries) in table {
+        let Some(entries) = entries.as_table() else {
+            tracing::error!(target: "config", "profile {}: overrides.{} should be a table", profile, kind);
+            continue;
+        };
+        for (key, value) in entries {
+            if let Err(err) = insert(&mut overrides, kind, key, value) {
+                tracing::error!(target: "config", "profile {}: skipping overrides.{}.{}: {}", profile, kind, key, err);
+            }
+        }
+    }
+    overrides
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(scene: Option<&str>, mode: Option<GameMode>, stage: Option<u32>) -> Context {
+        Context {
+            scene: scene.map(str::to_string),
+            mode,
+            stage,
+        }
+    }
+
+    fn base() -> SsbuSyncConfig {
+        SsbuSyncConfig {
+            enable_triple_buffer: false,
+            pacer_bias_triple: 1,
+            buffer_policy_hold_frames: 30,
+            ..SsbuSyncConfig::default()
+        }
+    }
+
+    /// `(enable_triple_buffer, pacer_bias_triple, buffer_policy_hold_frames)`, the fields the tables below touch.
+    fn touched(config: &SsbuSyncConfig) -> (bool, u16, u16) {
+        (config.enable_triple_buffer, config.pacer_bias_triple, config.buffer_policy_hold_frames)
+    }
+
+    fn overrides() -> Overrides {
+        let mut overrides = Overrides::default();
+        overrides.scene.insert(
+            "results".to_string(),
+            ConfigPatch {
+                enable_triple_buffer: Some(true),
+                ..Default::default()
+            },
+        );
+        overrides.mode.insert(
+            GameMode::Online,
+            ConfigPatch {
+                enable_triple_buffer: Some(true),
+                pacer_bias_triple: Some(2),
+                ..Default::default()
+            },
+        );
+        overrides.stage.insert(
+            118,
+            ConfigPatch {
+                pacer_bias_triple: Some(0),
+                buffer_policy_hold_frames: Some(10),
+                ..Default::default()
+            },
+        );
+        overrides
+    }
+
+    #[test]
+    fn keeps_the_base_when_nothing_matches() {
+        let overrides = overrides();
+        assert_eq!(touched(&overrides.resolve(&base(), &Context::default())), (false, 1, 30));
+        // Tables for other keys don't leak in.
+        let other = context(Some("battle"), Some(GameMode::Training), Some(3));
+        assert_eq!(touched(&overrides.resolve(&base(), &other)), (false, 1, 30));
+    }
+
+    #[test]
+    fn only_sets_what_a_table_sets() {
+        let results = overrides().resolve(&base(), &context(Some("results"), None, None));
+        assert_eq!(touched(&results), (true, 1, 30));
+        assert_eq!(results.slow_pacer_bias, base().slow_pacer_bias);
+    }
+
+    #[test]
+    fn layers_broadest_first_so_the_stage_wins() {
+        let overrides = overrides();
+        let online_on_118 = context(Some("battle"), Some(GameMode::Online), Some(118));
+        assert_eq!(overrides.layers(&online_on_118).len(), 2);
+        // Triple buffering from the mode table, which the stage table doesn't set; the bias the stage table sets wins
+        // over the mode table's.
+        assert_eq!(touched(&overrides.resolve(&base(), &online_on_118)), (true, 0, 10));
+        // Online on any other stage only gets the mode table.
+        let online = context(None, Some(GameMode::Online), Some(1));
+        assert_eq!(overrides.resolve(&base(), &online).pacer_bias_triple, 2);
+    }
+
+    #[test]
+    fn resolves_from_the_base_every_time() {
+        let overrides = overrides();
+        let on_118 = overrides.resolve(&base(), &context(None, None, Some(118)));
+        // Leaving the stage goes back to the profile rather than keeping the stage's values.
+        let after = overrides.resolve(&base(), &context(Some("results"), None, None));
+        assert_eq!(on_118.buffer_policy_hold_frames, 10);
+        assert_eq!(touched(&after), (true, 1, 30));
+    }
+
+    #[test]
+    fn reported_context_wins_over_detected() {
+        let detected = context(Some("battle"), None, Some(118));
+        assert_eq!(Context::default().or(detected.clone()), detected);
+        let reported = context(Some("results"), Some(GameMode::Online), None);
+        assert_eq!(
+            reported.or(detected),
+            context(Some("results"), Some(GameMode::Online), Some(118))
+        );
+    }
+
+    #[test]
+    fn parses_tables_and_skips_bad_ones() {
+        let table: Table = toml::from_str(
+            r#"
+            [scene.results]
+            enable_triple_buffer = true
+
+            [mode.online]
+            pacer_bias_triple = 2
+
+            [mode.smashdown]
+            pacer_bias_triple = 2
+
+            [stage.118]
+            buffer_policy_hold_frames = 10
+
+            [stage.final_destination]
+            enable_triple_buffer = true
+
+            [stage.3]
+            hot_reload = false
+            "#,
+        )
+        .unwrap();
+        let overrides = parse("Default", &table);
+        assert_eq!(overrides.scene.keys().collect::<Vec<_>>(), ["results"]);
+        assert_eq!(overrides.mode.keys().copied().collect::<Vec<_>>(), [GameMode::Online]);
+        // Only live settings can be overridden, so stage 3's table is dropped.
+        assert_eq!(overrides.stage.keys().copied().collect::<Vec<_>>(), [118]);
+        assert_eq!(overrides.stage[&118].buffer_policy_hold_frames, Some(10));
+    }
+
+    #[test]
+    fn mode_names_round_trip() {
+        for mode in GameMode::ALL {
+            assert_eq!(GameMode::from_name(mode.name()), Some(mode));
+        }
+        assert_eq!(GameMode::from_name("smashdown"), None);
+    }
+}
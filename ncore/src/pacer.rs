@@ -0,0 +1,104 @@
+/*! Pacer bias values and the `movz` encoding written over the pacer bias load */
+use crate::buffer::BufferMode;
+use crate::config::SsbuSyncConfig;
+
+/// Largest bias (in frames) the pacer check is allowed to be seeded with.
+pub const MAX_PACER_BIAS: u16 = 3;
+
+/// Register the pacer bias instruction writes to (`x8`).
+const PACER_BIAS_REGISTER: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacerBiasError {
+    /// The requested bias is larger than [`MAX_PACER_BIAS`].
+    BiasOutOfRange(u16),
+    /// `movz` can only target x0-x30 (x31 is the zero register).
+    InvalidRegister(u8),
+    /// `movz` only supports shifts of 0, 16, 32 or 48.
+    InvalidShift(u8),
+    /// Install was refused for this game build, so there is no pacer bias site to patch.
+    NotInstalled,
+}
+
+impl std::fmt::Display for PacerBiasError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BiasOutOfRange(bias) => {
+                write!(f, "pacer bias {} is out of range (0..={})", bias, MAX_PACER_BIAS)
+            }
+            Self::InvalidRegister(rd) => write!(f, "movz cannot target register x{}", rd),
+            Self::InvalidShift(shift) => write!(f, "movz cannot shift by {}", shift),
+            Self::NotInstalled => write!(f, "no offsets for this game build"),
+        }
+    }
+}
+
+/** Encodes `movz xRd, #imm16, lsl #shift`
+ *
+ * Layout: `1 10 100101 hw imm16 Rd` where `hw` is the shift divided by 16.
+ */
+pub const fn encode_movz_x(rd: u8, imm16: u16, shift: u8) -> Result<u32, PacerBiasError> {
+    if rd > 30 {
+        return Err(PacerBiasError::InvalidRegister(rd));
+    }
+    let hw = match shift {
+        0 => 0u32,
+        16 => 1,
+        32 => 2,
+        48 => 3,
+        _ => return Err(PacerBiasError::InvalidShift(shift)),
+    };
+    Ok(0xD2800000u32 | (hw << 21) | ((imm16 as u32) << 5) | rd as u32)
+}
+
+/// Encodes the `mov x8, #bias` instruction written over the pacer bias load.
+pub const fn encode_pacer_bias(bias: u16) -> Result<u32, PacerBiasError> {
+    if bias > MAX_PACER_BIAS {
+        return Err(PacerBiasError::BiasOutOfRange(bias));
+    }
+    encode_movz_x(PACER_BIAS_REGISTER, bias, 0)
+}
+
+/// Returns the bias a config asks for in `mode`. Without `slow_pacer_bias` the pacer is unbiased.
+pub fn pacer_bias_for(config: &SsbuSyncConfig, mode: BufferMode) -> u16 {
+    if !config.slow_pacer_bias || config.emulator_check {
+        return 0;
+    }
+    match mode {
+        BufferMode::Double => config.pacer_bias_double,
+        BufferMode::Triple => config.pacer_bias_triple,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_every_allowed_bias_as_mov_x8() {
+        // `mov x8, #0` .. `mov x8, #3`, as assembled.
+        let expected = [0xD2800008u32, 0xD2800028, 0xD2800048, 0xD2800068];
+        assert_eq!(expected.len(), MAX_PACER_BIAS as usize + 1);
+        for bias in 0..=MAX_PACER_BIAS {
+            assert_eq!(encode_pacer_bias(bias), Ok(expected[bias as usize]), "bias {}", bias);
+        }
+    }
+
+    #[test]
+    fn rejects_biases_past_the_maximum() {
+        assert_eq!(
+            encode_pacer_bias(MAX_PACER_BIAS + 1),
+            Err(PacerBiasError::BiasOutOfRange(MAX_PACER_BIAS + 1))
+        );
+        assert_eq!(encode_pacer_bias(u16::MAX), Err(PacerBiasError::BiasOutOfRange(u16::MAX)));
+    }
+
+    #[test]
+    fn encodes_movz_shifts_and_registers() {
+        assert_eq!(encode_movz_x(0, 0xFFFF, 0), Ok(0xD29FFFE0));
+        assert_eq!(encode_movz_x(1, 1, 16), Ok(0xD2A00021));
+        assert_eq!(encode_movz_x(30, 2, 48), Ok(0xD2E0005E));
+        assert_eq!(encode_movz_x(31, 0, 0), Err(PacerBiasError::InvalidRegister(31)));
+        assert_eq!(encode_movz_x(8, 0, 8), Err(PacerBiasError::InvalidShift(8)));
+    }
+}
@@ -0,0 +1,613 @@
+/** Verified patching
+ *
+ * Every patch names what its site holds in an unmodified game. `PatchRegistry` checks that before writing, so a site
+ * another plugin already changed is reported instead of overwritten, and keeps the bytes it replaced so each patch can
+ * be reverted. The reads and writes go through `Memory`, which the plugin implements over the game's `.text`.
+ */
+/// `nop`
+pub const NOP: u32 = 0xD503201F;
+/// `ret`
+pub const RET: u32 = 0xD65F03C0;
+
+/// Bytes written to (or read back from) a patch site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchData {
+    /// A single instruction.
+    U32(u32),
+    /// A pointer, e.g. a vtable entry.
+    U64(u64),
+}
+
+impl std::fmt::Display for PatchData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::U32(value) => write!(f, "0x{:08x}", value),
+            Self::U64(value) => write!(f, "0x{:016x}", value),
+        }
+    }
+}
+
+/// Where patches are read from and written to: the game's `.text` in the plugin, a byte buffer in tests.
+pub trait Memory {
+    /// Reads the same width as `like` from `.text + offset`.
+    fn read(&self, offset: usize, like: PatchData) -> PatchData;
+    /// Writes `data` to `.text + offset`, returning whether it went through.
+    fn write(&mut self, offset: usize, data: PatchData) -> bool;
+    /// Whether `pointer` points into `.text`.
+    fn in_text(&self, pointer: u64) -> bool;
+}
+
+impl<M: Memory + ?Sized> Memory for &mut M {
+    fn read(&self, offset: usize, like: PatchData) -> PatchData {
+        (**self).read(offset, like)
+    }
+
+    fn write(&mut self, offset: usize, data: PatchData) -> bool {
+        (**self).write(offset, data)
+    }
+
+    fn in_text(&self, pointer: u64) -> bool {
+        (**self).in_text(pointer)
+    }
+}
+
+/// What a patch site holds in an unmodified game, as recorded per build in the offset table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Original {
+    /// Exactly this instruction or pointer.
+    Exact(PatchData),
+    /// A `bl` to `.text + target`.
+    CallTo(usize),
+    /// A `bl` or `blr`, for calls made through a function pointer.
+    AnyCall,
+    /// A pointer into the game's `.text`, e.g. a vtable entry. Another plugin's replacement points into its own module.
+    TextPointer,
+    /// The exact bytes aren't recorded for this build. Accepts anything except what another plugin's patch leaves
+    /// behind: a `nop`, a `ret`, a `b` or a hook trampoline.
+    Unpatched,
+}
+
+impl Original {
+    /// Whether `found`, read from `.text + offset` of `memory`, is what this site should hold.
+    pub fn matches<M: Memory + ?Sized>(self, memory: &M, offset: usize, found: PatchData) -> bool {
+        match (self, found) {
+            (Self::Exact(expected), found) => expected == found,
+            (Self::CallTo(target), PatchData::U32(word)) => word == encode_bl(offset, target),
+            (Self::AnyCall, PatchData::U32(word)) => {
+                word & 0xFC000000 == 0x94000000 || word & 0xFFFFFC1F == 0xD63F0000
+            }
+            (Self::TextPointer, PatchData::U64(pointer)) => memory.in_text(pointer),
+            (Self::Unpatched, PatchData::U32(word)) => {
+                // `b <imm26>`, and `ldr x16/x17, #8` which starts a hook trampoline.
+                let branch = word & 0xFC000000 == 0x14000000;
+                let trampoline = word == 0x58000050 || word == 0x58000051;
+                word != NOP && word != RET && !branch && !trampoline
+            }
+            (Self::Unpatched, PatchData::U64(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Original {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exact(data) => write!(f, "{}", data),
+            Self::CallTo(target) => write!(f, "bl 0x{:x}", target),
+            Self::AnyCall => write!(f, "a bl or blr"),
+            Self::TextPointer => write!(f, "a pointer into .text"),
+            Self::Unpatched => write!(f, "unpatched code"),
+        }
+    }
+}
+
+/// Encodes `bl` from `.text + from` to `.text + to`.
+pub const fn encode_bl(from: usize, to: usize) -> u32 {
+    let words = (to as i64 - from as i64) / 4;
+    0x94000000 | (words as u32 & 0x03FFFFFF)
+}
+
+/// Describes one patch: where it goes, what should be there, and what to write.
+#[derive(Debug, Clone, Copy)]
+pub struct PatchSpec {
+    pub name: &'static str,
+    pub offset: usize,
+    /// What the site holds in an unmodified game. The plugin fills this in from the offset table when it's `None`.
+    pub expected: Option<Original>,
+    pub replacement: PatchData,
+}
+
+impl PatchSpec {
+    pub const fn nop(name: &'static str, offset: usize) -> Self {
+        Self::data(name, offset, NOP)
+    }
+
+    pub const fn data(name: &'static str, offset: usize, instruction: u32) -> Self {
+        Self {
+            name,
+            offset,
+            expected: None,
+            replacement: PatchData::U32(instruction),
+        }
+    }
+
+    pub const fn pointer(name: &'static str, offset: usize, value: u64) -> Self {
+        Self {
+            name,
+            offset,
+            expected: None,
+            replacement: PatchData::U64(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    /// The site holds something other than the expected original bytes, usually another plugin's patch.
+    Conflict {
+        name: &'static str,
+        offset: usize,
+        expected: Original,
+        found: PatchData,
+    },
+    /// The offset table doesn't record what this site should hold, so it can't be checked.
+    NoOriginal(&'static str),
+    /// A different patch is already registered at this offset.
+    Overlap {
+        name: &'static str,
+        existing: &'static str,
+        offset: usize,
+    },
+    /// No patch with this name is applied.
+    NotApplied(&'static str),
+    /// The write didn't go through.
+    WriteFailed { name: &'static str, offset: usize },
+}
+
+impl std::fmt::Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Conflict {
+                name,
+                offset,
+                expected,
+                found,
+            } => write!(
+                f,
+                "patch '{}' at 0x{:x} expected {} but found {} (patched by another plugin?)",
+                name, offset, expected, found
+            ),
+            Self::NoOriginal(name) => {
+                write!(f, "patch '{}' has no original bytes in the offset table", name)
+            }
+            Self::Overlap {
+                name,
+                existing,
+                offset,
+            } => write!(
+                f,
+                "patch '{}' overlaps '{}' at 0x{:x}",
+                name, existing, offset
+            ),
+            Self::NotApplied(name) => write!(f, "patch '{}' is not applied", name),
+            Self::WriteFailed { name, offset } => {
+                write!(f, "failed writing patch '{}' at 0x{:x}", name, offset)
+            }
+        }
+    }
+}
+
+/// A patch that has been written, along with the bytes it replaced.
+#[derive(Debug, Clone, Copy)]
+pub struct AppliedPatch {
+    pub spec: PatchSpec,
+    pub original: PatchData,
+}
+
+/// Every patch ssbusync has written, in the order they were applied.
+pub struct PatchRegistry {
+    applied: Vec<AppliedPatch>,
+}
+
+impl Default for PatchRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PatchRegistry {
+    pub const fn new() -> Self {
+        Self { applied: Vec::new() }
+    }
+
+    /// Verifies the site and writes `spec`.
+    ///
+    /// Re-applying a patch with the same name replaces its data but keeps the original bytes recorded the first time.
+    /// A site that already holds the replacement (another plugin made the same patch) is recorded without writing, with
+    /// those bytes as its original, so reverting it leaves the site as the other plugin wants it.
+    pub fn apply<M: Memory + ?Sized>(&mut self, memory: &mut M, spec: PatchSpec) -> Result<(), PatchError> {
+        if let Some(existing) = self.applied.iter_mut().find(|p| p.spec.offset == spec.offset) {
+            if existing.spec.name != spec.name {
+                return Err(PatchError::Overlap {
+                    name: spec.name,
+                    existing: existing.spec.name,
+                    offset: spec.offset,
+                });
+            }
+            if !memory.write(spec.offset, spec.replacement) {
+                return Err(PatchError::WriteFailed {
+                    name: spec.name,
+                    offset: spec.offset,
+                });
+            }
+            existing.spec.replacement = spec.replacement;
+            return Ok(());
+        }
+
+        let expected = spec.expected.ok_or(PatchError::NoOriginal(spec.name))?;
+        let found = memory.read(spec.offset, spec.replacement);
+        if found == spec.replacement {
+            tracing::info!(
+                target: "patches",
+                "'{}' at 0x{:x} already holds {}; recording it without writing",
+                spec.name, spec.offset, found
+            );
+            self.applied.push(AppliedPatch {
+                spec,
+                original: found,
+            });
+            return Ok(());
+        }
+        if !expected.matches(memory, spec.offset, found) {
+            return Err(PatchError::Conflict {
+                name: spec.name,
+                offset: spec.offset,
+                expected,
+                found,
+            });
+        }
+
+        if !memory.write(spec.offset, spec.replacement) {
+            return Err(PatchError::WriteFailed {
+                name: spec.name,
+                offset: spec.offset,
+            });
+        }
+        self.applied.push(AppliedPatch {
+            spec,
+            original: found,
+        });
+        Ok(())
+    }
+
+    /// Writes back the original bytes of the patch called `name`.
+    pub fn revert<M: Memory + ?Sized>(&mut self, memory: &mut M, name: &'static str) -> Result<(), PatchError> {
+        let Some(index) = self.applied.iter().position(|p| p.spec.name == name) else {
+            return Err(PatchError::NotApplied(name));
+        };
+        let patch = self.applied[index];
+        if !memory.write(patch.spec.offset, patch.original) {
+            return Err(PatchError::WriteFailed {
+                name,
+                offset: patch.spec.offset,
+            });
+        }
+        self.applied.remove(index);
+        Ok(())
+    }
+
+    /// Reverts every patch, newest first. Returns the patches that could not be reverted.
+    pub fn revert_all<M: Memory + ?Sized>(&mut self, memory: &mut M) -> Vec<PatchError> {
+        let mut errors = Vec::new();
+        while let Some(patch) = self.applied.last().copied() {
+            if let Err(err) = self.revert(memory, patch.spec.name) {
+                errors.push(err);
+                // Drop it so we don't retry forever; its bytes are left as they are.
+                self.applied.pop();
+            }
+        }
+        errors
+    }
+
+    pub fn is_applied(&self, name: &str) -> bool {
+        self.applied.iter().any(|p| p.spec.name == name)
+    }
+
+    pub fn applied(&self) -> &[AppliedPatch] {
+        &self.applied
+    }
+}
+
+/** Patches that go in together or not at all
+ *
+ * Install writes every patch through one of these before any hook goes in. If one fails, dropping the transaction
+ * without `commit` reverts the ones it applied, newest first, so the game is left as it was instead of half patched.
+ */
+pub struct Transaction<'a, M: Memory> {
+    registry: &'a mut PatchRegistry,
+    memory: M,
+    applied: Vec<&'static str>,
+    committed: bool,
+}
+
+impl<'a, M: Memory> Transaction<'a, M> {
+    pub fn begin(registry: &'a mut PatchRegistry, memory: M) -> Self {
+        Self {
+            registry,
+            memory,
+            applied: Vec::new(),
+            committed: false,
+        }
+    }
+
+    pub fn apply(&mut self, spec: PatchSpec) -> Result<(), PatchError> {
+        let fresh = !self.registry.is_applied(spec.name);
+        self.registry.apply(&mut self.memory, spec)?;
+        if fresh {
+            self.applied.push(spec.name);
+        }
+        Ok(())
+    }
+
+    /// Keeps every patch applied.
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl<M: Memory> Drop for Transaction<'_, M> {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        for name in self.applied.drain(..).rev() {
+            match self.registry.revert(&mut self.memory, name) {
+                Ok(()) => tracing::info!(target: "patches", "rolled back '{}'", name),
+                Err(err) => tracing::error!(target: "patches", "failed rolling back: {}", err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `.text` as a byte buffer starting at address `BASE`.
+    struct FakeText {
+        bytes: Vec<u8>,
+        writes: usize,
+        /// Fails every write from this one on.
+        fail_after: Option<usize>,
+    }
+
+    const BASE: u64 = 0x8000_0000;
+
+    impl FakeText {
+        fn with_words(words: &[u32]) -> Self {
+            Self {
+                bytes: words.iter().flat_map(|word| word.to_le_bytes()).collect(),
+                writes: 0,
+                fail_after: None,
+            }
+        }
+
+        fn word(&self, offset: usize) -> u32 {
+            u32::from_le_bytes(self.bytes[offset..offset + 4].try_into().unwrap())
+        }
+    }
+
+    impl Memory for FakeText {
+        fn read(&self, offset: usize, like: PatchData) -> PatchData {
+            match like {
+                PatchData::U32(_) => PatchData::U32(self.word(offset)),
+                PatchData::U64(_) => {
+                    PatchData::U64(u64::from_le_bytes(self.bytes[offset..offset + 8].try_into().unwrap()))
+                }
+            }
+        }
+
+        fn write(&mut self, offset: usize, data: PatchData) -> bool {
+            if self.fail_after.is_some_and(|limit| self.writes >= limit) {
+                return false;
+            }
+            self.writes += 1;
+            match data {
+                PatchData::U32(value) => self.bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes()),
+                PatchData::U64(value) => self.bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes()),
+            }
+            true
+        }
+
+        fn in_text(&self, pointer: u64) -> bool {
+            (BASE..BASE + self.bytes.len() as u64).contains(&pointer)
+        }
+    }
+
+    const ADD: u32 = 0x11000929;
+    const BL: u32 = 0x94000010;
+
+    fn nop(name: &'static str, offset: usize, expected: Original) -> PatchSpec {
+        PatchSpec {
+            expected: Some(expected),
+            ..PatchSpec::nop(name, offset)
+        }
+    }
+
+    #[test]
+    fn apply_writes_and_records_the_original() {
+        let mut text = FakeText::with_words(&[ADD, BL]);
+        let mut registry = PatchRegistry::new();
+        registry
+            .apply(&mut text, nop("add", 0, Original::Exact(PatchData::U32(ADD))))
+            .unwrap();
+        assert_eq!(text.word(0), NOP);
+        assert_eq!(registry.applied()[0].original, PatchData::U32(ADD));
+    }
+
+    #[test]
+    fn apply_refuses_a_site_that_was_changed() {
+        let mut text = FakeText::with_words(&[RET]);
+        let mut registry = PatchRegistry::new();
+        let err = registry
+            .apply(&mut text, nop("add", 0, Original::Exact(PatchData::U32(ADD))))
+            .unwrap_err();
+        assert!(matches!(err, PatchError::Conflict { found: PatchData::U32(RET), .. }));
+        assert_eq!(text.word(0), RET);
+        assert_eq!(text.writes, 0);
+        assert!(!registry.is_applied("add"));
+    }
+
+    #[test]
+    fn apply_needs_an_original() {
+        let mut text = FakeText::with_words(&[ADD]);
+        let mut registry = PatchRegistry::new();
+        assert_eq!(
+            registry.apply(&mut text, PatchSpec::nop("add", 0)),
+            Err(PatchError::NoOriginal("add"))
+        );
+    }
+
+    #[test]
+    fn a_site_already_holding_the_replacement_is_a_no_op() {
+        let mut text = FakeText::with_words(&[NOP]);
+        let mut registry = PatchRegistry::new();
+        registry
+            .apply(&mut text, nop("add", 0, Original::Exact(PatchData::U32(ADD))))
+            .unwrap();
+        assert!(registry.is_applied("add"));
+        assert_eq!(text.writes, 0);
+
+        registry.revert(&mut text, "add").unwrap();
+        assert_eq!(text.word(0), NOP);
+    }
+
+    #[test]
+    fn unpatched_rejects_other_plugins_patches() {
+        let text = FakeText::with_words(&[]);
+        for word in [NOP, RET, 0x14000004, 0x58000050, 0x58000051] {
+            assert!(!Original::Unpatched.matches(&text, 0, PatchData::U32(word)), "0x{:08x}", word);
+        }
+        assert!(Original::Unpatched.matches(&text, 0, PatchData::U32(ADD)));
+    }
+
+    #[test]
+    fn calls_and_pointers_are_checked() {
+        let text = FakeText::with_words(&[0; 4]);
+        assert!(Original::CallTo(0x40).matches(&text, 0, PatchData::U32(BL)));
+        assert!(!Original::CallTo(0x44).matches(&text, 0, PatchData::U32(BL)));
+        assert!(Original::AnyCall.matches(&text, 0, PatchData::U32(0xD63F0100)));
+        assert!(!Original::AnyCall.matches(&text, 0, PatchData::U32(ADD)));
+        assert!(Original::TextPointer.matches(&text, 0, PatchData::U64(BASE + 8)));
+        assert!(!Original::TextPointer.matches(&text, 0, PatchData::U64(BASE + 16)));
+    }
+
+    #[test]
+    fn reapplying_keeps_the_first_original() {
+        let mut text = FakeText::with_words(&[ADD]);
+        let mut registry = PatchRegistry::new();
+        let expected = Some(Original::Exact(PatchData::U32(ADD)));
+        registry
+            .apply(&mut text, PatchSpec { expected, ..PatchSpec::data("bias", 0, 0xD2800020) })
+            .unwrap();
+        registry
+            .apply(&mut text, PatchSpec { expected, ..PatchSpec::data("bias", 0, 0xD2800040) })
+            .unwrap();
+        assert_eq!(text.word(0), 0xD2800040);
+
+        registry.revert(&mut text, "bias").unwrap();
+        assert_eq!(text.word(0), ADD);
+        assert_eq!(registry.revert(&mut text, "bias"), Err(PatchError::NotApplied("bias")));
+    }
+
+    #[test]
+    fn a_different_patch_at_the_same_offset_overlaps() {
+        let mut text = FakeText::with_words(&[ADD]);
+        let mut registry = PatchRegistry::new();
+        registry
+            .apply(&mut text, nop("first", 0, Original::Exact(PatchData::U32(ADD))))
+            .unwrap();
+        assert_eq!(
+            registry.apply(&mut text, nop("second", 0, Original::Unpatched)),
+            Err(PatchError::Overlap { name: "second", existing: "first", offset: 0 })
+        );
+    }
+
+    #[test]
+    fn revert_all_goes_newest_first() {
+        let mut text = FakeText::with_words(&[ADD, BL]);
+        let mut registry = PatchRegistry::new();
+        registry
+            .apply(&mut text, nop("add", 0, Original::Exact(PatchData::U32(ADD))))
+            .unwrap();
+        registry.apply(&mut text, nop("call", 4, Original::AnyCall)).unwrap();
+        assert!(registry.revert_all(&mut text).is_empty());
+        assert_eq!((text.word(0), text.word(4)), (ADD, BL));
+        assert!(registry.applied().is_empty());
+    }
+
+    #[test]
+    fn revert_all_reports_and_drops_what_it_cannot_write() {
+        let mut text = FakeText::with_words(&[ADD]);
+        let mut registry = PatchRegistry::new();
+        registry
+            .apply(&mut text, nop("add", 0, Original::Exact(PatchData::U32(ADD))))
+            .unwrap();
+        text.fail_after = Some(text.writes);
+        assert_eq!(
+            registry.revert_all(&mut text),
+            vec![PatchError::WriteFailed { name: "add", offset: 0 }]
+        );
+        assert!(registry.applied().is_empty());
+        assert_eq!(text.word(0), NOP);
+    }
+
+    #[test]
+    fn a_dropped_transaction_rolls_back() {
+        let mut registry = PatchRegistry::new();
+        let mut text = FakeText::with_words(&[ADD, BL, RET]);
+        {
+            let mut transaction = Transaction::begin(&mut registry, &mut text);
+            transaction
+                .apply(nop("add", 0, Original::Exact(PatchData::U32(ADD))))
+                .unwrap();
+            transaction.apply(nop("call", 4, Original::AnyCall)).unwrap();
+            // Another plugin already returns early here.
+            assert!(transaction.apply(nop("body", 8, Original::Unpatched)).is_err());
+        }
+        assert_eq!((text.word(0), text.word(4), text.word(8)), (ADD, BL, RET));
+        assert!(registry.applied().is_empty());
+    }
+
+    #[test]
+    fn a_committed_transaction_keeps_its_patches() {
+        let mut registry = PatchRegistry::new();
+        let mut text = FakeText::with_words(&[ADD, BL]);
+        let mut transaction = Transaction::begin(&mut registry, &mut text);
+        transaction
+            .apply(nop("add", 0, Original::Exact(PatchData::U32(ADD))))
+            .unwrap();
+        transaction.apply(nop("call", 4, Original::AnyCall)).unwrap();
+        transaction.commit();
+        assert_eq!((text.word(0), text.word(4)), (NOP, NOP));
+        assert_eq!(registry.applied().len(), 2);
+    }
+
+    #[test]
+    fn rollback_leaves_patches_applied_before_the_transaction() {
+        let mut registry = PatchRegistry::new();
+        let mut text = FakeText::with_words(&[ADD, BL]);
+        registry
+            .apply(&mut text, nop("add", 0, Original::Exact(PatchData::U32(ADD))))
+            .unwrap();
+        {
+            let mut transaction = Transaction::begin(&mut registry, &mut text);
+            transaction
+                .apply(nop("add", 0, Original::Exact(PatchData::U32(ADD))))
+                .unwrap();
+            transaction.apply(nop("call", 4, Original::AnyCall)).unwrap();
+        }
+        assert_eq!((text.word(0), text.word(4)), (NOP, BL));
+        assert!(registry.is_applied("add"));
+    }
+}
@@ -0,0 +1,216 @@
+/** Automatic double/triple buffer selection
+ *
+ * High fighter counts (doubles, or dittos of fighters that bring extra bodies like Ice Climbers, Pokemon Trainer and Aegis)
+ * are where double buffering starts to drop frames. `BufferPolicy` picks `BufferMode::Triple` for heavy matches and
+ * goes back to `BufferMode::Double` once the load drops again. It only deals in numbers; the plugin samples the match
+ * and starts the swap.
+ */
+use crate::buffer::BufferMode;
+use crate::config::SsbuSyncConfig;
+
+/// How many fighters are in the current match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MatchLoad {
+    /// Fighter entries, i.e. players.
+    pub fighters: u8,
+    /// Entries playing a fighter that spawns extra bodies (Ice Climbers, Pokemon Trainer, Aegis, ...).
+    pub heavy_fighters: u8,
+}
+
+/// `FIGHTER_KIND_*` values of fighters that bring extra bodies. Trainer's entry plays as whichever Pokemon is out and
+/// Aegis as Pyra or Mythra, so all of their kinds are listed.
+const HEAVY_FIGHTER_KINDS: [i32; 7] = [
+    0x24, // pzenigame
+    0x25, // pfushigisou
+    0x26, // plizardon
+    0x4b, // popo
+    0x4c, // nana
+    0x5a, // eflame
+    0x5b, // elight
+];
+
+pub fn is_heavy_fighter_kind(kind: i32) -> bool {
+    HEAVY_FIGHTER_KINDS.contains(&kind)
+}
+
+impl MatchLoad {
+    pub const fn new(fighters: u8, heavy_fighters: u8) -> Self {
+        Self {
+            fighters,
+            heavy_fighters,
+        }
+    }
+
+    /// Each heavy fighter adds `heavy_weight` on top of counting as a fighter.
+    pub const fn weighted(self, heavy_weight: u8) -> u16 {
+        self.fighters as u16 + self.heavy_fighters as u16 * heavy_weight as u16
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolicyThresholds {
+    /// Switch to triple buffering once the weighted load reaches this.
+    pub triple_at: u16,
+    /// Switch back to double buffering once the weighted load is at or below this.
+    pub double_at: u16,
+    /// Extra weight of a heavy fighter.
+    pub heavy_weight: u8,
+    /// How many consecutive updates a new target has to hold before switching.
+    pub hold_frames: u16,
+}
+
+impl PolicyThresholds {
+    pub fn from_config(config: &SsbuSyncConfig) -> Self {
+        let triple_at = config.triple_fighter_threshold.max(1) as u16;
+        Self {
+            triple_at,
+            // Keep a gap between the two so a 3 player match doesn't bounce between modes.
+            double_at: (config.double_fighter_threshold as u16).min(triple_at - 1),
+            heavy_weight: config.heavy_fighter_weight,
+            hold_frames: config.buffer_policy_hold_frames,
+        }
+    }
+}
+
+/// Hysteresis state machine that decides which buffer mode a match should run in.
+#[derive(Debug, Clone)]
+pub struct BufferPolicy {
+    thresholds: PolicyThresholds,
+    target: Option<BufferMode>,
+    held_frames: u16,
+}
+
+impl BufferPolicy {
+    pub const fn new(thresholds: PolicyThresholds) -> Self {
+        Self {
+            thresholds,
+            target: None,
+            held_frames: 0,
+        }
+    }
+
+    pub fn thresholds(&self) -> PolicyThresholds {
+        self.thresholds
+    }
+
+    /// Feeds one frame of `load` while the game is running in `active`.
+    ///
+    /// Returns the mode to switch to once a different target has held for `hold_frames` updates.
+    pub fn update(&mut self, load: MatchLoad, active: BufferMode) -> Option<BufferMode> {
+        let weighted = load.weighted(self.thresholds.heavy_weight);
+        let wanted = if weighted >= self.thresholds.triple_at {
+            BufferMode::Triple
+        } else if weighted <= self.thresholds.double_at {
+            BufferMode::Double
+        } else {
+            // Between the thresholds, whatever is active stays.
+            active
+        };
+
+        if wanted == active {
+            self.target = None;
+            self.held_frames = 0;
+            return None;
+        }
+
+        if self.target != Some(wanted) {
+            self.target = Some(wanted);
+            self.held_frames = 0;
+        }
+        self.held_frames = self.held_frames.saturating_add(1);
+
+        if self.held_frames >= self.thresholds.hold_frames {
+            Some(wanted)
+        } else {
+            None
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.target = None;
+        self.held_frames = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLDS: PolicyThresholds = PolicyThresholds {
+        triple_at: 4,
+        double_at: 2,
+        heavy_weight: 1,
+        hold_frames: 3,
+    };
+
+    /// Feeds `load` `frames` times, switching `active` whenever the policy asks, and returns every switch.
+    fn run(policy: &mut BufferPolicy, active: &mut BufferMode, load: MatchLoad, frames: u16) -> Vec<BufferMode> {
+        let mut switches = Vec::new();
+        for _ in 0..frames {
+            if let Some(mode) = policy.update(load, *active) {
+                *active = mode;
+                switches.push(mode);
+            }
+        }
+        switches
+    }
+
+    #[test]
+    fn heavy_fighters_add_their_weight() {
+        assert_eq!(MatchLoad::new(2, 0).weighted(1), 2);
+        assert_eq!(MatchLoad::new(2, 1).weighted(1), 3);
+        assert_eq!(MatchLoad::new(2, 2).weighted(3), 8);
+        assert!(is_heavy_fighter_kind(0x4b));
+        assert!(!is_heavy_fighter_kind(0x0));
+    }
+
+    #[test]
+    fn switches_once_the_target_held_for_hold_frames() {
+        let mut policy = BufferPolicy::new(THRESHOLDS);
+        let heavy = MatchLoad::new(4, 0);
+        assert_eq!(policy.update(heavy, BufferMode::Double), None);
+        assert_eq!(policy.update(heavy, BufferMode::Double), None);
+        assert_eq!(policy.update(heavy, BufferMode::Double), Some(BufferMode::Triple));
+    }
+
+    #[test]
+    fn a_blip_shorter_than_hold_frames_does_nothing() {
+        let mut policy = BufferPolicy::new(THRESHOLDS);
+        let mut active = BufferMode::Double;
+        assert!(run(&mut policy, &mut active, MatchLoad::new(4, 0), 2).is_empty());
+        // Dropping back below the threshold resets the count.
+        assert!(run(&mut policy, &mut active, MatchLoad::new(2, 0), 1).is_empty());
+        assert!(run(&mut policy, &mut active, MatchLoad::new(4, 0), 2).is_empty());
+        assert_eq!(active, BufferMode::Double);
+    }
+
+    #[test]
+    fn keeps_the_active_mode_between_the_thresholds() {
+        let mut policy = BufferPolicy::new(THRESHOLDS);
+        let mut active = BufferMode::Double;
+        assert_eq!(run(&mut policy, &mut active, MatchLoad::new(4, 0), 3), vec![BufferMode::Triple]);
+        // 3 is above double_at and below triple_at: no bouncing back.
+        assert!(run(&mut policy, &mut active, MatchLoad::new(3, 0), 10).is_empty());
+        assert_eq!(run(&mut policy, &mut active, MatchLoad::new(2, 0), 3), vec![BufferMode::Double]);
+        assert!(run(&mut policy, &mut active, MatchLoad::new(3, 0), 10).is_empty());
+        assert_eq!(active, BufferMode::Double);
+    }
+
+    #[test]
+    fn a_heavy_fighter_tips_a_three_player_match() {
+        let mut policy = BufferPolicy::new(THRESHOLDS);
+        let mut active = BufferMode::Double;
+        assert!(run(&mut policy, &mut active, MatchLoad::new(3, 0), 5).is_empty());
+        assert_eq!(run(&mut policy, &mut active, MatchLoad::new(3, 1), 3), vec![BufferMode::Triple]);
+    }
+
+    #[test]
+    fn reset_starts_the_hold_over() {
+        let mut policy = BufferPolicy::new(THRESHOLDS);
+        let heavy = MatchLoad::new(4, 0);
+        policy.update(heavy, BufferMode::Double);
+        policy.update(heavy, BufferMode::Double);
+        policy.reset();
+        assert_eq!(policy.update(heavy, BufferMode::Double), None);
+    }
+}
@@ -0,0 +1,322 @@
+/** Byte-signature scanner used to relocate patch sites
+ *
+ * A signature matches a block of code and names the sites inside it, so every site in one block moves together.
+ * Patterns are written as space separated tokens:
+ * - `3F`     an exact byte
+ * - `??`     any byte
+ * - `08/1F`  a byte compared under a mask (`byte & 0x1F == 0x08`), for register fields
+ * - `??{24}` a run of 24 wildcard bytes
+ *
+ * Everything here works on plain `&[u8]` so it does not depend on the game being loaded.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PatternByte {
+    value: u8,
+    mask: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    bytes: Vec<PatternByte>,
+}
+
+/// A block of code located by a pattern, and the patch sites inside it.
+pub struct Signature {
+    pub name: &'static str,
+    pub pattern: &'static str,
+    /// Each site's offset-table name and its distance from the start of the match.
+    pub sites: &'static [(&'static str, usize)],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanError {
+    /// A token in the pattern could not be parsed.
+    InvalidPattern { name: &'static str, token: String },
+    /// The pattern matched nowhere.
+    NotFound { name: &'static str },
+    /// The pattern matched more than once; holds every match offset.
+    Ambiguous { name: &'static str, matches: Vec<usize> },
+}
+
+impl std::fmt::Display for ScanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidPattern { name, token } => {
+                write!(f, "signature '{}' has invalid token '{}'", name, token)
+            }
+            Self::NotFound { name } => write!(f, "signature '{}' not found", name),
+            Self::Ambiguous { name, matches } => {
+                write!(f, "signature '{}' is ambiguous ({} matches", name, matches.len())?;
+                for offset in matches.iter().take(4) {
+                    write!(f, ", 0x{:x}", offset)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+fn parse_byte(token: &str) -> Option<u8> {
+    if token.len() != 2 {
+        return None;
+    }
+    u8::from_str_radix(token, 16).ok()
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        let mut bytes = Vec::new();
+        for token in pattern.split_whitespace() {
+            if let Some(count) = token
+                .strip_prefix("??{")
+                .and_then(|rest| rest.strip_suffix('}'))
+            {
+                let count: usize = count.parse().map_err(|_| token.to_string())?;
+                bytes.extend(std::iter::repeat_n(PatternByte { value: 0, mask: 0 }, count));
+            } else if token == "??" {
+                bytes.push(PatternByte { value: 0, mask: 0 });
+            } else if let Some((value, mask)) = token.split_once('/') {
+                let (Some(value), Some(mask)) = (parse_byte(value), parse_byte(mask)) else {
+                    return Err(token.to_string());
+                };
+                bytes.push(PatternByte { value: value & mask, mask });
+            } else {
+                let Some(value) = parse_byte(token) else {
+                    return Err(token.to_string());
+                };
+                bytes.push(PatternByte { value, mask: 0xFF });
+            }
+        }
+        if bytes.is_empty() {
+            return Err(String::from("<empty>"));
+        }
+        Ok(Self { bytes })
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn matches_at(&self, text: &[u8], at: usize) -> bool {
+        self.bytes
+            .iter()
+            .zip(&text[at..at + self.bytes.len()])
+            .all(|(pattern, byte)| byte & pattern.mask == pattern.value)
+    }
+
+    /// Returns the offset of every match starting on an `align` byte boundary.
+    pub fn find_all(&self, text: &[u8], align: usize) -> Vec<usize> {
+        let align = align.max(1);
+        if text.len() < self.bytes.len() {
+            return Vec::new();
+        }
+        (0..=text.len() - self.bytes.len())
+            .step_by(align)
+            .filter(|at| self.matches_at(text, *at))
+            .collect()
+    }
+}
+
+fn resolve(name: &'static str, mut matches: Vec<usize>) -> Result<usize, ScanError> {
+    match matches.len() {
+        0 => Err(ScanError::NotFound { name }),
+        1 => Ok(matches.remove(0)),
+        _ => Err(ScanError::Ambiguous { name, matches }),
+    }
+}
+
+/** Finds where each of `signatures` starts in `text`, in the same order
+ *
+ * Every pattern is parsed once and `text` is walked once, trying each pattern at every position. Instructions are
+ * 4-byte aligned so only aligned matches count.
+ */
+pub fn scan_all(text: &[u8], signatures: &[Signature]) -> Vec<Result<usize, ScanError>> {
+    let patterns: Vec<Result<Pattern, ScanError>> = signatures
+        .iter()
+        .map(|signature| {
+            Pattern::parse(signature.pattern).map_err(|token| ScanError::InvalidPattern {
+                name: signature.name,
+                token,
+            })
+        })
+        .collect();
+    let mut matches = vec![Vec::new(); signatures.len()];
+    for at in (0..text.len()).step_by(4) {
+        for (pattern, found) in patterns.iter().zip(&mut matches) {
+            if let Ok(pattern) = pattern {
+                if at + pattern.len() <= text.len() && pattern.matches_at(text, at) {
+                    found.push(at);
+                }
+            }
+        }
+    }
+    signatures
+        .iter()
+        .zip(patterns)
+        .zip(matches)
+        .map(|((signature, pattern), matches)| pattern.and_then(|_| resolve(signature.name, matches)))
+        .collect()
+}
+
+/// Finds where the single match of `signature` starts in `text`.
+pub fn scan(text: &[u8], signature: &Signature) -> Result<usize, ScanError> {
+    let pattern = Pattern::parse(signature.pattern).map_err(|token| ScanError::InvalidPattern {
+        name: signature.name,
+        token,
+    })?;
+    resolve(signature.name, pattern.find_all(text, 4))
+}
+
+/// Blocks whose sites can be relocated by signature.
+pub static SIGNATURES: &[Signature] = &[
+    // The `blr` calls of the present/acquire wrapper, ending in the render sync wait `blr x8`.
+    Signature {
+        name: "swapchain_wrapper",
+        pattern: "?? ?? 3F D6 ??{28} ?? ?? 3F D6 ??{56} ?? ?? 3F D6 ??{12} 00 01 3F D6",
+        sites: &[
+            ("present_texture_call", 0),
+            ("acquire_texture_call", 0x20),
+            ("queue_wait_sync_call", 0x5c),
+            ("render_sync_wait", 0x6c),
+        ],
+    },
+    // add w9, w9, #0x2 ; movz wN, #0xaaab (the start of `% 3`)
+    Signature {
+        name: "frame_index_add",
+        pattern: "29 09 00 11 ?? 55 95 52",
+        sites: &[("frame_index_add", 0)],
+    },
+    // bl <scene manager> ; ... ; ldr w8, [xN, #imm], the load of how many frames behind that the pacer checks.
+    Signature {
+        name: "frame_pacer_check",
+        pattern: "?? ?? ?? 94/FC ??{24} 08/1F ?? 40/C0 B9",
+        sites: &[("scene_manager_call_0", 0), ("frame_pacer_check", 0x1c)],
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signature(pattern: &'static str) -> Signature {
+        Signature {
+            name: "test",
+            pattern,
+            sites: &[],
+        }
+    }
+
+    /// `len` bytes of filler that none of the patterns below match, with `code` written at each offset.
+    fn text(len: usize, code: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut text = vec![0xEE; len];
+        for (at, bytes) in code {
+            text[*at..*at + bytes.len()].copy_from_slice(bytes);
+        }
+        text
+    }
+
+    #[test]
+    fn wildcards_match_any_byte() {
+        let text = text(64, &[(16, &[0x12, 0x34, 0x3F, 0xD6])]);
+        assert_eq!(scan(&text, &signature("?? ?? 3F D6")), Ok(16));
+    }
+
+    #[test]
+    fn masked_bytes_only_compare_the_masked_bits() {
+        // `bl` is 0b100101 in the top six bits, so the last byte is 0x94..=0x97.
+        for last in 0x94..=0x97u8 {
+            let text = text(32, &[(8, &[0x01, 0x02, 0x03, last])]);
+            assert_eq!(scan(&text, &signature("?? ?? ?? 94/FC")), Ok(8), "byte 0x{:x}", last);
+        }
+        let text = text(32, &[(8, &[0x01, 0x02, 0x03, 0x98])]);
+        assert_eq!(
+            scan(&text, &signature("?? ?? ?? 94/FC")),
+            Err(ScanError::NotFound { name: "test" })
+        );
+    }
+
+    #[test]
+    fn repeats_expand_to_that_many_wildcards() {
+        let pattern = Pattern::parse("AA ??{6} BB").unwrap();
+        assert_eq!(pattern.len(), 8);
+        let text = text(32, &[(4, &[0xAA, 1, 2, 3, 4, 5, 6, 0xBB])]);
+        assert_eq!(pattern.find_all(&text, 4), vec![4]);
+        // One byte too far apart doesn't match.
+        let text = self::text(32, &[(4, &[0xAA]), (12, &[0xBB])]);
+        assert!(pattern.find_all(&text, 4).is_empty());
+    }
+
+    #[test]
+    fn reports_every_match_when_ambiguous() {
+        let text = text(64, &[(8, &[0x00, 0x01, 0x3F, 0xD6]), (40, &[0x00, 0x01, 0x3F, 0xD6])]);
+        assert_eq!(
+            scan(&text, &signature("00 01 3F D6")),
+            Err(ScanError::Ambiguous {
+                name: "test",
+                matches: vec![8, 40],
+            })
+        );
+    }
+
+    #[test]
+    fn only_aligned_matches_count() {
+        let text = text(32, &[(6, &[0x00, 0x01, 0x3F, 0xD6])]);
+        assert_eq!(
+            scan(&text, &signature("00 01 3F D6")),
+            Err(ScanError::NotFound { name: "test" })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_tokens() {
+        for bad in ["GG", "ABC", "??{x}", "12/", ""] {
+            assert!(Pattern::parse(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn every_signature_parses() {
+        for signature in SIGNATURES {
+            assert!(Pattern::parse(signature.pattern).is_ok(), "{}", signature.name);
+        }
+    }
+
+    #[test]
+    fn pacer_check_is_anchored_to_the_scene_manager_call() {
+        let pacer = SIGNATURES.iter().find(|signature| signature.name == "frame_pacer_check").unwrap();
+        // bl #0x1000 ; 6 instructions ; ldr w8, [x19, #0x10]
+        let block = text(96, &[(16, &[0x00, 0x04, 0x00, 0x94]), (44, &[0x68, 0x12, 0x40, 0xB9])]);
+        assert_eq!(scan(&block, pacer), Ok(16));
+        // The same load into w9 isn't the pacer check.
+        let other = text(96, &[(16, &[0x00, 0x04, 0x00, 0x94]), (44, &[0x69, 0x12, 0x40, 0xB9])]);
+        assert_eq!(scan(&other, pacer), Err(ScanError::NotFound { name: "frame_pacer_check" }));
+    }
+
+    #[test]
+    fn scan_all_resolves_each_signature_in_one_pass() {
+        let signatures = [
+            signature("00 01 3F D6"),
+            signature("29 09 00 11"),
+            signature("77 77 77 77"),
+            signature("ZZ"),
+        ];
+        let text = text(64, &[(12, &[0x00, 0x01, 0x3F, 0xD6]), (60, &[0x29, 0x09, 0x00, 0x11])]);
+        assert_eq!(
+            scan_all(&text, &signatures),
+            vec![
+                Ok(12),
+                Ok(60),
+                Err(ScanError::NotFound { name: "test" }),
+                Err(ScanError::InvalidPattern {
+                    name: "test",
+                    token: "ZZ".to_string(),
+                }),
+            ]
+        );
+    }
+}
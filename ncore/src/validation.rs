@@ -0,0 +1,355 @@
+/** Config validation
+ *
+ * `validate` works out the config ssbusync will actually run with and says why it differs from the one it was given.
+ * Several settings only make sense together (`auto_buffer_policy` needs `allow_buffer_swap`, `online_only` needs
+ * triple buffering) and several are ignored on emulator; before this the installers quietly picked a winner. Each
+ * resolution here mirrors what the installer for that feature does, so the effective config is what ends up running.
+ *
+ * The plugin's `install_unchecked` logs every diagnostic under the `config` target and keeps the result for
+ * `control::validation`.
+ */
+use std::fmt;
+
+use crate::config::{ProfilingSink, SsbuSyncConfig};
+use crate::pacer::MAX_PACER_BIAS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Expected on this platform, e.g. the pacer being disabled on emulator.
+    Note,
+    /// A setting that contradicts another one or the platform and is ignored.
+    Warning,
+    /// A value that can't be used; a safe one is used instead.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The setting that was changed or ignored, as spelled in `ssbusync.toml`.
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Validation {
+    /// The config after every resolution below was applied.
+    pub effective: SsbuSyncConfig,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Validation {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    /// Diagnostics at `severity` or worse.
+    pub fn at_least(&self, severity: Severity) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(move |diagnostic| diagnostic.severity >= severity)
+    }
+
+    /// Writes each diagnostic to the log at the matching level.
+    pub fn log(&self) {
+        for diagnostic in &self.diagnostics {
+            match diagnostic.severity {
+                Severity::Note => tracing::info!(target: "config", "{}", diagnostic),
+                Severity::Warning => tracing::warn!(target: "config", "{}", diagnostic),
+                Severity::Error => tracing::error!(target: "config", "{}", diagnostic),
+            }
+        }
+    }
+}
+
+fn push(out: &mut Vec<Diagnostic>, severity: Severity, field: &'static str, message: impl Into<String>) {
+    out.push(Diagnostic {
+        severity,
+        field,
+        message: message.into(),
+    });
+}
+
+/// Resolves `config` for the platform in `config.emulator_check`.
+pub fn validate(config: &SsbuSyncConfig) -> Validation {
+    let mut effective = *config;
+    let mut diagnostics = Vec::new();
+    buffering(&mut effective, &mut diagnostics);
+    pacer(&mut effective, &mut diagnostics);
+    buffer_policy(&mut effective, &mut diagnostics);
+    windows(&mut effective, &mut diagnostics);
+    profiling(&mut effective, &mut diagnostics);
+    if config.smooth_ffa {
+        push(
+            &mut diagnostics,
+            Severity::Warning,
+            "smooth_ffa",
+            "does nothing in this build; use auto_buffer_policy for heavy matches",
+        );
+    }
+    Validation { effective, diagnostics }
+}
+
+fn buffering(config: &mut SsbuSyncConfig, out: &mut Vec<Diagnostic>) {
+    if config.emulator_check {
+        if config.allow_buffer_swap {
+            config.allow_buffer_swap = false;
+            push(
+                out,
+                Severity::Warning,
+                "allow_buffer_swap",
+                "ignored on emulator, which keeps its own frame index policy",
+            );
+        }
+    } else if config.online_only && !config.enable_triple_buffer {
+        config.enable_triple_buffer = true;
+        push(
+            out,
+            Severity::Warning,
+            "enable_triple_buffer",
+            "online_only forces triple buffering on console, so false has no effect",
+        );
+    }
+}
+
+fn pacer(config: &mut SsbuSyncConfig, out: &mut Vec<Diagnostic>) {
+    if config.emulator_check {
+        if !config.disable_pacer {
+            config.disable_pacer = true;
+            push(out, Severity::Note, "disable_pacer", "the frame pacer is always disabled on emulator");
+        }
+        if config.slow_pacer_bias {
+            config.slow_pacer_bias = false;
+            push(
+                out,
+                Severity::Warning,
+                "slow_pacer_bias",
+                "ignored on emulator because the pacer is disabled",
+            );
+        }
+        return;
+    }
+    if !config.slow_pacer_bias {
+        return;
+    }
+    for (field, bias) in [
+        ("pacer_bias_double", &mut config.pacer_bias_double),
+        ("pacer_bias_triple", &mut config.pacer_bias_triple),
+    ] {
+        if *bias > MAX_PACER_BIAS {
+            let requested = *bias;
+            *bias = 0;
+            push(
+                out,
+                Severity::Error,
+                field,
+                format!("{} is out of range (0..={}); using 0", requested, MAX_PACER_BIAS),
+            );
+        }
+    }
+}
+
+fn buffer_policy(config: &mut SsbuSyncConfig, out: &mut Vec<Diagnostic>) {
+    if !config.auto_buffer_policy {
+        return;
+    }
+    if config.emulator_check || !config.allow_buffer_swap {
+        config.auto_buffer_policy = false;
+        let reason = if config.emulator_check {
+            "buffer modes are fixed on emulator"
+        } else {
+            "it needs allow_buffer_swap = true"
+        };
+        push(out, Severity::Warning, "auto_buffer_policy", format!("left off: {}", reason));
+        return;
+    }
+    if config.triple_fighter_threshold == 0 {
+        config.triple_fighter_threshold = 1;
+        push(out, Severity::Error, "triple_fighter_threshold", "must be at least 1; using 1");
+    }
+    if config.double_fighter_threshold >= config.triple_fighter_threshold {
+        let requested = config.double_fighter_threshold;
+        config.double_fighter_threshold = config.triple_fighter_threshold - 1;
+        push(
+            out,
+            Severity::Warning,
+            "double_fighter_threshold",
+            format!(
+                "{} is not below triple_fighter_threshold ({}); using {} so the modes don't bounce",
+                requested, config.triple_fighter_threshold, config.double_fighter_threshold
+            ),
+        );
+    }
+}
+
+/// Window and threshold sizes that the features clamp to 1.
+fn windows(config: &mut SsbuSyncConfig, out: &mut Vec<Diagnostic>) {
+    if config.watchdog {
+        at_least_one("watchdog_window_frames", &mut config.watchdog_window_frames, out);
+        at_least_one("watchdog_skip_threshold", &mut config.watchdog_skip_threshold, out);
+    }
+    if config.latency_stats {
+        at_least_one("latency_window_frames", &mut config.latency_window_frames, out);
+    }
+}
+
+fn at_least_one(field: &'static str, value: &mut u16, out: &mut Vec<Diagnostic>) {
+    if *value == 0 {
+        *value = 1;
+        push(out, Severity::Error, field, "must be at least 1; using 1");
+    }
+}
+
+fn profiling(config: &mut SsbuSyncConfig, out: &mut Vec<Diagnostic>) {
+    if !config.profiling || !config.profiling_backend.nprof() {
+        return;
+    }
+    if matches!(config.profiling_sink, ProfilingSink::File | ProfilingSink::Both) {
+        at_least_one("profiling_capture_rotate_mb", &mut config.profiling_capture_rotate_mb, out);
+        at_least_one("profiling_capture_max_files", &mut config.profiling_capture_max_files, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn console() -> SsbuSyncConfig {
+        SsbuSyncConfig {
+            emulator_check: false,
+            ..SsbuSyncConfig::default()
+        }
+    }
+
+    fn emulator() -> SsbuSyncConfig {
+        SsbuSyncConfig {
+            emulator_check: true,
+            ..SsbuSyncConfig::default()
+        }
+    }
+
+    fn fields(validation: &Validation) -> Vec<&'static str> {
+        validation.diagnostics.iter().map(|diagnostic| diagnostic.field).collect()
+    }
+
+    #[test]
+    fn defaults_are_clean_on_console() {
+        let validation = validate(&console());
+        assert!(validation.diagnostics.is_empty(), "{:?}", validation.diagnostics);
+    }
+
+    #[test]
+    fn emulator_drops_buffer_swaps_and_the_slow_bias() {
+        let validation = validate(&SsbuSyncConfig {
+            allow_buffer_swap: true,
+            slow_pacer_bias: true,
+            disable_pacer: false,
+            ..emulator()
+        });
+        assert!(!validation.effective.allow_buffer_swap);
+        assert!(!validation.effective.slow_pacer_bias);
+        assert!(validation.effective.disable_pacer);
+        assert_eq!(fields(&validation), ["allow_buffer_swap", "disable_pacer", "slow_pacer_bias"]);
+        assert!(!validation.has_errors());
+    }
+
+    #[test]
+    fn online_only_forces_triple_buffering_on_console() {
+        let validation = validate(&SsbuSyncConfig {
+            online_only: true,
+            enable_triple_buffer: false,
+            ..console()
+        });
+        assert!(validation.effective.enable_triple_buffer);
+        assert_eq!(fields(&validation), ["enable_triple_buffer"]);
+
+        // Emulator keeps its own frame index policy, so there's nothing to force.
+        let validation = validate(&SsbuSyncConfig {
+            online_only: true,
+            enable_triple_buffer: false,
+            ..emulator()
+        });
+        assert!(!validation.effective.enable_triple_buffer);
+    }
+
+    #[test]
+    fn out_of_range_bias_falls_back_to_zero() {
+        let validation = validate(&SsbuSyncConfig {
+            slow_pacer_bias: true,
+            pacer_bias_double: MAX_PACER_BIAS + 1,
+            pacer_bias_triple: MAX_PACER_BIAS,
+            ..console()
+        });
+        assert_eq!(validation.effective.pacer_bias_double, 0);
+        assert_eq!(validation.effective.pacer_bias_triple, MAX_PACER_BIAS);
+        assert_eq!(fields(&validation), ["pacer_bias_double"]);
+        assert!(validation.has_errors());
+
+        // Without the slow bias the values aren't used, so they aren't checked.
+        let validation = validate(&SsbuSyncConfig {
+            slow_pacer_bias: false,
+            pacer_bias_double: MAX_PACER_BIAS + 1,
+            ..console()
+        });
+        assert!(validation.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn buffer_policy_thresholds_are_kept_apart() {
+        let validation = validate(&SsbuSyncConfig {
+            auto_buffer_policy: true,
+            allow_buffer_swap: true,
+            triple_fighter_threshold: 0,
+            double_fighter_threshold: 3,
+            ..console()
+        });
+        assert_eq!(validation.effective.triple_fighter_threshold, 1);
+        assert_eq!(validation.effective.double_fighter_threshold, 0);
+        assert_eq!(fields(&validation), ["triple_fighter_threshold", "double_fighter_threshold"]);
+    }
+
+    #[test]
+    fn buffer_policy_needs_buffer_swaps() {
+        let validation = validate(&SsbuSyncConfig {
+            auto_buffer_policy: true,
+            allow_buffer_swap: false,
+            ..console()
+        });
+        assert!(!validation.effective.auto_buffer_policy);
+        assert_eq!(fields(&validation), ["auto_buffer_policy"]);
+    }
+
+    #[test]
+    fn empty_windows_are_clamped_to_one() {
+        let validation = validate(&SsbuSyncConfig {
+            watchdog: true,
+            watchdog_window_frames: 0,
+            watchdog_skip_threshold: 0,
+            latency_stats: true,
+            latency_window_frames: 0,
+            ..console()
+        });
+        assert_eq!(validation.effective.watchdog_window_frames, 1);
+        assert_eq!(validation.effective.watchdog_skip_threshold, 1);
+        assert_eq!(validation.effective.latency_window_frames, 1);
+        assert_eq!(
+            fields(&validation),
+            ["watchdog_window_frames", "watchdog_skip_threshold", "latency_window_frames"]
+        );
+        assert_eq!(validation.at_least(Severity::Error).count(), 3);
+
+        // Features that are off don't read their windows.
+        let validation = validate(&SsbuSyncConfig {
+            watchdog: false,
+            watchdog_window_frames: 0,
+            latency_stats: false,
+            latency_window_frames: 0,
+            ..console()
+        });
+        assert!(validation.diagnostics.is_empty());
+    }
+}
@@ -0,0 +1,293 @@
+/** Frame-skip detector
+ *
+ * `Watchdog` watches the frame history `nn::vi::ListFrameHistory` reports and asks to step down one `DegradeLevel` each
+ * time presented frames start going missing in a burst. It only looks at the records it is fed, so recorded frame
+ * histories can be replayed through it; the plugin applies the levels.
+ */
+use std::collections::VecDeque;
+
+use nprof::stats::VBLANK_TICKS;
+
+use crate::config::SsbuSyncConfig;
+use crate::frame_history::{FrameInfo, FrameStatus};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum DegradeLevel {
+    Normal = 0,
+    TripleBuffer = 1,
+    PacerEnabled = 2,
+    Vanilla = 3,
+}
+
+impl DegradeLevel {
+    fn next(self) -> Option<Self> {
+        match self {
+            Self::Normal => Some(Self::TripleBuffer),
+            Self::TripleBuffer => Some(Self::PacerEnabled),
+            Self::PacerEnabled => Some(Self::Vanilla),
+            Self::Vanilla => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchdogConfig {
+    /// Number of presented frames the burst detector looks back over.
+    pub window_frames: u16,
+    /// Bad frames inside the window that count as a burst.
+    pub skip_threshold: u16,
+    /// Presented frames to wait after stepping down before looking for the next burst.
+    pub cooldown_frames: u16,
+}
+
+impl WatchdogConfig {
+    pub fn from_config(config: &SsbuSyncConfig) -> Self {
+        Self {
+            window_frames: config.watchdog_window_frames.max(1),
+            skip_threshold: config.watchdog_skip_threshold.max(1),
+            cooldown_frames: config.watchdog_cooldown_frames,
+        }
+    }
+}
+
+/// Why the watchdog stepped down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Escalation {
+    pub level: DegradeLevel,
+    /// Frame numbers that never got presented inside the window.
+    pub skipped: u32,
+    /// Presents that missed at least one vblank inside the window.
+    pub late: u32,
+    /// The presented frame that tipped the window over.
+    pub frame_number: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct WindowEntry {
+    skipped: u32,
+    late: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    config: WatchdogConfig,
+    level: DegradeLevel,
+    last_presented: Option<u64>,
+    last_vblank: Option<i64>,
+    window: VecDeque<WindowEntry>,
+    cooldown: u16,
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        Self {
+            config,
+            level: DegradeLevel::Normal,
+            last_presented: None,
+            last_vblank: None,
+            window: VecDeque::with_capacity(config.window_frames as usize),
+            cooldown: 0,
+        }
+    }
+
+    pub fn level(&self) -> DegradeLevel {
+        self.level
+    }
+
+    /// Moves past `level` when the plugin can't apply it (a buffer swap on emulator, say), returning the level to apply
+    /// instead. Does nothing unless `level` is the current one.
+    pub fn skip(&mut self, level: DegradeLevel) -> Option<DegradeLevel> {
+        if self.level != level {
+            return None;
+        }
+        self.level = level.next()?;
+        Some(self.level)
+    }
+
+    /// Feeds one frame history record. Records that aren't newly presented frames are ignored, so the whole
+    /// history can be passed in every time.
+    pub fn feed(&mut self, info: &FrameInfo) -> Option<Escalation> {
+        if info.status != FrameStatus::Presented {
+            return None;
+        }
+        if self.last_presented.is_some_and(|last| info.frame_number <= last) {
+            return None;
+        }
+
+        let skipped = match self.last_presented {
+            Some(last) => (info.frame_number - last - 1).min(u32::MAX as u64) as u32,
+            None => 0,
+        };
+        let late = match self.last_vblank {
+            Some(last) => info.vblank_time - last > VBLANK_TICKS + VBLANK_TICKS / 2,
+            None => false,
+        };
+        self.last_presented = Some(info.frame_number);
+        self.last_vblank = Some(info.vblank_time);
+
+        // Frames dropped while the last step settles don't count towards the next one.
+        if self.cooldown > 0 {
+            self.cooldown -= 1;
+            return None;
+        }
+
+        if self.window.len() == self.config.window_frames as usize {
+            self.window.pop_front();
+        }
+        self.window.push_back(WindowEntry { skipped, late });
+
+        let skipped: u32 = self.window.iter().map(|entry| entry.skipped).sum();
+        let late = self.window.iter().filter(|entry| entry.late).count() as u32;
+        if skipped + late < self.config.skip_threshold as u32 {
+            return None;
+        }
+
+        let level = self.level.next()?;
+        self.level = level;
+        self.window.clear();
+        self.cooldown = self.config.cooldown_frames;
+        Some(Escalation {
+            level,
+            skipped,
+            late,
+            frame_number: info.frame_number,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: WatchdogConfig = WatchdogConfig {
+        window_frames: 10,
+        skip_threshold: 3,
+        cooldown_frames: 5,
+    };
+
+    /// One presented frame per vblank starting at `start`, with the frame numbers in `gaps` left out.
+    fn trace(start: u64, count: u64, gaps: &[u64]) -> Vec<FrameInfo> {
+        (start..start + count)
+            .filter(|frame| !gaps.contains(frame))
+            .map(|frame| FrameInfo {
+                status: FrameStatus::Presented,
+                frame_number: frame,
+                vblank_time: frame as i64 * VBLANK_TICKS,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn replay(watchdog: &mut Watchdog, records: &[FrameInfo]) -> Vec<Escalation> {
+        records.iter().filter_map(|info| watchdog.feed(info)).collect()
+    }
+
+    #[test]
+    fn steady_trace_never_steps_down() {
+        let mut watchdog = Watchdog::new(CONFIG);
+        assert!(replay(&mut watchdog, &trace(0, 600, &[])).is_empty());
+        assert_eq!(watchdog.level(), DegradeLevel::Normal);
+    }
+
+    #[test]
+    fn scattered_skips_below_the_threshold_are_tolerated() {
+        let mut watchdog = Watchdog::new(CONFIG);
+        assert!(replay(&mut watchdog, &trace(0, 100, &[10, 30, 50, 70, 90])).is_empty());
+    }
+
+    #[test]
+    fn burst_steps_down_one_level() {
+        let mut watchdog = Watchdog::new(CONFIG);
+        let escalations = replay(&mut watchdog, &trace(0, 40, &[20, 21, 22]));
+        assert_eq!(
+            escalations,
+            [Escalation {
+                level: DegradeLevel::TripleBuffer,
+                skipped: 3,
+                late: 1,
+                frame_number: 23,
+            }]
+        );
+        assert_eq!(watchdog.level(), DegradeLevel::TripleBuffer);
+    }
+
+    #[test]
+    fn late_vblanks_count_towards_a_burst() {
+        let mut watchdog = Watchdog::new(CONFIG);
+        let mut records = trace(0, 40, &[]);
+        for info in &mut records[20..] {
+            info.vblank_time += 2 * VBLANK_TICKS;
+        }
+        for info in &mut records[21..] {
+            info.vblank_time += 2 * VBLANK_TICKS;
+        }
+        for info in &mut records[22..] {
+            info.vblank_time += 2 * VBLANK_TICKS;
+        }
+        let escalations = replay(&mut watchdog, &records);
+        assert_eq!(escalations.len(), 1);
+        assert_eq!(escalations[0].skipped, 0);
+        assert_eq!(escalations[0].late, 3);
+        assert_eq!(escalations[0].frame_number, 22);
+    }
+
+    #[test]
+    fn cooldown_holds_off_the_next_step() {
+        let mut watchdog = Watchdog::new(CONFIG);
+        // The second burst lands inside the cooldown after the first and is forgotten with it.
+        let escalations = replay(&mut watchdog, &trace(0, 40, &[10, 11, 12, 14, 15, 16]));
+        assert_eq!(escalations.len(), 1);
+        assert_eq!(watchdog.level(), DegradeLevel::TripleBuffer);
+
+        let escalations = replay(&mut watchdog, &trace(40, 40, &[60, 61, 62]));
+        assert_eq!(escalations.len(), 1);
+        assert_eq!(escalations[0].level, DegradeLevel::PacerEnabled);
+    }
+
+    #[test]
+    fn stops_at_vanilla() {
+        let mut watchdog = Watchdog::new(CONFIG);
+        let levels: Vec<_> = replay(&mut watchdog, &trace(0, 200, &[20, 21, 22, 60, 61, 62, 100, 101, 102, 140, 141, 142]))
+            .into_iter()
+            .map(|escalation| escalation.level)
+            .collect();
+        assert_eq!(levels, [DegradeLevel::TripleBuffer, DegradeLevel::PacerEnabled, DegradeLevel::Vanilla]);
+        assert_eq!(watchdog.level(), DegradeLevel::Vanilla);
+    }
+
+    #[test]
+    fn skipping_a_level_moves_to_the_next_one() {
+        let mut watchdog = Watchdog::new(CONFIG);
+        assert_eq!(watchdog.skip(DegradeLevel::TripleBuffer), None);
+        replay(&mut watchdog, &trace(0, 20, &[10, 11, 12]));
+        assert_eq!(watchdog.skip(DegradeLevel::TripleBuffer), Some(DegradeLevel::PacerEnabled));
+        // A late retry of the skipped level doesn't skip the next one as well.
+        assert_eq!(watchdog.skip(DegradeLevel::TripleBuffer), None);
+        assert_eq!(watchdog.level(), DegradeLevel::PacerEnabled);
+
+        let escalations = replay(&mut watchdog, &trace(20, 40, &[40, 41, 42]));
+        assert_eq!(escalations.len(), 1);
+        assert_eq!(escalations[0].level, DegradeLevel::Vanilla);
+    }
+
+    #[test]
+    fn replaying_the_whole_history_only_counts_new_frames() {
+        let mut watchdog = Watchdog::new(CONFIG);
+        let history = trace(0, 30, &[20, 21, 22]);
+        // `ListFrameHistory` hands back overlapping windows; older and unpresented records must be ignored.
+        let mut escalations = Vec::new();
+        for end in 1..=history.len() {
+            let start = end.saturating_sub(8);
+            let mut window = history[start..end].to_vec();
+            window.push(FrameInfo {
+                status: FrameStatus::Enqueued,
+                frame_number: history[end - 1].frame_number + 1,
+                ..Default::default()
+            });
+            escalations.extend(replay(&mut watchdog, &window));
+        }
+        assert_eq!(escalations.len(), 1);
+        assert_eq!(escalations[0].frame_number, 23);
+    }
+}
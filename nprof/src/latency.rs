@@ -0,0 +1,193 @@
+/** Rolling present latency statistics
+ *
+ * `LatencyStats` keeps aggregates over the last few presented frames of a `nn::vi::ListFrameHistory`: enqueue→present
+ * and present→vblank latency, missed vblanks and frame number gaps, summarized as the `wire::LatencySummary` record.
+ * The plugin feeds it live; it only looks at the records it is fed, so it is tested with synthetic histories.
+ */
+use std::collections::VecDeque;
+
+use crate::stats::{Summary, VBLANK_TICKS};
+use crate::wire::LatencySummary;
+
+/// One presented frame from the history, in system ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresentedFrame {
+    pub frame_number: u64,
+    pub enqueue: i64,
+    pub present: i64,
+    pub vblank: i64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    enqueue_to_present: i64,
+    present_to_vblank: i64,
+    missed_vblanks: u32,
+    frame_gap: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct LatencyStats {
+    window_frames: usize,
+    entries: VecDeque<Entry>,
+    last: Option<PresentedFrame>,
+}
+
+impl LatencyStats {
+    pub fn new(window_frames: usize) -> Self {
+        let window_frames = window_frames.max(1);
+        Self {
+            window_frames,
+            entries: VecDeque::with_capacity(window_frames),
+            last: None,
+        }
+    }
+
+    /// Adds a presented frame. Frames at or before the newest one already seen are ignored, so the whole history can
+    /// be passed in every time; returns whether this one was new.
+    pub fn feed(&mut self, frame: PresentedFrame) -> bool {
+        if self.last.is_some_and(|last| frame.frame_number <= last.frame_number) {
+            return false;
+        }
+
+        let (missed_vblanks, frame_gap) = match self.last {
+            Some(last) => {
+                // Round to whole vblanks so jitter in the reported times doesn't count as a miss.
+                let vblanks = (frame.vblank - last.vblank + VBLANK_TICKS / 2) / VBLANK_TICKS;
+                (
+                    (vblanks - 1).clamp(0, u32::MAX as i64) as u32,
+                    (frame.frame_number - last.frame_number - 1).min(u32::MAX as u64) as u32,
+                )
+            }
+            None => (0, 0),
+        };
+        self.last = Some(frame);
+
+        if self.entries.len() == self.window_frames {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry {
+            enqueue_to_present: frame.present - frame.enqueue,
+            present_to_vblank: frame.vblank - frame.present,
+            missed_vblanks,
+            frame_gap,
+        });
+        true
+    }
+
+    /// `None` until a frame has been presented.
+    pub fn summary(&self) -> Option<LatencySummary> {
+        let last = self.last?;
+        let enqueue_to_present: Vec<i64> = self.entries.iter().map(|entry| entry.enqueue_to_present).collect();
+        let present_to_vblank: Vec<i64> = self.entries.iter().map(|entry| entry.present_to_vblank).collect();
+        Some(LatencySummary {
+            enqueue_to_present: Summary::of(&enqueue_to_present)?,
+            present_to_vblank: Summary::of(&present_to_vblank)?,
+            missed_vblanks: self.entries.iter().map(|entry| entry.missed_vblanks).sum(),
+            frame_gaps: self.entries.iter().map(|entry| entry.frame_gap).sum(),
+            last_frame: last.frame_number,
+            last_vblank: last.vblank,
+        })
+    }
+
+    /// Forgets everything, e.g. after a scene change where gaps are expected.
+    pub fn reset(&mut self) {
+        self.entries.clear();
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A steady 60fps history: frame `n` is enqueued `queued` ticks before its present and shown `scanout` after.
+    fn steady(frames: std::ops::Range<u64>, queued: i64, scanout: i64) -> Vec<PresentedFrame> {
+        frames
+            .map(|frame_number| {
+                let present = frame_number as i64 * VBLANK_TICKS;
+                PresentedFrame {
+                    frame_number,
+                    enqueue: present - queued,
+                    present,
+                    vblank: present + scanout,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn summarizes_a_steady_history() {
+        let mut stats = LatencyStats::new(600);
+        assert_eq!(stats.summary(), None);
+        for frame in steady(1..121, 1000, 5000) {
+            assert!(stats.feed(frame));
+        }
+        let summary = stats.summary().unwrap();
+        assert_eq!(summary.enqueue_to_present.count, 120);
+        assert_eq!(summary.enqueue_to_present.p95, 1000);
+        assert_eq!(summary.present_to_vblank.max, 5000);
+        assert_eq!(summary.missed_vblanks, 0);
+        assert_eq!(summary.frame_gaps, 0);
+        assert_eq!(summary.last_frame, 120);
+        assert_eq!(summary.last_vblank, 120 * VBLANK_TICKS + 5000);
+    }
+
+    #[test]
+    fn ignores_frames_already_seen() {
+        let mut stats = LatencyStats::new(600);
+        let history = steady(1..5, 1000, 5000);
+        for frame in &history {
+            stats.feed(*frame);
+        }
+        // The history ring hands back the same records on the next call.
+        for frame in &history {
+            assert!(!stats.feed(*frame));
+        }
+        assert_eq!(stats.summary().unwrap().enqueue_to_present.count, 4);
+    }
+
+    #[test]
+    fn counts_missed_vblanks_and_frame_gaps() {
+        let mut stats = LatencyStats::new(600);
+        let mut history = steady(1..4, 1000, 5000);
+        // Frame 4 was never presented and frame 5 came two vblanks late (with a little jitter).
+        history.push(PresentedFrame {
+            frame_number: 5,
+            enqueue: 6 * VBLANK_TICKS - 1000,
+            present: 6 * VBLANK_TICKS,
+            vblank: 6 * VBLANK_TICKS + 5000 + 300,
+        });
+        for frame in history {
+            stats.feed(frame);
+        }
+        let summary = stats.summary().unwrap();
+        assert_eq!(summary.frame_gaps, 1);
+        assert_eq!(summary.missed_vblanks, 2);
+        assert_eq!(summary.present_to_vblank.max, 5300);
+    }
+
+    #[test]
+    fn rolls_old_frames_out_of_the_window() {
+        let mut stats = LatencyStats::new(10);
+        let mut history = steady(1..3, 1000, 5000);
+        history.push(PresentedFrame {
+            frame_number: 4,
+            enqueue: 4 * VBLANK_TICKS - 90_000,
+            present: 4 * VBLANK_TICKS,
+            vblank: 4 * VBLANK_TICKS + 5000,
+        });
+        history.extend(steady(5..15, 1000, 5000));
+        for frame in history {
+            stats.feed(frame);
+        }
+        // Frame 4's gap and latency spike have left the 10-frame window.
+        let summary = stats.summary().unwrap();
+        assert_eq!(summary.enqueue_to_present.count, 10);
+        assert_eq!(summary.enqueue_to_present.max, 1000);
+        assert_eq!(summary.frame_gaps, 0);
+
+        stats.reset();
+        assert_eq!(stats.summary(), None);
+    }
+}
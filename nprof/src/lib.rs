@@ -0,0 +1,12 @@
+/*! Host-side tools for the ssbusync profiler
+ *
+ * `wire`, `spans` and `ring` are shared with the plugin, which builds and serializes frames through them, so the two
+ * can't drift apart, and so is `latency`, which aggregates the present latency the plugin reports. `trace` and the
+ * `nprof` binary only run on the host.
+ */
+pub mod latency;
+pub mod ring;
+pub mod spans;
+pub mod stats;
+pub mod trace;
+pub mod wire;
@@ -0,0 +1,246 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process::ExitCode;
+
+use nprof::stats::{Analysis, Histogram, Summary};
+use nprof::trace::ChromeTrace;
+use nprof::wire::Decoder;
+
+const DEFAULT_PORT: u16 = 1776;
+
+const USAGE: &str = "\
+usage:
+    nprof connect <switch-ip>[:port] [--record <capture.bin>] [--every <frames>] [--trace <trace.json>]
+    nprof replay <capture.bin> [--trace <trace.json>]
+
+--trace writes a Chrome Trace Event file for chrome://tracing or ui.perfetto.dev.";
+
+enum Source {
+    Connect { addr: String, record: Option<String> },
+    Replay(String),
+}
+
+struct Options {
+    source: Source,
+    every: u64,
+    trace: Option<String>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let command = args.next().ok_or("missing command")?;
+    let target = args.next().ok_or("missing address or capture path")?;
+    let mut record = None;
+    let mut every = 0;
+    let mut trace = None;
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(format!("{} needs a value", flag))?;
+        match flag.as_str() {
+            "--record" if command == "connect" => record = Some(value),
+            "--every" if command == "connect" => {
+                every = value.parse().map_err(|_| format!("bad frame count '{}'", value))?
+            }
+            "--trace" => trace = Some(value),
+            _ => return Err(format!("unknown flag '{}'", flag)),
+        }
+    }
+    let source = match command.as_str() {
+        "replay" => Source::Replay(target),
+        "connect" => {
+            let addr = if target.contains(':') {
+                target
+            } else {
+                format!("{}:{}", target, DEFAULT_PORT)
+            };
+            Source::Connect { addr, record }
+        }
+        _ => return Err(format!("unknown command '{}'", command)),
+    };
+    Ok(Options { source, every, trace })
+}
+
+fn print_summary(analysis: &Analysis, label: &str, summary: &Summary) {
+    println!(
+        "{:<28} {:>7} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3}",
+        label,
+        summary.count,
+        analysis.millis(summary.mean),
+        analysis.millis(summary.p50 as f64),
+        analysis.millis(summary.p95 as f64),
+        analysis.millis(summary.p99 as f64),
+        analysis.millis(summary.max as f64),
+    );
+}
+
+fn print_histogram(title: &str, histogram: &Histogram, label: impl Fn(usize) -> String) {
+    let total = histogram.total().max(1);
+    println!("{}", title);
+    for (index, &count) in histogram.buckets.iter().enumerate() {
+        let bar = "#".repeat(((count * 50) / total) as usize);
+        println!("  {:>10} {:>7} {}", label(index), count, bar);
+    }
+}
+
+fn report(analysis: &Analysis) {
+    if let Some(handshake) = &analysis.handshake {
+        println!(
+            "\nprotocol {}, game {}, ssbusync {}, {}Hz ticks",
+            handshake.protocol, handshake.build, handshake.plugin_version, handshake.tick_frequency
+        );
+    }
+    println!(
+        "\n{} frames, {} dangled, {} skipped",
+        analysis.frames, analysis.dangled, analysis.skipped
+    );
+    if analysis.dropped_events != 0 {
+        println!(
+            "{} profiler events dropped on the console (queue full); spans may be missing",
+            analysis.dropped_events
+        );
+    }
+    if !analysis.threads.is_empty() {
+        let threads: Vec<String> = analysis
+            .threads
+            .iter()
+            .map(|(name, count)| format!("{} ({})", name, count))
+            .collect();
+        println!("spans by thread: {}", threads.join(", "));
+    }
+    if let Some(latency) = &analysis.latency {
+        println!(
+            "console frame history ({} frames up to frame {}): {} missed vblanks, {} frame gaps",
+            latency.enqueue_to_present.count, latency.last_frame, latency.missed_vblanks, latency.frame_gaps
+        );
+    }
+    println!(
+        "{:<28} {:>7} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "span (ms)", "count", "mean", "p50", "p95", "p99", "max"
+    );
+    for (name, durations) in analysis.spans.iter() {
+        if let Some(summary) = Summary::of(durations) {
+            print_summary(analysis, name, &summary);
+        }
+    }
+    if let Some(summary) = Summary::of(&analysis.frame_latency) {
+        print_summary(analysis, "<frame start to present>", &summary);
+    }
+    if let Some(summary) = Summary::of(&analysis.present_intervals) {
+        print_summary(analysis, "<present interval>", &summary);
+    }
+    if let Some(summary) = Summary::of(&analysis.vblank_intervals) {
+        print_summary(analysis, "<vblank interval>", &summary);
+    }
+    if let Some(latency) = &analysis.latency {
+        print_summary(analysis, "<enqueue to present>", &latency.enqueue_to_present);
+        print_summary(analysis, "<present to vblank>", &latency.present_to_vblank);
+    }
+
+    let pacing = analysis.pacing_histogram();
+    let last = pacing.buckets.len() - 1;
+    print_histogram("present interval (vblanks)", &pacing, |index| match index {
+        0 => "<1".to_string(),
+        index if index == last => format!("{}+", index),
+        index => index.to_string(),
+    });
+    let latency = analysis.latency_histogram();
+    let width = analysis.millis(latency.bucket_width as f64);
+    let last = latency.buckets.len() - 1;
+    print_histogram("frame latency (ms)", &latency, |index| {
+        if index == last {
+            format!("{:.0}+", index as f64 * width)
+        } else {
+            format!("{:.0}-{:.0}", index as f64 * width, (index + 1) as f64 * width)
+        }
+    });
+}
+
+fn run(
+    mut input: impl Read,
+    mut record: Option<File>,
+    every: u64,
+    mut trace: Option<&mut ChromeTrace>,
+) -> Result<Analysis, String> {
+    let mut decoder = Decoder::new();
+    let mut analysis = Analysis::new();
+    let mut chunk = [0u8; 64 * 1024];
+    let mut next_report = every;
+    loop {
+        let read = input.read(&mut chunk).map_err(|err| err.to_string())?;
+        if read == 0 {
+            break;
+        }
+        if let Some(file) = record.as_mut() {
+            file.write_all(&chunk[..read]).map_err(|err| err.to_string())?;
+        }
+        decoder.push(&chunk[..read]);
+        while let Some(record) = decoder.next_record().map_err(|err| err.to_string())? {
+            analysis.add(&record);
+            if let Some(trace) = trace.as_mut() {
+                trace.add(&record);
+            }
+        }
+        if every != 0 && analysis.frames >= next_report {
+            report(&analysis);
+            next_report = analysis.frames + every;
+        }
+    }
+    if decoder.skipped() != 0 {
+        eprintln!("skipped {} corrupt bytes while resyncing", decoder.skipped());
+    }
+    if decoder.pending() != 0 {
+        eprintln!("ignoring {} bytes of a partial record at the end of the stream", decoder.pending());
+    }
+    Ok(analysis)
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut trace = options.trace.as_ref().map(|_| ChromeTrace::new());
+    let result = match options.source {
+        Source::Replay(path) => File::open(&path)
+            .map_err(|err| format!("{}: {}", path, err))
+            .and_then(|file| run(file, None, 0, trace.as_mut())),
+        Source::Connect { addr, record } => {
+            let record = match record.map(File::create).transpose() {
+                Ok(record) => record,
+                Err(err) => {
+                    eprintln!("could not create capture file: {}", err);
+                    return ExitCode::FAILURE;
+                }
+            };
+            eprintln!("connecting to {}", addr);
+            TcpStream::connect(&addr)
+                .map_err(|err| format!("{}: {}", addr, err))
+                .and_then(|stream| run(stream, record, options.every, trace.as_mut()))
+        }
+    };
+    match result {
+        Ok(analysis) => {
+            report(&analysis);
+            if let (Some(path), Some(trace)) = (options.trace, trace) {
+                let written = File::create(&path)
+                    .map(std::io::BufWriter::new)
+                    .and_then(|file| trace.write_to(file));
+                match written {
+                    Ok(()) => eprintln!("wrote {}", path),
+                    Err(err) => {
+                        eprintln!("{}: {}", path, err);
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,256 @@
+/*! Fixed-capacity lock-free queue for profiler events
+ *
+ * Span hooks run on the game's hot paths, so recording an event must not allocate, lock, or block. `Ring` is a bounded
+ * multi-producer queue (Dmitry Vyukov's array queue): every slot carries a sequence number, producers claim a slot
+ * with one CAS on `head`, and the consumer frees it by bumping that slot's sequence a lap ahead. All memory is
+ * allocated up front. When the queue is full, `push` hands the event back and counts it in `dropped` instead of
+ * waiting for the consumer.
+ *
+ * It is here rather than in the plugin so the stress tests can run on the host.
+ */
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Keeps the producer and consumer cursors on separate cache lines.
+#[repr(align(64))]
+struct Padded<T>(T);
+
+struct Slot<T> {
+    /// `pos` when the slot is free for the push at `pos`, `pos + 1` once that push has written it.
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+pub struct Ring<T> {
+    slots: Box<[Slot<T>]>,
+    mask: usize,
+    /// Next position to push.
+    head: Padded<AtomicUsize>,
+    /// Next position to pop.
+    tail: Padded<AtomicUsize>,
+    dropped: AtomicU64,
+}
+
+unsafe impl<T: Send> Send for Ring<T> {}
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    /// `capacity` is rounded up to a power of two, at least 2.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(2).next_power_of_two();
+        let slots = (0..capacity)
+            .map(|i| Slot {
+                sequence: AtomicUsize::new(i),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect();
+        Self {
+            slots,
+            mask: capacity - 1,
+            head: Padded(AtomicUsize::new(0)),
+            tail: Padded(AtomicUsize::new(0)),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Events refused because the queue was full, since it was created.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Queues `value`, or gives it back if the queue is full. Never blocks.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mut pos = self.head.0.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & self.mask];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            let lag = sequence.wrapping_sub(pos) as isize;
+            if lag == 0 {
+                match self
+                    .head
+                    .0
+                    .compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    Ok(_) => {
+                        // The CAS made this slot ours until we publish it below.
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.sequence.store(pos.wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if lag < 0 {
+                // The slot still holds the value from a lap ago: full.
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return Err(value);
+            } else {
+                // Another producer took this position; catch up.
+                pos = self.head.0.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Takes the oldest event, if any. Safe to call from several threads, though the profiler only uses one.
+    pub fn pop(&self) -> Option<T> {
+        let mut pos = self.tail.0.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & self.mask];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            let lag = sequence.wrapping_sub(pos.wrapping_add(1)) as isize;
+            if lag == 0 {
+                match self
+                    .tail
+                    .0
+                    .compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    Ok(_) => {
+                        let value = unsafe { (*slot.value.get()).assume_init_read() };
+                        slot.sequence
+                            .store(pos.wrapping_add(self.mask).wrapping_add(1), Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if lag < 0 {
+                return None;
+            } else {
+                pos = self.tail.0.load(Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    #[test]
+    fn is_fifo_and_refuses_when_full() {
+        let ring = Ring::new(3);
+        assert_eq!(ring.capacity(), 4);
+        for i in 0..4 {
+            ring.push(i).unwrap();
+        }
+        assert_eq!(ring.push(4), Err(4));
+        assert_eq!(ring.dropped(), 1);
+        assert_eq!(ring.pop(), Some(0));
+        ring.push(5).unwrap();
+        let rest: Vec<_> = std::iter::from_fn(|| ring.pop()).collect();
+        assert_eq!(rest, vec![1, 2, 3, 5]);
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn drops_values_left_in_the_queue() {
+        let counter = Arc::new(());
+        let ring = Ring::new(8);
+        for _ in 0..5 {
+            ring.push(counter.clone()).unwrap();
+        }
+        drop(ring.pop());
+        assert_eq!(Arc::strong_count(&counter), 5);
+        drop(ring);
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+
+    /// Several producers hammer a small ring while one consumer drains it. Every value must arrive at most once, in
+    /// order per producer, and whatever didn't arrive must be counted as dropped.
+    #[test]
+    fn stress_many_producers() {
+        const PRODUCERS: u64 = 8;
+        const PER_PRODUCER: u64 = 200_000;
+
+        let ring = Arc::new(Ring::<(u64, u64)>::new(256));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let consumer = {
+            let ring = ring.clone();
+            let done = done.clone();
+            std::thread::spawn(move || {
+                let mut next = vec![0u64; PRODUCERS as usize];
+                let mut received = 0u64;
+                loop {
+                    match ring.pop() {
+                        Some((producer, seq)) => {
+                            let expected = &mut next[producer as usize];
+                            assert!(seq >= *expected, "producer {} went backwards: {} < {}", producer, seq, expected);
+                            *expected = seq + 1;
+                            received += 1;
+                        }
+                        None if done.load(Ordering::Acquire) => {
+                            // Producers are finished; one more pass picks up anything published just before.
+                            while let Some((producer, seq)) = ring.pop() {
+                                assert!(seq >= next[producer as usize]);
+                                next[producer as usize] = seq + 1;
+                                received += 1;
+                            }
+                            return received;
+                        }
+                        None => std::hint::spin_loop(),
+                    }
+                }
+            })
+        };
+
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|producer| {
+                let ring = ring.clone();
+                std::thread::spawn(move || {
+                    let mut refused = 0u64;
+                    for seq in 0..PER_PRODUCER {
+                        if ring.push((producer, seq)).is_err() {
+                            refused += 1;
+                        }
+                    }
+                    refused
+                })
+            })
+            .collect();
+        let refused: u64 = producers.into_iter().map(|p| p.join().unwrap()).sum();
+        done.store(true, Ordering::Release);
+        let received = consumer.join().unwrap();
+
+        assert_eq!(refused, ring.dropped());
+        assert_eq!(received + refused, PRODUCERS * PER_PRODUCER);
+        assert!(received > 0);
+    }
+
+    /// With a consumer that keeps up and a ring bigger than the burst, nothing is lost.
+    #[test]
+    fn stress_without_drops() {
+        const PRODUCERS: u64 = 4;
+        const PER_PRODUCER: u64 = 10_000;
+
+        let ring = Arc::new(Ring::<u64>::new((PRODUCERS * PER_PRODUCER) as usize));
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|producer| {
+                let ring = ring.clone();
+                std::thread::spawn(move || {
+                    for seq in 0..PER_PRODUCER {
+                        ring.push(producer * PER_PRODUCER + seq).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut values: Vec<_> = std::iter::from_fn(|| ring.pop()).collect();
+        values.sort_unstable();
+        assert_eq!(values, (0..PRODUCERS * PER_PRODUCER).collect::<Vec<_>>());
+        assert_eq!(ring.dropped(), 0);
+    }
+}
@@ -0,0 +1,193 @@
+/*! Span nesting for one frame
+ *
+ * The plugin's profiler thread feeds `start`/`end` events through a `SpanStack`, which gives every span a parent and
+ * depth and notices when an `end` doesn't match the innermost open span. It lives here rather than in the plugin so
+ * it can be tested on the host.
+ */
+use crate::wire::SpanRef;
+
+/// An `end` that didn't match the innermost open span.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpanMismatch {
+    /// No span with this name is open; the end was ignored.
+    NotOpen { name: &'static str },
+    /// `name` was open but not innermost. The spans in `closed` (innermost first) were closed early and are marked
+    /// `unclosed`.
+    OutOfOrder {
+        name: &'static str,
+        closed: Vec<&'static str>,
+    },
+}
+
+impl std::fmt::Display for SpanMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotOpen { name } => write!(f, "end_span(\"{}\") without a matching start_span", name),
+            Self::OutOfOrder { name, closed } => write!(
+                f,
+                "end_span(\"{}\") while {:?} were still open inside it; closed them too",
+                name, closed
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SpanStack {
+    spans: Vec<SpanRef<'static>>,
+    /// Indices into `spans`, outermost first.
+    open: Vec<u32>,
+}
+
+impl SpanStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// Opens `name` inside the innermost open span. Starting the span that is already innermost again is a no-op,
+    /// which is what hooks that fire on every loop iteration want.
+    pub fn start(&mut self, name: &'static str, tick: i64) {
+        let parent = self.open.last().copied();
+        if let Some(parent) = parent {
+            if self.spans[parent as usize].name == name {
+                return;
+            }
+        }
+        self.open.push(self.spans.len() as u32);
+        self.spans.push(SpanRef {
+            name,
+            start: tick,
+            end: tick,
+            parent,
+            depth: (self.open.len() - 1) as u16,
+            unclosed: false,
+        });
+    }
+
+    pub fn end(&mut self, name: &'static str, tick: i64) -> Result<(), SpanMismatch> {
+        let Some(position) = self
+            .open
+            .iter()
+            .rposition(|&index| self.spans[index as usize].name == name)
+        else {
+            return Err(SpanMismatch::NotOpen { name });
+        };
+
+        let mut closed = Vec::new();
+        while self.open.len() > position + 1 {
+            let index = self.open.pop().unwrap() as usize;
+            let span = &mut self.spans[index];
+            span.end = tick;
+            span.unclosed = true;
+            closed.push(span.name);
+        }
+        let index = self.open.pop().unwrap() as usize;
+        self.spans[index].end = tick;
+
+        if closed.is_empty() {
+            Ok(())
+        } else {
+            Err(SpanMismatch::OutOfOrder { name, closed })
+        }
+    }
+
+    /// Closes anything still open at `tick` (marked `unclosed`) and returns the frame's spans in start order, along
+    /// with the names that had to be closed.
+    pub fn finish(mut self, tick: i64) -> (Vec<SpanRef<'static>>, Vec<&'static str>) {
+        let mut unclosed = Vec::new();
+        while let Some(index) = self.open.pop() {
+            let span = &mut self.spans[index as usize];
+            span.end = tick;
+            span.unclosed = true;
+            unclosed.push(span.name);
+        }
+        (self.spans, unclosed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(name, parent, depth, start, end, unclosed)`
+    type Shape = (&'static str, Option<u32>, u16, i64, i64, bool);
+
+    fn shape(spans: &[SpanRef<'static>]) -> Vec<Shape> {
+        spans
+            .iter()
+            .map(|s| (s.name, s.parent, s.depth, s.start, s.end, s.unclosed))
+            .collect()
+    }
+
+    #[test]
+    fn nests_spans() {
+        let mut stack = SpanStack::new();
+        stack.start("Looping", 0);
+        stack.start("RunSceneManager", 1);
+        stack.start("MutexLock", 2);
+        stack.end("MutexLock", 3).unwrap();
+        stack.end("RunSceneManager", 4).unwrap();
+        stack.start("UiUpdate", 5);
+        stack.end("UiUpdate", 6).unwrap();
+        stack.end("Looping", 7).unwrap();
+        let (spans, unclosed) = stack.finish(8);
+        assert!(unclosed.is_empty());
+        assert_eq!(
+            shape(&spans),
+            vec![
+                ("Looping", None, 0, 0, 7, false),
+                ("RunSceneManager", Some(0), 1, 1, 4, false),
+                ("MutexLock", Some(1), 2, 2, 3, false),
+                ("UiUpdate", Some(0), 1, 5, 6, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn restarting_the_innermost_span_is_ignored() {
+        let mut stack = SpanStack::new();
+        stack.start("Looping", 0);
+        stack.start("Looping", 1);
+        stack.end("Looping", 2).unwrap();
+        let (spans, _) = stack.finish(3);
+        assert_eq!(shape(&spans), vec![("Looping", None, 0, 0, 2, false)]);
+    }
+
+    #[test]
+    fn reports_mismatched_ends() {
+        let mut stack = SpanStack::new();
+        stack.start("Looping", 0);
+        stack.start("MutexLock", 1);
+        assert_eq!(stack.end("UiUpdate", 2), Err(SpanMismatch::NotOpen { name: "UiUpdate" }));
+        assert_eq!(
+            stack.end("Looping", 3),
+            Err(SpanMismatch::OutOfOrder {
+                name: "Looping",
+                closed: vec!["MutexLock"],
+            })
+        );
+        let (spans, unclosed) = stack.finish(4);
+        assert!(unclosed.is_empty());
+        assert_eq!(
+            shape(&spans),
+            vec![
+                ("Looping", None, 0, 0, 3, false),
+                ("MutexLock", Some(0), 1, 1, 3, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn finish_closes_open_spans() {
+        let mut stack = SpanStack::new();
+        stack.start("Looping", 0);
+        stack.start("RunSceneManager", 1);
+        let (spans, unclosed) = stack.finish(9);
+        assert_eq!(unclosed, vec!["RunSceneManager", "Looping"]);
+        assert!(spans.iter().all(|s| s.end == 9 && s.unclosed));
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+
+use crate::wire::{Frame, Handshake, LatencySummary, Record};
+
+/// System ticks per second on the console (19.2MHz), for streams whose handshake didn't say.
+pub const TICK_FREQUENCY: i64 = 19_200_000;
+/// System ticks per vblank at 60Hz.
+pub const VBLANK_TICKS: i64 = TICK_FREQUENCY / 60;
+
+pub fn ticks_to_nanos(ticks: i64, tick_frequency: i64) -> i64 {
+    (ticks as i128 * 1_000_000_000 / tick_frequency.max(1) as i128) as i64
+}
+
+/// Takes a float so means and bucket bounds convert the same way as whole tick counts.
+pub fn ticks_to_millis(ticks: f64, tick_frequency: i64) -> f64 {
+    ticks * 1000.0 / tick_frequency.max(1) as f64
+}
+
+/// Distribution of a set of durations, in ticks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub count: usize,
+    pub mean: f64,
+    pub min: i64,
+    pub p50: i64,
+    pub p95: i64,
+    pub p99: i64,
+    pub max: i64,
+}
+
+/// Nearest-rank percentile of an already sorted slice.
+pub fn percentile(sorted: &[i64], pct: f64) -> i64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl Summary {
+    pub fn of(values: &[i64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_unstable();
+        let sum: i128 = sorted.iter().map(|&v| v as i128).sum();
+        Some(Self {
+            count: sorted.len(),
+            mean: sum as f64 / sorted.len() as f64,
+            min: sorted[0],
+            p50: percentile(&sorted, 50.0),
+            p95: percentile(&sorted, 95.0),
+            p99: percentile(&sorted, 99.0),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+/// Counts of values falling into fixed-width buckets; the last bucket also holds everything above it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    pub bucket_width: i64,
+    pub buckets: Vec<u64>,
+}
+
+impl Histogram {
+    pub fn new(bucket_width: i64, bucket_count: usize) -> Self {
+        Self {
+            bucket_width: bucket_width.max(1),
+            buckets: vec![0; bucket_count.max(1)],
+        }
+    }
+
+    pub fn add(&mut self, value: i64) {
+        let index = (value.max(0) / self.bucket_width) as usize;
+        let last = self.buckets.len() - 1;
+        self.buckets[index.min(last)] += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+}
+
+/// Everything the analyzer keeps from a stream.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    /// The last handshake seen; v1 streams don't have one.
+    pub handshake: Option<Handshake>,
+    /// Span durations by name, in ticks.
+    pub spans: BTreeMap<String, Vec<i64>>,
+    /// Spans recorded per thread name. Empty for streams from before per-thread lanes.
+    pub threads: BTreeMap<String, u64>,
+    /// Frame start to present, in ticks.
+    pub frame_latency: Vec<i64>,
+    /// Present to present of consecutive non-dangling frames, in ticks.
+    pub present_intervals: Vec<i64>,
+    pub vblank_intervals: Vec<i64>,
+    pub frames: u64,
+    pub dangled: u64,
+    /// Frame indices that were never seen between two presented frames.
+    pub skipped: u64,
+    /// Events the plugin dropped because its queue was full, summed over connections.
+    pub dropped_events: u64,
+    /// The newest present latency summary from the console's frame history, if it sent any.
+    pub latency: Option<LatencySummary>,
+    /// The current connection's running drop count, reset by each handshake.
+    connection_dropped: u64,
+    last_present: Option<(u64, i64)>,
+    last_vblank: Option<i64>,
+}
+
+impl Analysis {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ticks per second from the handshake, or the console's 19.2MHz for streams without one.
+    pub fn tick_frequency(&self) -> i64 {
+        self.handshake
+            .as_ref()
+            .map(|handshake| handshake.tick_frequency.min(i64::MAX as u64) as i64)
+            .filter(|&frequency| frequency > 0)
+            .unwrap_or(TICK_FREQUENCY)
+    }
+
+    pub fn millis(&self, ticks: f64) -> f64 {
+        ticks_to_millis(ticks, self.tick_frequency())
+    }
+
+    pub fn add(&mut self, record: &Record) {
+        match record {
+            Record::Handshake(handshake) => {
+                // A handshake starts a new connection; intervals must not span the reconnect.
+                self.handshake = Some(handshake.clone());
+                self.connection_dropped = 0;
+                self.last_present = None;
+                self.last_vblank = None;
+            }
+            Record::Frame(frame) => self.add_frame(frame),
+            &Record::Dropped(events) => {
+                self.dropped_events += events.saturating_sub(self.connection_dropped);
+                self.connection_dropped = events;
+            }
+            Record::Latency(latency) => self.latency = Some(*latency),
+            Record::VBlank(tick) => {
+                if let Some(last) = self.last_vblank {
+                    self.vblank_intervals.push(tick - last);
+                }
+                self.last_vblank = Some(*tick);
+            }
+        }
+    }
+
+    fn add_frame(&mut self, frame: &Frame) {
+        self.frames += 1;
+        for span in frame.spans.iter() {
+            self.spans
+                .entry(span.name.clone())
+                .or_default()
+                .push(span.duration());
+            if let Some(lane) = frame.lanes.get(span.lane as usize).filter(|lane| !lane.is_unknown()) {
+                let name = if lane.name.is_empty() {
+                    format!("{:#x}", lane.thread_id)
+                } else {
+                    lane.name.clone()
+                };
+                *self.threads.entry(name).or_default() += 1;
+            }
+        }
+        if frame.dangled {
+            self.dangled += 1;
+            return;
+        }
+        self.frame_latency.push(frame.presented - frame.start);
+        if let Some((index, presented)) = self.last_present {
+            if frame.index > index {
+                self.present_intervals.push(frame.presented - presented);
+                self.skipped += frame.index - index - 1;
+            }
+        }
+        self.last_present = Some((frame.index, frame.presented));
+    }
+
+    /// Present intervals bucketed by vblank: `[0]` is under one vblank, `[1]` is one, ..., the last is 4 or more.
+    pub fn pacing_histogram(&self) -> Histogram {
+        let vblank = self.tick_frequency() / 60;
+        let mut histogram = Histogram::new(vblank, 5);
+        for &interval in self.present_intervals.iter() {
+            // Round to the nearest vblank so jitter around 16.6ms lands in the right bucket.
+            histogram.add(interval + vblank / 2);
+        }
+        histogram
+    }
+
+    /// Frame latency in 4ms buckets up to 64ms.
+    pub fn latency_histogram(&self) -> Histogram {
+        let mut histogram = Histogram::new(self.tick_frequency() / 250, 17);
+        for &latency in self.frame_latency.iter() {
+            histogram.add(latency);
+        }
+        histogram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_use_nearest_rank() {
+        let values: Vec<i64> = (1..=100).collect();
+        let summary = Summary::of(&values).unwrap();
+        assert_eq!(summary.p50, 50);
+        assert_eq!(summary.p95, 95);
+        assert_eq!(summary.p99, 99);
+        assert_eq!(summary.min, 1);
+        assert_eq!(summary.max, 100);
+        assert_eq!(summary.mean, 50.5);
+    }
+
+    #[test]
+    fn pacing_histogram_buckets_by_vblank() {
+        let mut analysis = Analysis::new();
+        analysis.present_intervals = vec![VBLANK_TICKS, VBLANK_TICKS + 10, 2 * VBLANK_TICKS, 9 * VBLANK_TICKS];
+        assert_eq!(analysis.pacing_histogram().buckets, vec![0, 2, 1, 0, 1]);
+    }
+
+    #[test]
+    fn sums_drop_counts_across_connections() {
+        let mut analysis = Analysis::new();
+        let handshake = Record::Handshake(Handshake {
+            protocol: 4,
+            tick_frequency: TICK_FREQUENCY as u64,
+            build: "13.0.3".to_owned(),
+            plugin_version: "0.1.2".to_owned(),
+        });
+        analysis.add(&handshake);
+        analysis.add(&Record::Dropped(3));
+        analysis.add(&Record::Dropped(10));
+        analysis.add(&handshake);
+        analysis.add(&Record::Dropped(2));
+        assert_eq!(analysis.dropped_events, 12);
+    }
+
+    fn handshake(tick_frequency: u64) -> Record {
+        Record::Handshake(Handshake {
+            protocol: 4,
+            tick_frequency,
+            build: "13.0.3".to_owned(),
+            plugin_version: "0.1.2".to_owned(),
+        })
+    }
+
+    fn frame(index: u64, presented: i64) -> Record {
+        Record::Frame(Frame {
+            index,
+            start: presented - 1000,
+            submitted_at: presented - 500,
+            presented,
+            dangled: false,
+            lanes: Vec::new(),
+            spans: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn handshake_starts_intervals_over() {
+        let mut analysis = Analysis::new();
+        analysis.add(&handshake(TICK_FREQUENCY as u64));
+        analysis.add(&frame(10, VBLANK_TICKS));
+        analysis.add(&Record::VBlank(VBLANK_TICKS));
+        analysis.add(&frame(11, 2 * VBLANK_TICKS));
+        analysis.add(&Record::VBlank(2 * VBLANK_TICKS));
+        // Reconnected: the new connection's first frame and vblank don't pair with the old ones.
+        analysis.add(&handshake(TICK_FREQUENCY as u64));
+        analysis.add(&frame(500, 900 * VBLANK_TICKS));
+        analysis.add(&Record::VBlank(900 * VBLANK_TICKS));
+        assert_eq!(analysis.present_intervals, vec![VBLANK_TICKS]);
+        assert_eq!(analysis.vblank_intervals, vec![VBLANK_TICKS]);
+        assert_eq!(analysis.skipped, 0);
+    }
+
+    #[test]
+    fn converts_with_the_handshake_tick_frequency() {
+        let mut analysis = Analysis::new();
+        assert_eq!(analysis.tick_frequency(), TICK_FREQUENCY);
+        assert_eq!(analysis.millis(19_200.0), 1.0);
+
+        analysis.add(&handshake(1_000_000));
+        assert_eq!(analysis.tick_frequency(), 1_000_000);
+        assert_eq!(analysis.millis(1_000.0), 1.0);
+        analysis.present_intervals = vec![1_000_000 / 60, 2 * 1_000_000 / 60];
+        assert_eq!(analysis.pacing_histogram().buckets, vec![0, 1, 1, 0, 0]);
+        assert_eq!(analysis.latency_histogram().bucket_width, 4_000);
+
+        analysis.add(&handshake(0));
+        assert_eq!(analysis.tick_frequency(), TICK_FREQUENCY);
+    }
+
+    #[test]
+    fn converts_ticks_without_overflowing() {
+        assert_eq!(ticks_to_nanos(TICK_FREQUENCY, TICK_FREQUENCY), 1_000_000_000);
+        assert_eq!(ticks_to_nanos(12, TICK_FREQUENCY), 625);
+        assert_eq!(ticks_to_nanos(i64::MAX / 2, TICK_FREQUENCY), ((i64::MAX / 2) as i128 * 625 / 12) as i64);
+        assert_eq!(ticks_to_millis(9_600.0, TICK_FREQUENCY), 0.5);
+    }
+}
@@ -0,0 +1,428 @@
+/*! Chrome Trace Event export
+ *
+ * Writes the JSON object format (`{"traceEvents": [...]}`), which both `chrome://tracing` and ui.perfetto.dev open.
+ * Frames and vblanks get their own tracks, and every thread that recorded spans gets one named after it. Streams from
+ * before per-thread lanes fall back to one track per group of spans. Timestamps are `OsTick`s converted to
+ * nanoseconds at the handshake's tick frequency, written as fractional microseconds like the format expects.
+ */
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::stats::{ticks_to_nanos, TICK_FREQUENCY};
+use crate::wire::{Frame, Lane, Record};
+
+const PID: u32 = 1;
+
+/// A timeline row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Track {
+    Frames,
+    VBlank,
+    Scene,
+    Render,
+    TaskWorkers,
+    Ui,
+    Other,
+    /// Index into the threads seen so far.
+    Thread(u16),
+}
+
+impl Track {
+    /// Every track except per-thread ones.
+    pub const ALL: [Track; 7] = [
+        Track::Frames,
+        Track::VBlank,
+        Track::Scene,
+        Track::Render,
+        Track::TaskWorkers,
+        Track::Ui,
+        Track::Other,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Frames => "Frames",
+            Self::VBlank => "VBlank",
+            Self::Scene => "Scene",
+            Self::Render => "Render",
+            Self::TaskWorkers => "Task Workers",
+            Self::Ui => "UI",
+            Self::Other => "Other",
+            Self::Thread(_) => "Thread",
+        }
+    }
+
+    fn tid(self) -> u32 {
+        match self {
+            Self::Frames => 1,
+            Self::VBlank => 2,
+            Self::Scene => 3,
+            Self::Render => 4,
+            Self::TaskWorkers => 5,
+            Self::Ui => 6,
+            Self::Other => 7,
+            Self::Thread(index) => 16 + index as u32,
+        }
+    }
+
+    /// The track a span name is drawn on.
+    pub fn for_span(name: &str) -> Self {
+        match name {
+            "RunSceneManager" | "MutexLock" | "Looping" => Self::Scene,
+            "InitRenderpasses" | "CommandBufferReset" => Self::Render,
+            "UiUpdate" => Self::Ui,
+            name if name.contains("TaskWorker") => Self::TaskWorkers,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Writes `s` as a JSON string literal.
+fn write_json_str(out: &mut impl Write, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+/// Microseconds with nanosecond precision, e.g. `1234.567`.
+fn write_us(out: &mut impl Write, ticks: i64, tick_frequency: i64) -> io::Result<()> {
+    let nanos = ticks_to_nanos(ticks, tick_frequency);
+    let sign = if nanos < 0 { "-" } else { "" };
+    let nanos = nanos.unsigned_abs();
+    write!(out, "{}{}.{:03}", sign, nanos / 1000, nanos % 1000)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Event {
+    Complete {
+        track: Track,
+        name: String,
+        start: i64,
+        end: i64,
+        args: Vec<(&'static str, String)>,
+    },
+    Instant {
+        track: Track,
+        name: &'static str,
+        tick: i64,
+    },
+}
+
+impl Event {
+    fn write(&self, out: &mut impl Write, tick_frequency: i64) -> io::Result<()> {
+        match self {
+            Self::Complete {
+                track,
+                name,
+                start,
+                end,
+                args,
+            } => {
+                out.write_all(b"{\"ph\":\"X\",\"name\":")?;
+                write_json_str(out, name)?;
+                write!(out, ",\"pid\":{},\"tid\":{},\"ts\":", PID, track.tid())?;
+                write_us(out, *start, tick_frequency)?;
+                out.write_all(b",\"dur\":")?;
+                write_us(out, (end - start).max(0), tick_frequency)?;
+                if !args.is_empty() {
+                    out.write_all(b",\"args\":{")?;
+                    for (i, (key, value)) in args.iter().enumerate() {
+                        if i != 0 {
+                            out.write_all(b",")?;
+                        }
+                        write_json_str(out, key)?;
+                        out.write_all(b":")?;
+                        write_json_str(out, value)?;
+                    }
+                    out.write_all(b"}")?;
+                }
+                out.write_all(b"}")
+            }
+            Self::Instant { track, name, tick } => {
+                out.write_all(b"{\"ph\":\"i\",\"s\":\"t\",\"name\":")?;
+                write_json_str(out, name)?;
+                write!(out, ",\"pid\":{},\"tid\":{},\"ts\":", PID, track.tid())?;
+                write_us(out, *tick, tick_frequency)?;
+                out.write_all(b"}")
+            }
+        }
+    }
+}
+
+/// Collects decoded records and writes them out as one trace.
+#[derive(Debug, Default)]
+pub struct ChromeTrace {
+    /// Game build from the handshake, shown in the process name.
+    build: Option<String>,
+    /// Ticks per second from the handshake.
+    tick_frequency: Option<i64>,
+    events: Vec<Event>,
+    /// Spans seen per track, so empty tracks can be left out of the metadata.
+    used: BTreeMap<Track, usize>,
+    /// Threads in the order they first showed up, for `Track::Thread`.
+    threads: Vec<Lane>,
+}
+
+impl ChromeTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, record: &Record) {
+        match record {
+            Record::Handshake(handshake) => {
+                self.build = Some(handshake.build.clone());
+                self.tick_frequency = Some(handshake.tick_frequency.min(i64::MAX as u64) as i64).filter(|&f| f > 0);
+            }
+            Record::Frame(frame) => self.add_frame(frame),
+            Record::VBlank(tick) => self.push(Event::Instant {
+                track: Track::VBlank,
+                name: "VBlank",
+                tick: *tick,
+            }),
+            Record::Dropped(_) | Record::Latency(_) => {}
+        }
+    }
+
+    fn add_frame(&mut self, frame: &Frame) {
+        let name = if frame.dangled {
+            format!("Frame {} (dangled)", frame.index)
+        } else {
+            format!("Frame {}", frame.index)
+        };
+        self.push(Event::Complete {
+            track: Track::Frames,
+            name,
+            start: frame.start,
+            end: frame.presented,
+            args: vec![
+                ("index", frame.index.to_string()),
+                ("submitted_at_us", format!("{:.3}", ticks_to_nanos(frame.submitted_at - frame.start, self.tick_frequency()) as f64 / 1000.0)),
+                ("dangled", frame.dangled.to_string()),
+            ],
+        });
+        let lanes: Vec<Option<Track>> = frame.lanes.iter().map(|lane| self.thread_track(lane)).collect();
+        // Nested spans stay on their outermost span's track so the viewer draws them stacked under it.
+        let mut tracks: Vec<Track> = Vec::with_capacity(frame.spans.len());
+        for span in frame.spans.iter() {
+            let track = span
+                .parent
+                .and_then(|parent| tracks.get(parent as usize).copied())
+                .or_else(|| lanes.get(span.lane as usize).copied().flatten())
+                .unwrap_or_else(|| Track::for_span(&span.name));
+            tracks.push(track);
+            let mut args = vec![("frame", frame.index.to_string())];
+            if span.depth != 0 {
+                args.push(("depth", span.depth.to_string()));
+            }
+            if span.unclosed {
+                args.push(("unclosed", "true".to_owned()));
+            }
+            self.push(Event::Complete {
+                track,
+                name: span.name.clone(),
+                start: span.start,
+                end: span.end,
+                args,
+            });
+        }
+    }
+
+    /// The track for a thread's lane, or `None` for lanes from streams that didn't say which thread.
+    fn thread_track(&mut self, lane: &Lane) -> Option<Track> {
+        if lane.is_unknown() {
+            return None;
+        }
+        let index = match self.threads.iter().position(|known| known == lane) {
+            Some(index) => index,
+            None => {
+                self.threads.push(lane.clone());
+                self.threads.len() - 1
+            }
+        };
+        Some(Track::Thread(index.min(u16::MAX as usize) as u16))
+    }
+
+    fn track_name(&self, track: Track) -> String {
+        let Track::Thread(index) = track else {
+            return track.name().to_owned();
+        };
+        match self.threads.get(index as usize) {
+            Some(lane) if lane.name.is_empty() => format!("Thread {:#x}", lane.thread_id),
+            Some(lane) => format!("{} ({:#x})", lane.name, lane.thread_id),
+            None => track.name().to_owned(),
+        }
+    }
+
+    fn tick_frequency(&self) -> i64 {
+        self.tick_frequency.unwrap_or(TICK_FREQUENCY)
+    }
+
+    fn push(&mut self, event: Event) {
+        let track = match &event {
+            Event::Complete { track, .. } | Event::Instant { track, .. } => *track,
+        };
+        *self.used.entry(track).or_default() += 1;
+        self.events.push(event);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn write_to(&self, mut out: impl Write) -> io::Result<()> {
+        out.write_all(b"{\"displayTimeUnit\":\"ns\",\"traceEvents\":[\n")?;
+        let process = match &self.build {
+            Some(build) => format!("Smash Ultimate {}", build),
+            None => "Smash Ultimate".to_owned(),
+        };
+        write!(
+            out,
+            "{{\"ph\":\"M\",\"name\":\"process_name\",\"pid\":{},\"args\":{{\"name\":",
+            PID
+        )?;
+        write_json_str(&mut out, &process)?;
+        out.write_all(b"}}")?;
+        for &track in self.used.keys() {
+            out.write_all(b",\n")?;
+            write!(
+                out,
+                "{{\"ph\":\"M\",\"name\":\"thread_name\",\"pid\":{},\"tid\":{},\"args\":{{\"name\":",
+                PID,
+                track.tid()
+            )?;
+            write_json_str(&mut out, &self.track_name(track))?;
+            out.write_all(b"}},\n")?;
+            write!(
+                out,
+                "{{\"ph\":\"M\",\"name\":\"thread_sort_index\",\"pid\":{},\"tid\":{},\"args\":{{\"sort_index\":{}}}}}",
+                PID,
+                track.tid(),
+                track.tid()
+            )?;
+        }
+        for event in self.events.iter() {
+            out.write_all(b",\n")?;
+            event.write(&mut out, self.tick_frequency())?;
+        }
+        out.write_all(b"\n]}\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::Span;
+
+    #[test]
+    fn writes_spans_on_their_subsystem_tracks() {
+        let mut trace = ChromeTrace::new();
+        trace.add(&Record::Frame(Frame {
+            index: 3,
+            start: 0,
+            submitted_at: 192,
+            presented: 19_200,
+            dangled: false,
+            lanes: vec![Lane::default()],
+            spans: vec![
+                Span {
+                    name: "RunSceneManager".to_owned(),
+                    start: 12,
+                    end: 24,
+                    lane: 0,
+                    parent: None,
+                    depth: 0,
+                    unclosed: false,
+                },
+                Span {
+                    name: "Quote\"d".to_owned(),
+                    start: 24,
+                    end: 36,
+                    lane: 0,
+                    parent: None,
+                    depth: 0,
+                    unclosed: false,
+                },
+                Span {
+                    name: "SceneUpdate".to_owned(),
+                    start: 14,
+                    end: 20,
+                    lane: 0,
+                    parent: Some(0),
+                    depth: 1,
+                    unclosed: false,
+                },
+            ],
+        }));
+        trace.add(&Record::VBlank(19_200));
+
+        let mut out = Vec::new();
+        trace.write_to(&mut out).unwrap();
+        let json = String::from_utf8(out).unwrap();
+
+        // 12 ticks is 625ns.
+        assert!(json.contains(
+            "{\"ph\":\"X\",\"name\":\"RunSceneManager\",\"pid\":1,\"tid\":3,\"ts\":0.625,\"dur\":0.625,\"args\":{\"frame\":\"3\"}}"
+        ));
+        assert!(json.contains("\"name\":\"Quote\\\"d\",\"pid\":1,\"tid\":7"));
+        // SceneUpdate alone would go on Other; nested, it stays under RunSceneManager.
+        assert!(json.contains("\"name\":\"SceneUpdate\",\"pid\":1,\"tid\":3"));
+        assert!(json.contains("\"depth\":\"1\""));
+        assert!(json.contains("{\"ph\":\"i\",\"s\":\"t\",\"name\":\"VBlank\",\"pid\":1,\"tid\":2,\"ts\":1000.000}"));
+        assert!(json.contains("\"args\":{\"name\":\"Scene\"}"));
+        assert!(!json.contains("\"args\":{\"name\":\"UI\"}"));
+    }
+
+    #[test]
+    fn writes_a_track_per_thread() {
+        let span = |name: &str, lane| Span {
+            name: name.to_owned(),
+            start: 12,
+            end: 24,
+            lane,
+            parent: None,
+            depth: 0,
+            unclosed: false,
+        };
+        let mut trace = ChromeTrace::new();
+        trace.add(&Record::Frame(Frame {
+            index: 1,
+            start: 0,
+            submitted_at: 192,
+            presented: 19_200,
+            dangled: false,
+            lanes: vec![
+                Lane {
+                    thread_id: 0x51,
+                    name: "MainThread".to_owned(),
+                },
+                Lane {
+                    thread_id: 0x77,
+                    name: String::new(),
+                },
+            ],
+            spans: vec![span("RunSceneManager", 0), span("RunSceneManager", 1)],
+        }));
+
+        let mut out = Vec::new();
+        trace.write_to(&mut out).unwrap();
+        let json = String::from_utf8(out).unwrap();
+
+        assert!(json.contains("\"name\":\"RunSceneManager\",\"pid\":1,\"tid\":16"));
+        assert!(json.contains("\"name\":\"RunSceneManager\",\"pid\":1,\"tid\":17"));
+        assert!(json.contains("\"args\":{\"name\":\"MainThread (0x51)\"}"));
+        assert!(json.contains("\"args\":{\"name\":\"Thread 0x77\"}"));
+        // Thread tracks replace the subsystem ones.
+        assert!(!json.contains("\"args\":{\"name\":\"Scene\"}"));
+    }
+}
@@ -0,0 +1,517 @@
+/*! Profiler stream formats
+ *
+ * `v2` is what the plugin writes now; see its module docs. Version 1 (below) is still decoded so old captures keep
+ * working, and `Decoder` tells the two apart from the first byte: v1 starts with a record tag, v2 with a sync word.
+ *
+ * Version 1 is little-endian with no framing. Each record starts with a one byte tag:
+ *
+ * - `0` frame: `index: u64, start: i64, submitted_at: i64, presented: i64, dangled: u8, span_count: u64`, then
+ *   `span_count` spans of `start: i64, end: i64, name_len: u64, name: [u8; name_len]`
+ * - `1` vblank: `tick: i64`
+ *
+ * Lengths were written as `usize` on the console, which is 8 bytes there; they are `u64` here so host tools read the
+ * same bytes.
+ */
+pub mod v2;
+
+pub use v2::Handshake;
+
+use crate::stats::Summary;
+
+pub const TAG_FRAME: u8 = 0;
+pub const TAG_VBLANK: u8 = 1;
+
+/// Names longer than this are treated as a corrupt stream rather than allocated.
+pub const MAX_NAME_LEN: u64 = 4096;
+/// Frames with more spans than this are treated as a corrupt stream.
+pub const MAX_SPANS: u64 = 65536;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub name: String,
+    pub start: i64,
+    pub end: i64,
+    /// Index into `Frame::lanes` of the thread that recorded the span.
+    pub lane: u16,
+    /// Index of the enclosing span in the same frame (always on the same lane). Always `None` in v1 streams.
+    pub parent: Option<u32>,
+    /// Nesting depth, 0 for top-level spans.
+    pub depth: u16,
+    /// The span was still open when its parent ended or the frame was submitted.
+    pub unclosed: bool,
+}
+
+/// A span to be written, borrowed from the writer's own storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanRef<'a> {
+    pub name: &'a str,
+    pub start: i64,
+    pub end: i64,
+    /// Index of the enclosing span within the same lane.
+    pub parent: Option<u32>,
+    pub depth: u16,
+    pub unclosed: bool,
+}
+
+impl Span {
+    pub fn duration(&self) -> i64 {
+        self.end - self.start
+    }
+}
+
+/// A thread that recorded spans during a frame. v1 streams have a single lane with no thread.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lane {
+    /// `nn::os::GetThreadId`, or 0 when unknown.
+    pub thread_id: u64,
+    /// The thread's `nn::os` name, or empty when unknown.
+    pub name: String,
+}
+
+impl Lane {
+    pub fn is_unknown(&self) -> bool {
+        self.thread_id == 0 && self.name.is_empty()
+    }
+}
+
+/// One thread's spans to be written, in start order.
+#[derive(Debug, Clone, Copy)]
+pub struct LaneRef<'a> {
+    pub thread_id: u64,
+    pub name: &'a str,
+    pub spans: &'a [SpanRef<'a>],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub index: u64,
+    pub start: i64,
+    pub submitted_at: i64,
+    pub presented: i64,
+    /// The frame was never presented; `presented` is when a later frame was.
+    pub dangled: bool,
+    pub lanes: Vec<Lane>,
+    /// Every lane's spans, lane after lane.
+    pub spans: Vec<Span>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    /// Start of a v2 connection.
+    Handshake(Handshake),
+    Frame(Frame),
+    VBlank(i64),
+    /// Events the plugin has dropped since the handshake because its queue was full. Only v2 streams report this.
+    Dropped(u64),
+    /// Present latency over the plugin's recent frame history. Only v2 streams report this.
+    Latency(LatencySummary),
+}
+
+/// Rolling present latency aggregates the plugin keeps over its recent frame history.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencySummary {
+    pub enqueue_to_present: Summary,
+    pub present_to_vblank: Summary,
+    /// Vblanks between consecutive presented frames that showed nothing new.
+    pub missed_vblanks: u32,
+    /// Frame numbers between consecutive presented frames that were never presented.
+    pub frame_gaps: u32,
+    /// The newest presented frame.
+    pub last_frame: u64,
+    pub last_vblank: i64,
+}
+
+/// Frame fields that come before the spans.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameHeader {
+    pub index: u64,
+    pub start: i64,
+    pub submitted_at: i64,
+    pub presented: i64,
+    pub dangled: bool,
+}
+
+pub fn serialize_span(name: &str, start: i64, end: i64, buffer: &mut Vec<u8>) {
+    buffer.extend_from_slice(&start.to_le_bytes());
+    buffer.extend_from_slice(&end.to_le_bytes());
+    buffer.extend_from_slice(&(name.len() as u64).to_le_bytes());
+    buffer.extend_from_slice(name.as_bytes());
+}
+
+/// Writes a frame record. `spans` yields `(name, start, end)`.
+pub fn serialize_frame<'a, I>(header: FrameHeader, spans: I, buffer: &mut Vec<u8>)
+where
+    I: ExactSizeIterator<Item = (&'a str, i64, i64)>,
+{
+    buffer.push(TAG_FRAME);
+    buffer.extend_from_slice(&header.index.to_le_bytes());
+    buffer.extend_from_slice(&header.start.to_le_bytes());
+    buffer.extend_from_slice(&header.submitted_at.to_le_bytes());
+    buffer.extend_from_slice(&header.presented.to_le_bytes());
+    buffer.push(header.dangled as u8);
+    buffer.extend_from_slice(&(spans.len() as u64).to_le_bytes());
+    for (name, start, end) in spans {
+        serialize_span(name, start, end, buffer);
+    }
+}
+
+pub fn serialize_vblank(tick: i64, buffer: &mut Vec<u8>) {
+    buffer.push(TAG_VBLANK);
+    buffer.extend_from_slice(&tick.to_le_bytes());
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownTag { tag: u8, offset: u64 },
+    NameTooLong { len: u64, offset: u64 },
+    TooManySpans { count: u64, offset: u64 },
+    InvalidName { offset: u64 },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownTag { tag, offset } => write!(f, "unknown record tag {} at byte {}", tag, offset),
+            Self::NameTooLong { len, offset } => {
+                write!(f, "span name of {} bytes at byte {} (corrupt stream?)", len, offset)
+            }
+            Self::TooManySpans { count, offset } => {
+                write!(f, "frame with {} spans at byte {} (corrupt stream?)", count, offset)
+            }
+            Self::InvalidName { offset } => write!(f, "span name at byte {} is not UTF-8", offset),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Reads fixed-width fields off a slice, returning `None` when it runs out.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let out = self.bytes.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(out)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8).map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Option<i64> {
+        self.take(8).map(|b| i64::from_le_bytes(b.try_into().unwrap()))
+    }
+}
+
+#[derive(Debug, Default)]
+enum Version {
+    #[default]
+    Unknown,
+    V1,
+    V2(v2::State),
+}
+
+/** Incremental decoder for either version
+ *
+ * Feed it whatever `read` returned with `push`, then drain records with `next_record` until it returns `Ok(None)`.
+ * Partial records stay buffered until the rest arrives.
+ *
+ * v1 has no framing, so a corrupt v1 stream is an error. v2 skips bad bytes and carries on from the next packet;
+ * `skipped` says how many bytes that cost.
+ */
+#[derive(Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    /// Stream offset of `buffer[0]`, for error messages.
+    offset: u64,
+    version: Version,
+    skipped: u64,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Bytes received that don't form a whole record yet.
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// The protocol version, once the first byte has arrived: 1, or what the last v2 handshake announced.
+    pub fn version(&self) -> Option<u16> {
+        match &self.version {
+            Version::Unknown => None,
+            Version::V1 => Some(1),
+            Version::V2(state) => Some(state.protocol()),
+        }
+    }
+
+    /// Bytes thrown away while resyncing a v2 stream.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    fn consume(&mut self, used: usize) {
+        self.buffer.drain(..used);
+        self.offset += used as u64;
+    }
+
+    pub fn next_record(&mut self) -> Result<Option<Record>, DecodeError> {
+        loop {
+            match &mut self.version {
+                Version::Unknown => match self.buffer.first() {
+                    None => return Ok(None),
+                    Some(&byte) if byte == v2::SYNC[0] => self.version = Version::V2(v2::State::default()),
+                    Some(_) => self.version = Version::V1,
+                },
+                Version::V1 => return self.next_v1_record(),
+                Version::V2(state) => match state.step(&self.buffer) {
+                    v2::Step::Incomplete => return Ok(None),
+                    v2::Step::Packet(used, record) => {
+                        self.consume(used);
+                        if record.is_some() {
+                            return Ok(record);
+                        }
+                    }
+                    v2::Step::Skip(count) => {
+                        self.consume(count);
+                        self.skipped += count as u64;
+                    }
+                },
+            }
+        }
+    }
+
+    fn next_v1_record(&mut self) -> Result<Option<Record>, DecodeError> {
+        let mut reader = Reader {
+            bytes: &self.buffer,
+            pos: 0,
+        };
+        let record = match decode_record(&mut reader, self.offset) {
+            Ok(Some(record)) => record,
+            Ok(None) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let used = reader.pos;
+        self.consume(used);
+        Ok(Some(record))
+    }
+}
+
+fn decode_record(reader: &mut Reader, offset: u64) -> Result<Option<Record>, DecodeError> {
+    let Some(tag) = reader.u8() else {
+        return Ok(None);
+    };
+    match tag {
+        TAG_VBLANK => Ok(reader.i64().map(Record::VBlank)),
+        TAG_FRAME => decode_frame(reader, offset).map(|frame| frame.map(Record::Frame)),
+        tag => Err(DecodeError::UnknownTag { tag, offset }),
+    }
+}
+
+fn decode_frame(reader: &mut Reader, offset: u64) -> Result<Option<Frame>, DecodeError> {
+    macro_rules! field {
+        ($read:expr) => {
+            match $read {
+                Some(value) => value,
+                None => return Ok(None),
+            }
+        };
+    }
+
+    let index = field!(reader.u64());
+    let start = field!(reader.i64());
+    let submitted_at = field!(reader.i64());
+    let presented = field!(reader.i64());
+    let dangled = field!(reader.u8()) != 0;
+    let count = field!(reader.u64());
+    if count > MAX_SPANS {
+        return Err(DecodeError::TooManySpans { count, offset });
+    }
+
+    let mut spans = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let start = field!(reader.i64());
+        let end = field!(reader.i64());
+        let len = field!(reader.u64());
+        if len > MAX_NAME_LEN {
+            return Err(DecodeError::NameTooLong {
+                len,
+                offset: offset + reader.pos as u64,
+            });
+        }
+        let name_offset = offset + reader.pos as u64;
+        let name = field!(reader.take(len as usize));
+        let name = std::str::from_utf8(name)
+            .map_err(|_| DecodeError::InvalidName { offset: name_offset })?
+            .to_owned();
+        spans.push(Span {
+            name,
+            start,
+            end,
+            lane: 0,
+            parent: None,
+            depth: 0,
+            unclosed: false,
+        });
+    }
+
+    Ok(Some(Frame {
+        index,
+        start,
+        submitted_at,
+        presented,
+        dangled,
+        lanes: vec![Lane::default()],
+        spans,
+    }))
+}
+
+/// Decodes a complete capture. Trailing bytes that don't form a whole record are ignored.
+pub fn decode_all(bytes: &[u8]) -> Result<Vec<Record>, DecodeError> {
+    let mut decoder = Decoder::new();
+    decoder.push(bytes);
+    let mut records = Vec::new();
+    while let Some(record) = decoder.next_record()? {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: FrameHeader = FrameHeader {
+        index: 7,
+        start: 0x100,
+        submitted_at: 0x200,
+        presented: 0x300,
+        dangled: false,
+    };
+
+    #[rustfmt::skip]
+    const GOLDEN_FRAME: &[u8] = &[
+        0x00,
+        0x07, 0, 0, 0, 0, 0, 0, 0,
+        0x00, 0x01, 0, 0, 0, 0, 0, 0,
+        0x00, 0x02, 0, 0, 0, 0, 0, 0,
+        0x00, 0x03, 0, 0, 0, 0, 0, 0,
+        0x00,
+        0x02, 0, 0, 0, 0, 0, 0, 0,
+        // span 0: 0x110..0x150 "UiUpdate"
+        0x10, 0x01, 0, 0, 0, 0, 0, 0,
+        0x50, 0x01, 0, 0, 0, 0, 0, 0,
+        0x08, 0, 0, 0, 0, 0, 0, 0,
+        b'U', b'i', b'U', b'p', b'd', b'a', b't', b'e',
+        // span 1: 0x150..0x1f0 "RunSceneManager"
+        0x50, 0x01, 0, 0, 0, 0, 0, 0,
+        0xf0, 0x01, 0, 0, 0, 0, 0, 0,
+        0x0f, 0, 0, 0, 0, 0, 0, 0,
+        b'R', b'u', b'n', b'S', b'c', b'e', b'n', b'e', b'M', b'a', b'n', b'a', b'g', b'e', b'r',
+    ];
+
+    const GOLDEN_VBLANK: &[u8] = &[0x01, 0x40, 0x42, 0x0f, 0, 0, 0, 0, 0];
+
+    fn golden_spans() -> [(&'static str, i64, i64); 2] {
+        [("UiUpdate", 0x110, 0x150), ("RunSceneManager", 0x150, 0x1f0)]
+    }
+
+    fn golden_frame() -> Frame {
+        Frame {
+            index: 7,
+            start: 0x100,
+            submitted_at: 0x200,
+            presented: 0x300,
+            dangled: false,
+            lanes: vec![Lane::default()],
+            spans: golden_spans()
+                .iter()
+                .map(|&(name, start, end)| Span {
+                    name: name.to_owned(),
+                    start,
+                    end,
+                    lane: 0,
+                    parent: None,
+                    depth: 0,
+                    unclosed: false,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn serialize_frame_matches_golden() {
+        let mut buffer = Vec::new();
+        serialize_frame(HEADER, golden_spans().into_iter(), &mut buffer);
+        assert_eq!(buffer, GOLDEN_FRAME);
+    }
+
+    #[test]
+    fn serialize_vblank_matches_golden() {
+        let mut buffer = Vec::new();
+        serialize_vblank(1_000_000, &mut buffer);
+        assert_eq!(buffer, GOLDEN_VBLANK);
+    }
+
+    #[test]
+    fn decodes_golden_stream() {
+        let mut stream = GOLDEN_FRAME.to_vec();
+        stream.extend_from_slice(GOLDEN_VBLANK);
+        let records = decode_all(&stream).unwrap();
+        assert_eq!(
+            records,
+            vec![Record::Frame(golden_frame()), Record::VBlank(1_000_000)]
+        );
+    }
+
+    #[test]
+    fn decodes_dangling_frame() {
+        let mut buffer = Vec::new();
+        let header = FrameHeader {
+            dangled: true,
+            ..HEADER
+        };
+        serialize_frame(header, std::iter::empty(), &mut buffer);
+        let records = decode_all(&buffer).unwrap();
+        let [Record::Frame(frame)] = records.as_slice() else {
+            panic!("expected one frame, got {:?}", records);
+        };
+        assert!(frame.dangled);
+        assert!(frame.spans.is_empty());
+    }
+
+    #[test]
+    fn decoder_waits_for_partial_records() {
+        let mut decoder = Decoder::new();
+        for byte in GOLDEN_FRAME {
+            assert_eq!(decoder.next_record(), Ok(None));
+            decoder.push(std::slice::from_ref(byte));
+        }
+        assert_eq!(decoder.next_record(), Ok(Some(Record::Frame(golden_frame()))));
+        assert_eq!(decoder.pending(), 0);
+    }
+
+    #[test]
+    fn rejects_unknown_tag() {
+        let mut stream = GOLDEN_VBLANK.to_vec();
+        stream.push(9);
+        assert_eq!(
+            decode_all(&stream),
+            Err(DecodeError::UnknownTag { tag: 9, offset: 9 })
+        );
+    }
+}
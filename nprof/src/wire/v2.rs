@@ -0,0 +1,719 @@
+/*! Version 2 of the profiler stream
+ *
+ * Every packet is framed as
+ *
+ * `sync: b"NPF2", kind: u8, len: u32, payload: [u8; len], checksum: u32`
+ *
+ * where `checksum` is FNV-1a over `kind` and `payload`. A reader that loses its place (partial capture, corrupt
+ * bytes) scans forward to the next sync word whose length and checksum line up.
+ *
+ * Payloads, all little-endian with fixed widths:
+ *
+ * - `Handshake`: `protocol: u16, tick_frequency: u64, build: str16, plugin_version: str16`
+ * - `Intern`: `id: u32, name: str16`, sent once per span name before the first frame that uses it
+ * - `Frame`: `index: u64, start: i64, submitted_at: i64, presented: i64, flags: u8, lane_count: u16`, then
+ *   `lane_count` lanes of `thread_id: u64, thread_name: u32, span_count: u32` each followed by `span_count` spans of
+ *   `start: i64, end: i64, name: u32, parent: u32, depth: u16, flags: u8`. `parent` indexes the same lane.
+ * - `VBlank`: `tick: i64`
+ * - `Dropped`: `events: u64`, the running count of events the plugin dropped since the handshake. Sent whenever it
+ *   changes; older readers skip it.
+ * - `Latency`: `enqueue_to_present: summary, present_to_vblank: summary, missed_vblanks: u32, frame_gaps: u32,
+ *   last_frame: u64, last_vblank: i64`, where `summary` is `count: u32, mean: f64, min: i64, p50: i64, p95: i64,
+ *   p99: i64, max: i64`. Sent periodically when latency statistics are on; older readers skip it.
+ *
+ * The handshake announces the protocol, 2 to match the `NPF2` sync.
+ *
+ * `str16` is `len: u16, bytes: [u8; len]` of UTF-8. Frame flag bit 0 is `dangled`, span flag bit 0 `unclosed`, and
+ * `parent` is `u32::MAX` for top-level spans. Thread names are interned like span names.
+ *
+ * The handshake is always the first packet of a connection and resets the intern table.
+ */
+use std::collections::HashMap;
+
+use super::{Frame, FrameHeader, Lane, LaneRef, LatencySummary, Record, Span};
+use crate::stats::Summary;
+
+pub const PROTOCOL_VERSION: u16 = 2;
+pub const SYNC: [u8; 4] = *b"NPF2";
+
+/// Payloads larger than this are treated as a false sync match, so `Encoder` refuses to write them.
+pub const MAX_PAYLOAD: u32 = 1 << 20;
+
+const KIND_HANDSHAKE: u8 = 0;
+const KIND_INTERN: u8 = 1;
+const KIND_FRAME: u8 = 2;
+const KIND_VBLANK: u8 = 3;
+const KIND_DROPPED: u8 = 4;
+const KIND_LATENCY: u8 = 5;
+
+const FLAG_DANGLED: u8 = 1;
+const SPAN_FLAG_UNCLOSED: u8 = 1;
+const NO_PARENT: u32 = u32::MAX;
+
+/// sync + kind + len
+const HEADER_LEN: usize = 4 + 1 + 4;
+const CHECKSUM_LEN: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handshake {
+    pub protocol: u16,
+    /// System ticks per second.
+    pub tick_frequency: u64,
+    /// The game's display version, e.g. `13.0.3`.
+    pub build: String,
+    pub plugin_version: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The frame's spans don't fit in one packet; nothing was written for it.
+    FrameTooLarge { index: u64, len: usize },
+}
+
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FrameTooLarge { index, len } => write!(
+                f,
+                "frame {} is {} bytes, over the {} byte packet limit",
+                index, len, MAX_PAYLOAD
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+fn checksum(kind: u8, payload: &[u8]) -> u32 {
+    let mut hash = 0x811c9dc5u32;
+    for &byte in std::iter::once(&kind).chain(payload) {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+fn write_packet(kind: u8, payload: &[u8], buffer: &mut Vec<u8>) {
+    buffer.extend_from_slice(&SYNC);
+    buffer.push(kind);
+    buffer.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(payload);
+    buffer.extend_from_slice(&checksum(kind, payload).to_le_bytes());
+}
+
+fn write_summary(summary: &Summary, payload: &mut Vec<u8>) {
+    payload.extend_from_slice(&(summary.count.min(u32::MAX as usize) as u32).to_le_bytes());
+    payload.extend_from_slice(&summary.mean.to_le_bytes());
+    for value in [summary.min, summary.p50, summary.p95, summary.p99, summary.max] {
+        payload.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn write_str16(s: &str, payload: &mut Vec<u8>) {
+    let bytes = &s.as_bytes()[..s.len().min(u16::MAX as usize)];
+    payload.extend_from_slice(&(bytes.len() as u16).to_le_bytes());
+    payload.extend_from_slice(bytes);
+}
+
+/** Writes v2 packets, interning span names as it goes
+ *
+ * Call `handshake` at the start of every connection; it clears the intern table so the new reader gets every name.
+ */
+#[derive(Debug, Default)]
+pub struct Encoder {
+    names: HashMap<String, u32>,
+    payload: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn handshake(&mut self, handshake: &Handshake, buffer: &mut Vec<u8>) {
+        self.names.clear();
+        self.payload.clear();
+        self.payload.extend_from_slice(&handshake.protocol.to_le_bytes());
+        self.payload.extend_from_slice(&handshake.tick_frequency.to_le_bytes());
+        write_str16(&handshake.build, &mut self.payload);
+        write_str16(&handshake.plugin_version, &mut self.payload);
+        write_packet(KIND_HANDSHAKE, &self.payload, buffer);
+    }
+
+    fn intern(&mut self, name: &str, buffer: &mut Vec<u8>) -> u32 {
+        if let Some(&id) = self.names.get(name) {
+            return id;
+        }
+        let id = self.names.len() as u32;
+        self.names.insert(name.to_owned(), id);
+        let mut payload = Vec::with_capacity(6 + name.len());
+        payload.extend_from_slice(&id.to_le_bytes());
+        write_str16(name, &mut payload);
+        write_packet(KIND_INTERN, &payload, buffer);
+        id
+    }
+
+    /// Writes a frame, preceded by intern packets for any span or thread names not sent yet. A frame over
+    /// `MAX_PAYLOAD` would be skipped by every reader, so it isn't written; its intern packets still are.
+    pub fn frame(&mut self, header: FrameHeader, lanes: &[LaneRef], buffer: &mut Vec<u8>) -> Result<(), EncodeError> {
+        let mut payload = std::mem::take(&mut self.payload);
+        payload.clear();
+        payload.extend_from_slice(&header.index.to_le_bytes());
+        payload.extend_from_slice(&header.start.to_le_bytes());
+        payload.extend_from_slice(&header.submitted_at.to_le_bytes());
+        payload.extend_from_slice(&header.presented.to_le_bytes());
+        payload.push(if header.dangled { FLAG_DANGLED } else { 0 });
+        let lanes = &lanes[..lanes.len().min(u16::MAX as usize)];
+        payload.extend_from_slice(&(lanes.len() as u16).to_le_bytes());
+        for lane in lanes {
+            let thread_name = self.intern(lane.name, buffer);
+            payload.extend_from_slice(&lane.thread_id.to_le_bytes());
+            payload.extend_from_slice(&thread_name.to_le_bytes());
+            payload.extend_from_slice(&(lane.spans.len() as u32).to_le_bytes());
+            for span in lane.spans {
+                let id = self.intern(span.name, buffer);
+                payload.extend_from_slice(&span.start.to_le_bytes());
+                payload.extend_from_slice(&span.end.to_le_bytes());
+                payload.extend_from_slice(&id.to_le_bytes());
+                payload.extend_from_slice(&span.parent.unwrap_or(NO_PARENT).to_le_bytes());
+                payload.extend_from_slice(&span.depth.to_le_bytes());
+                payload.push(if span.unclosed { SPAN_FLAG_UNCLOSED } else { 0 });
+            }
+        }
+        let result = if payload.len() > MAX_PAYLOAD as usize {
+            Err(EncodeError::FrameTooLarge {
+                index: header.index,
+                len: payload.len(),
+            })
+        } else {
+            write_packet(KIND_FRAME, &payload, buffer);
+            Ok(())
+        };
+        self.payload = payload;
+        result
+    }
+
+    pub fn vblank(&mut self, tick: i64, buffer: &mut Vec<u8>) {
+        write_packet(KIND_VBLANK, &tick.to_le_bytes(), buffer);
+    }
+
+    pub fn dropped(&mut self, events: u64, buffer: &mut Vec<u8>) {
+        write_packet(KIND_DROPPED, &events.to_le_bytes(), buffer);
+    }
+
+    pub fn latency(&mut self, latency: &LatencySummary, buffer: &mut Vec<u8>) {
+        self.payload.clear();
+        write_summary(&latency.enqueue_to_present, &mut self.payload);
+        write_summary(&latency.present_to_vblank, &mut self.payload);
+        self.payload.extend_from_slice(&latency.missed_vblanks.to_le_bytes());
+        self.payload.extend_from_slice(&latency.frame_gaps.to_le_bytes());
+        self.payload.extend_from_slice(&latency.last_frame.to_le_bytes());
+        self.payload.extend_from_slice(&latency.last_vblank.to_le_bytes());
+        write_packet(KIND_LATENCY, &self.payload, buffer);
+    }
+}
+
+/// What `State::step` made of the front of the buffer.
+#[derive(Debug, PartialEq)]
+pub(super) enum Step {
+    /// Need more bytes.
+    Incomplete,
+    /// Consumed this many bytes, possibly producing a record.
+    Packet(usize, Option<Record>),
+    /// These bytes aren't a valid packet; drop them and look again.
+    Skip(usize),
+}
+
+/// Decoder state that outlives individual packets.
+#[derive(Debug)]
+pub(super) struct State {
+    /// From the last handshake. Streams joined mid-connection are assumed to be the newest version.
+    protocol: u16,
+    names: HashMap<u32, String>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            protocol: PROTOCOL_VERSION,
+            names: HashMap::new(),
+        }
+    }
+}
+
+struct Payload<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Payload<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let out = self.bytes.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(out)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8).map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Option<i64> {
+        self.take(8).map(|b| i64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Option<f64> {
+        self.u64().map(f64::from_bits)
+    }
+
+    fn summary(&mut self) -> Option<Summary> {
+        Some(Summary {
+            count: self.u32()? as usize,
+            mean: self.f64()?,
+            min: self.i64()?,
+            p50: self.i64()?,
+            p95: self.i64()?,
+            p99: self.i64()?,
+            max: self.i64()?,
+        })
+    }
+
+    fn str16(&mut self) -> Option<String> {
+        let len = self.u16()? as usize;
+        std::str::from_utf8(self.take(len)?).ok().map(str::to_owned)
+    }
+}
+
+impl State {
+    pub(super) fn protocol(&self) -> u16 {
+        self.protocol
+    }
+
+    /// A name we never saw (we joined mid-stream) still gets a stable label.
+    fn name(&self, id: u32) -> String {
+        self.names
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| format!("<name {}>", id))
+    }
+
+    /// Reads `count` spans of one lane, appending them to `spans` with parents rebased onto the whole frame.
+    fn spans(&self, payload: &mut Payload, count: u32, lane: u16, spans: &mut Vec<Span>) -> Option<()> {
+        let base = spans.len() as u32;
+        for _ in 0..count {
+            let start = payload.i64()?;
+            let end = payload.i64()?;
+            let id = payload.u32()?;
+            let parent = payload.u32()?;
+            let depth = payload.u16()?;
+            let flags = payload.u8()?;
+            spans.push(Span {
+                name: self.name(id),
+                start,
+                end,
+                lane,
+                parent: (parent != NO_PARENT).then(|| base.saturating_add(parent)),
+                depth,
+                unclosed: flags & SPAN_FLAG_UNCLOSED != 0,
+            });
+        }
+        Some(())
+    }
+}
+
+/// Distance to the next possible sync word, or everything but the last three bytes (which could start one).
+fn next_sync(buffer: &[u8]) -> usize {
+    buffer
+        .windows(SYNC.len())
+        .skip(1)
+        .position(|window| window == SYNC)
+        .map(|pos| pos + 1)
+        .unwrap_or(buffer.len().saturating_sub(SYNC.len() - 1).max(1))
+}
+
+impl State {
+    pub(super) fn step(&mut self, buffer: &[u8]) -> Step {
+        if buffer.len() < SYNC.len() {
+            return if SYNC.starts_with(buffer) {
+                Step::Incomplete
+            } else {
+                Step::Skip(1)
+            };
+        }
+        if buffer[..SYNC.len()] != SYNC {
+            return Step::Skip(next_sync(buffer));
+        }
+        if buffer.len() < HEADER_LEN {
+            return Step::Incomplete;
+        }
+        let kind = buffer[4];
+        let len = u32::from_le_bytes(buffer[5..9].try_into().unwrap());
+        if len > MAX_PAYLOAD {
+            return Step::Skip(next_sync(buffer));
+        }
+        let total = HEADER_LEN + len as usize + CHECKSUM_LEN;
+        if buffer.len() < total {
+            return Step::Incomplete;
+        }
+        let payload = &buffer[HEADER_LEN..HEADER_LEN + len as usize];
+        let expected = u32::from_le_bytes(buffer[total - CHECKSUM_LEN..total].try_into().unwrap());
+        if checksum(kind, payload) != expected {
+            return Step::Skip(next_sync(buffer));
+        }
+        match self.packet(kind, payload) {
+            Some(record) => Step::Packet(total, record),
+            None => Step::Skip(next_sync(buffer)),
+        }
+    }
+
+    /// Decodes a checksummed payload. `None` means it didn't parse; `Some(None)` means it parsed but isn't a record.
+    fn packet(&mut self, kind: u8, payload: &[u8]) -> Option<Option<Record>> {
+        let mut payload = Payload { bytes: payload, pos: 0 };
+        match kind {
+            KIND_HANDSHAKE => {
+                let handshake = Handshake {
+                    protocol: payload.u16()?,
+                    tick_frequency: payload.u64()?,
+                    build: payload.str16()?,
+                    plugin_version: payload.str16()?,
+                };
+                self.names.clear();
+                self.protocol = handshake.protocol;
+                Some(Some(Record::Handshake(handshake)))
+            }
+            KIND_INTERN => {
+                let id = payload.u32()?;
+                let name = payload.str16()?;
+                self.names.insert(id, name);
+                Some(None)
+            }
+            KIND_FRAME => {
+                let index = payload.u64()?;
+                let start = payload.i64()?;
+                let submitted_at = payload.i64()?;
+                let presented = payload.i64()?;
+                let flags = payload.u8()?;
+                let lane_count = payload.u16()?;
+                let mut lanes = Vec::with_capacity(lane_count as usize);
+                let mut spans = Vec::new();
+                for lane in 0..lane_count {
+                    let thread_id = payload.u64()?;
+                    let name = self.name(payload.u32()?);
+                    let count = payload.u32()?;
+                    lanes.push(Lane { thread_id, name });
+                    self.spans(&mut payload, count, lane, &mut spans)?;
+                }
+                Some(Some(Record::Frame(Frame {
+                    index,
+                    start,
+                    submitted_at,
+                    presented,
+                    dangled: flags & FLAG_DANGLED != 0,
+                    lanes,
+                    spans,
+                })))
+            }
+            KIND_VBLANK => Some(Some(Record::VBlank(payload.i64()?))),
+            KIND_DROPPED => Some(Some(Record::Dropped(payload.u64()?))),
+            KIND_LATENCY => Some(Some(Record::Latency(LatencySummary {
+                enqueue_to_present: payload.summary()?,
+                present_to_vblank: payload.summary()?,
+                missed_vblanks: payload.u32()?,
+                frame_gaps: payload.u32()?,
+                last_frame: payload.u64()?,
+                last_vblank: payload.i64()?,
+            }))),
+            // Kinds added by later minor revisions are skipped whole.
+            _ => Some(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::{decode_all, Decoder, SpanRef};
+
+    const MAIN_THREAD: u64 = 0x51;
+
+    fn handshake() -> Handshake {
+        Handshake {
+            protocol: PROTOCOL_VERSION,
+            tick_frequency: 19_200_000,
+            build: "13.0.3".to_owned(),
+            plugin_version: "0.1.2".to_owned(),
+        }
+    }
+
+    fn header(index: u64) -> FrameHeader {
+        FrameHeader {
+            index,
+            start: 100,
+            submitted_at: 200,
+            presented: 300,
+            dangled: false,
+        }
+    }
+
+    fn span(name: &str, start: i64, end: i64) -> SpanRef<'_> {
+        SpanRef {
+            name,
+            start,
+            end,
+            parent: None,
+            depth: 0,
+            unclosed: false,
+        }
+    }
+
+    fn main<'a>(spans: &'a [SpanRef<'a>]) -> [LaneRef<'a>; 1] {
+        [LaneRef {
+            thread_id: MAIN_THREAD,
+            name: "MainThread",
+            spans,
+        }]
+    }
+
+    fn stream() -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        let mut buffer = Vec::new();
+        encoder.handshake(&handshake(), &mut buffer);
+        encoder.frame(header(1), &main(&[span("UiUpdate", 110, 150)]), &mut buffer).unwrap();
+        encoder.frame(
+            header(2),
+            &main(&[span("UiUpdate", 120, 160), span("RunSceneManager", 160, 190)]),
+            &mut buffer,
+        ).unwrap();
+        encoder.vblank(320, &mut buffer);
+        encoder.dropped(7, &mut buffer);
+        encoder.latency(&latency(), &mut buffer);
+        buffer
+    }
+
+    fn latency() -> LatencySummary {
+        LatencySummary {
+            enqueue_to_present: Summary::of(&[900, 1000, 1100]).unwrap(),
+            present_to_vblank: Summary::of(&[5000, 5200]).unwrap(),
+            missed_vblanks: 2,
+            frame_gaps: 1,
+            last_frame: 2,
+            last_vblank: 320,
+        }
+    }
+
+    fn frame(index: u64, spans: &[(&str, i64, i64)]) -> Record {
+        Record::Frame(Frame {
+            index,
+            start: 100,
+            submitted_at: 200,
+            presented: 300,
+            dangled: false,
+            lanes: vec![Lane {
+                thread_id: MAIN_THREAD,
+                name: "MainThread".to_owned(),
+            }],
+            spans: spans
+                .iter()
+                .map(|&(name, start, end)| Span {
+                    name: name.to_owned(),
+                    start,
+                    end,
+                    lane: 0,
+                    parent: None,
+                    depth: 0,
+                    unclosed: false,
+                })
+                .collect(),
+        })
+    }
+
+    fn expected() -> Vec<Record> {
+        vec![
+            Record::Handshake(handshake()),
+            frame(1, &[("UiUpdate", 110, 150)]),
+            frame(2, &[("UiUpdate", 120, 160), ("RunSceneManager", 160, 190)]),
+            Record::VBlank(320),
+            Record::Dropped(7),
+            Record::Latency(latency()),
+        ]
+    }
+
+    #[test]
+    fn round_trips_with_interned_names() {
+        let bytes = stream();
+        // "UiUpdate" is only sent once.
+        assert_eq!(bytes.windows(8).filter(|w| w == b"UiUpdate").count(), 1);
+        assert_eq!(decode_all(&bytes).unwrap(), expected());
+    }
+
+    #[test]
+    fn decodes_byte_at_a_time() {
+        let mut decoder = Decoder::new();
+        let mut records = Vec::new();
+        for byte in stream() {
+            decoder.push(&[byte]);
+            while let Some(record) = decoder.next_record().unwrap() {
+                records.push(record);
+            }
+        }
+        assert_eq!(records, expected());
+        assert_eq!(decoder.version(), Some(PROTOCOL_VERSION));
+        assert_eq!(decoder.skipped(), 0);
+    }
+
+    #[test]
+    fn resyncs_after_corruption() {
+        let mut bytes = stream();
+        // Flip a byte inside the first frame's payload so its checksum fails.
+        let first_frame = bytes
+            .windows(5)
+            .position(|w| w[..4] == SYNC && w[4] == KIND_FRAME)
+            .unwrap();
+        bytes[first_frame + HEADER_LEN] ^= 0xff;
+        // And some garbage between packets.
+        let vblank = bytes
+            .windows(5)
+            .position(|w| w[..4] == SYNC && w[4] == KIND_VBLANK)
+            .unwrap();
+        bytes.splice(vblank..vblank, [0x4e, 0x00, 0x13, 0x37]);
+
+        let mut decoder = Decoder::new();
+        decoder.push(&bytes);
+        let mut records = Vec::new();
+        while let Some(record) = decoder.next_record().unwrap() {
+            records.push(record);
+        }
+        let mut expected = expected();
+        expected.remove(1);
+        assert_eq!(records, expected);
+        assert!(decoder.skipped() > 4);
+    }
+
+    #[test]
+    fn handshake_resets_interned_names() {
+        let mut encoder = Encoder::new();
+        let mut buffer = Vec::new();
+        encoder.handshake(&handshake(), &mut buffer);
+        encoder.frame(header(1), &main(&[span("UiUpdate", 110, 150)]), &mut buffer).unwrap();
+        let reconnect = buffer.len();
+        encoder.handshake(&handshake(), &mut buffer);
+        encoder.frame(header(2), &main(&[span("UiUpdate", 120, 160)]), &mut buffer).unwrap();
+        // A reader that only sees the second connection still gets the name.
+        assert_eq!(
+            decode_all(&buffer[reconnect..]).unwrap(),
+            vec![Record::Handshake(handshake()), frame(2, &[("UiUpdate", 120, 160)])]
+        );
+    }
+
+    #[test]
+    fn round_trips_nesting() {
+        let mut encoder = Encoder::new();
+        let mut buffer = Vec::new();
+        encoder.handshake(&handshake(), &mut buffer);
+        let child = SpanRef {
+            parent: Some(0),
+            depth: 1,
+            unclosed: true,
+            ..span("RunSceneManager", 120, 140)
+        };
+        encoder.frame(header(1), &main(&[span("Looping", 110, 150), child]), &mut buffer).unwrap();
+        let records = decode_all(&buffer).unwrap();
+        let Record::Frame(frame) = &records[1] else {
+            panic!("expected a frame, got {:?}", records[1]);
+        };
+        assert_eq!(frame.spans[0].parent, None);
+        assert_eq!(frame.spans[1].parent, Some(0));
+        assert_eq!(frame.spans[1].depth, 1);
+        assert!(frame.spans[1].unclosed);
+    }
+
+    #[test]
+    fn reports_the_handshake_protocol() {
+        let mut decoder = Decoder::new();
+        let mut buffer = Vec::new();
+        Encoder::new().handshake(
+            &Handshake {
+                protocol: PROTOCOL_VERSION + 1,
+                ..handshake()
+            },
+            &mut buffer,
+        );
+        decoder.push(&buffer[..1]);
+        assert_eq!(decoder.next_record(), Ok(None));
+        // Nothing announced yet: a stream joined mid-connection is assumed to be ours.
+        assert_eq!(decoder.version(), Some(PROTOCOL_VERSION));
+        decoder.push(&buffer[1..]);
+        assert!(matches!(decoder.next_record(), Ok(Some(Record::Handshake(_)))));
+        assert_eq!(decoder.version(), Some(PROTOCOL_VERSION + 1));
+    }
+
+    #[test]
+    fn refuses_frames_over_the_payload_limit() {
+        let mut encoder = Encoder::new();
+        let mut buffer = Vec::new();
+        encoder.handshake(&handshake(), &mut buffer);
+        // 27 bytes a span.
+        let spans = vec![span("UiUpdate", 110, 150); MAX_PAYLOAD as usize / 27 + 1];
+        let err = encoder.frame(header(1), &main(&spans), &mut buffer).unwrap_err();
+        assert!(matches!(err, EncodeError::FrameTooLarge { index: 1, len } if len > MAX_PAYLOAD as usize));
+        // The names still went out, so the next frame decodes with them.
+        encoder.frame(header(2), &main(&spans[..1]), &mut buffer).unwrap();
+        assert_eq!(
+            decode_all(&buffer).unwrap(),
+            vec![Record::Handshake(handshake()), frame(2, &[("UiUpdate", 110, 150)])]
+        );
+    }
+
+    #[test]
+    fn keeps_threads_on_separate_lanes() {
+        let mut encoder = Encoder::new();
+        let mut buffer = Vec::new();
+        encoder.handshake(&handshake(), &mut buffer);
+        let looping = [span("Looping", 110, 150)];
+        let worker = [
+            span("TaskWorker", 115, 145),
+            SpanRef {
+                parent: Some(0),
+                depth: 1,
+                ..span("MutexLock", 120, 125)
+            },
+        ];
+        let lanes = [
+            main(&looping)[0],
+            LaneRef {
+                thread_id: 0x77,
+                name: "TaskWorker0",
+                spans: &worker,
+            },
+        ];
+        encoder.frame(header(1), &lanes, &mut buffer).unwrap();
+        let records = decode_all(&buffer).unwrap();
+        let Record::Frame(frame) = &records[1] else {
+            panic!("expected a frame, got {:?}", records[1]);
+        };
+        assert_eq!(frame.lanes.len(), 2);
+        assert_eq!(frame.lanes[1].thread_id, 0x77);
+        assert_eq!(frame.lanes[1].name, "TaskWorker0");
+        let shape: Vec<_> = frame
+            .spans
+            .iter()
+            .map(|s| (s.name.as_str(), s.lane, s.parent))
+            .collect();
+        // The worker's parent index is rebased past the main thread's span.
+        assert_eq!(
+            shape,
+            vec![("Looping", 0, None), ("TaskWorker", 1, None), ("MutexLock", 1, Some(1))]
+        );
+    }
+}
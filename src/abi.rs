@@ -0,0 +1,307 @@
+/** Versioned C ABI exported by ssbusync.nro
+ *
+ * Plugins that don't link ssbusync as a library look up `ssbusync_abi_negotiate`, ask for the major version they
+ * were built against, and get back a pointer to a `SsbuSyncApi` function table (or null if the versions don't match).
+ *
+ * Minor versions only ever append fields to the end of `SsbuSyncApi` and `SsbuSyncAbiConfig`, so a client built
+ * against an older minor can keep using the table it gets. Both structs start with their size in bytes.
+ *
+ * The table is built into every copy of ssbusync so the arbiter can hand out the winner's; only ssbusync.nro exports
+ * `ssbusync_abi_negotiate`.
+ */
+use core::ffi::c_void;
+
+use crate::control::ControlError;
+use crate::render::buffer_swap::BufferMode;
+use crate::SsbuSyncConfig;
+
+pub const SSBUSYNC_ABI_NEGOTIATE_SYMBOL: &[u8] = b"ssbusync_abi_negotiate\0";
+
+/// Bumped when an existing field changes meaning or layout.
+pub const ABI_VERSION_MAJOR: u32 = 1;
+/// Bumped when fields are appended.
+pub const ABI_VERSION_MINOR: u32 = 0;
+
+/// Return codes used by the function table.
+pub mod AbiCode {
+    pub const OK: i32 = 0;
+    pub const NOT_INSTALLED: i32 = -1;
+    pub const NOT_ALLOWED_ON_EMULATOR: i32 = -2;
+    pub const BUFFER_SWAP_DISABLED: i32 = -3;
+    pub const COOLDOWN: i32 = -4;
+    pub const ALREADY_IN_MODE: i32 = -5;
+    pub const ONLINE_FIX_ALREADY_SET: i32 = -6;
+    pub const WINDOW_NOT_READY: i32 = -7;
+    pub const INSUFFICIENT_CAPACITY: i32 = -8;
+    /// The buffer mode wasn't 2 (double) or 3 (triple).
+    pub const INVALID_ARGUMENT: i32 = -9;
+    /// Every subscriber slot is taken.
+    pub const NO_SUBSCRIBER_SLOTS: i32 = -10;
+}
+
+impl ControlError {
+    pub fn abi_code(&self) -> i32 {
+        match self {
+            Self::NotInstalled => AbiCode::NOT_INSTALLED,
+            Self::NotAllowedOnEmulator => AbiCode::NOT_ALLOWED_ON_EMULATOR,
+            Self::BufferSwapDisabled => AbiCode::BUFFER_SWAP_DISABLED,
+            Self::Cooldown => AbiCode::COOLDOWN,
+            Self::AlreadyInMode(_) => AbiCode::ALREADY_IN_MODE,
+            Self::OnlineFixAlreadySet(_) => AbiCode::ONLINE_FIX_ALREADY_SET,
+            Self::WindowNotReady => AbiCode::WINDOW_NOT_READY,
+            Self::InsufficientCapacity { .. } => AbiCode::INSUFFICIENT_CAPACITY,
+        }
+    }
+}
+
+fn result_code(result: Result<(), ControlError>) -> i32 {
+    match result {
+        Ok(()) => AbiCode::OK,
+        Err(err) => err.abi_code(),
+    }
+}
+
+/// Called after a buffer mode change lands. `mode` is the active texture count (2 or 3).
+pub type BufferModeCallback = extern "C" fn(mode: u8, user_data: *mut c_void);
+
+/// C mirror of `SsbuSyncConfig`. Booleans are `0`/`1` bytes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SsbuSyncAbiConfig {
+    /// `size_of::<SsbuSyncAbiConfig>()` as the client knows it; only that many bytes are written.
+    pub size: u32,
+    pub disable_vsync: u8,
+    pub disable_pacer: u8,
+    pub slow_pacer_bias: u8,
+    pub enable_triple_buffer: u8,
+    pub allow_buffer_swap: u8,
+    pub smooth_ffa: u8,
+    pub online_only: u8,
+    pub profiling: u8,
+    pub emulator: u8,
+    pub auto_buffer_policy: u8,
+    pub watchdog: u8,
+    pub triple_fighter_threshold: u8,
+    pub double_fighter_threshold: u8,
+    pub heavy_fighter_weight: u8,
+    pub pacer_bias_double: u16,
+    pub pacer_bias_triple: u16,
+    pub buffer_policy_hold_frames: u16,
+    pub watchdog_window_frames: u16,
+    pub watchdog_skip_threshold: u16,
+    pub watchdog_cooldown_frames: u16,
+}
+
+impl From<&SsbuSyncConfig> for SsbuSyncAbiConfig {
+    fn from(config: &SsbuSyncConfig) -> Self {
+        Self {
+            size: core::mem::size_of::<Self>() as u32,
+            disable_vsync: config.disable_vsync as u8,
+            disable_pacer: config.disable_pacer as u8,
+            slow_pacer_bias: config.slow_pacer_bias as u8,
+            enable_triple_buffer: config.enable_triple_buffer as u8,
+            allow_buffer_swap: config.allow_buffer_swap as u8,
+            smooth_ffa: config.smooth_ffa as u8,
+            online_only: config.online_only as u8,
+            profiling: config.profiling as u8,
+            emulator: config.emulator_check as u8,
+            auto_buffer_policy: config.auto_buffer_policy as u8,
+            watchdog: config.watchdog as u8,
+            triple_fighter_threshold: config.triple_fighter_threshold,
+            double_fighter_threshold: config.double_fighter_threshold,
+            heavy_fighter_weight: config.heavy_fighter_weight,
+            pacer_bias_double: config.pacer_bias_double,
+            pacer_bias_triple: config.pacer_bias_triple,
+            buffer_policy_hold_frames: config.buffer_policy_hold_frames,
+            watchdog_window_frames: config.watchdog_window_frames,
+            watchdog_skip_threshold: config.watchdog_skip_threshold,
+            watchdog_cooldown_frames: config.watchdog_cooldown_frames,
+        }
+    }
+}
+
+/// Function table handed out by `ssbusync_abi_negotiate`.
+#[repr(C)]
+pub struct SsbuSyncApi {
+    /// `size_of::<SsbuSyncApi>()` for the version ssbusync.nro was built with.
+    pub size: u32,
+    pub version_major: u32,
+    pub version_minor: u32,
+    /// Same value as `ssbusync_status`, see `compatibility::Status`.
+    pub status: extern "C" fn() -> u32,
+    /// Active texture count (2 or 3).
+    pub current_buffer_mode: extern "C" fn() -> u8,
+    /// Window texture capacity, or -1 before the window has been seen.
+    pub window_texture_capacity: extern "C" fn() -> i32,
+    /// Starts a swap to `mode` (2 or 3). Returns an `AbiCode`.
+    pub request_buffer_mode: extern "C" fn(mode: u8) -> i32,
+    /// Returns an `AbiCode`.
+    pub set_online_fix: extern "C" fn(enabled: bool) -> i32,
+    /// Fills `out` up to `(*out).size` bytes. Returns an `AbiCode`.
+    pub effective_config: unsafe extern "C" fn(out: *mut SsbuSyncAbiConfig) -> i32,
+    /// Returns an `AbiCode`. `user_data` is passed back to `callback` untouched.
+    pub subscribe_buffer_mode: extern "C" fn(callback: BufferModeCallback, user_data: *mut c_void) -> i32,
+    /// Removes a subscription with the same callback and user data. Returns an `AbiCode`.
+    pub unsubscribe_buffer_mode: extern "C" fn(callback: BufferModeCallback, user_data: *mut c_void) -> i32,
+}
+
+impl SsbuSyncApi {
+    /// Whether the table has `field_end` bytes, i.e. was built with a minor version that includes the field.
+    pub fn has_field(&self, field_end: usize) -> bool {
+        self.size as usize >= field_end
+    }
+}
+
+mod host {
+    use core::ffi::c_void;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::control;
+    use crate::render::buffer_swap;
+
+    const MAX_SUBSCRIBERS: usize = 16;
+
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    struct Subscriber {
+        callback: BufferModeCallback,
+        user_data: usize,
+    }
+
+    static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+    static mut FORWARDER_REGISTERED: bool = false;
+
+    fn forward_buffer_mode(mode: BufferMode) {
+        let subscribers = match SUBSCRIBERS.lock() {
+            Ok(subscribers) => subscribers.clone(),
+            Err(_) => return,
+        };
+        for subscriber in subscribers {
+            (subscriber.callback)(mode as u8, subscriber.user_data as *mut c_void);
+        }
+    }
+
+    extern "C" fn status() -> u32 {
+        #[cfg(feature = "nro-entry")]
+        return crate::ssbusync_status();
+        #[cfg(not(feature = "nro-entry"))]
+        return if crate::SyncEnv::installed() {
+            crate::compatibility::Status::INSTALLED as u32
+        } else {
+            crate::compatibility::Status::PENDING as u32
+        };
+    }
+
+    extern "C" fn current_buffer_mode() -> u8 {
+        buffer_swap::current_buffer_mode() as u8
+    }
+
+    extern "C" fn window_texture_capacity() -> i32 {
+        buffer_swap::get_window_texture_capacity().unwrap_or(-1)
+    }
+
+    extern "C" fn request_buffer_mode(mode: u8) -> i32 {
+        match BufferMode::from_u8(mode) {
+            Some(mode) => result_code(control::request_buffer_mode(mode)),
+            None => AbiCode::INVALID_ARGUMENT,
+        }
+    }
+
+    extern "C" fn set_online_fix(enabled: bool) -> i32 {
+        result_code(control::set_online_fix(enabled))
+    }
+
+    unsafe extern "C" fn effective_config(out: *mut SsbuSyncAbiConfig) -> i32 {
+        if out.is_null() {
+            return AbiCode::INVALID_ARGUMENT;
+        }
+        let config = match control::effective_config() {
+            Ok(config) => SsbuSyncAbiConfig::from(&config),
+            Err(err) => return err.abi_code(),
+        };
+        let len = ((*out).size as usize).min(core::mem::size_of::<SsbuSyncAbiConfig>());
+        if len < core::mem::size_of::<u32>() {
+            return AbiCode::INVALID_ARGUMENT;
+        }
+        // Skip `size` so the client keeps the value it passed in.
+        let src = (&config as *const SsbuSyncAbiConfig).cast::<u8>();
+        let dst = out.cast::<u8>();
+        let skip = core::mem::size_of::<u32>();
+        core::ptr::copy_nonoverlapping(src.add(skip), dst.add(skip), len - skip);
+        AbiCode::OK
+    }
+
+    extern "C" fn subscribe_buffer_mode(callback: BufferModeCallback, user_data: *mut c_void) -> i32 {
+        let Ok(mut subscribers) = SUBSCRIBERS.lock() else {
+            return AbiCode::NO_SUBSCRIBER_SLOTS;
+        };
+        if subscribers.len() >= MAX_SUBSCRIBERS {
+            return AbiCode::NO_SUBSCRIBER_SLOTS;
+        }
+        // One slot in the Rust callback table fans out to every C subscriber.
+        unsafe {
+            if !FORWARDER_REGISTERED {
+                if !buffer_swap::subscribe_buffer_mode_change(forward_buffer_mode) {
+                    return AbiCode::NO_SUBSCRIBER_SLOTS;
+                }
+                FORWARDER_REGISTERED = true;
+            }
+        }
+        subscribers.push(Subscriber {
+            callback,
+            user_data: user_data as usize,
+        });
+        AbiCode::OK
+    }
+
+    extern "C" fn unsubscribe_buffer_mode(callback: BufferModeCallback, user_data: *mut c_void) -> i32 {
+        let Ok(mut subscribers) = SUBSCRIBERS.lock() else {
+            return AbiCode::INVALID_ARGUMENT;
+        };
+        let subscriber = Subscriber {
+            callback,
+            user_data: user_data as usize,
+        };
+        match subscribers.iter().position(|s| *s == subscriber) {
+            Some(index) => {
+                subscribers.remove(index);
+                AbiCode::OK
+            }
+            None => AbiCode::INVALID_ARGUMENT,
+        }
+    }
+
+    pub(super) static API: SsbuSyncApi = SsbuSyncApi {
+        size: core::mem::size_of::<SsbuSyncApi>() as u32,
+        version_major: ABI_VERSION_MAJOR,
+        version_minor: ABI_VERSION_MINOR,
+        status,
+        current_buffer_mode,
+        window_texture_capacity,
+        request_buffer_mode,
+        set_online_fix,
+        effective_config,
+        subscribe_buffer_mode,
+        unsubscribe_buffer_mode,
+    };
+}
+
+/// This copy's function table.
+pub(crate) fn api() -> &'static SsbuSyncApi {
+    &host::API
+}
+
+/// Returns the function table if ssbusync.nro speaks `major` and at least `min_minor`, null otherwise.
+#[cfg(feature = "nro-entry")]
+#[no_mangle]
+pub extern "C" fn ssbusync_abi_negotiate(major: u32, min_minor: u32) -> *const SsbuSyncApi {
+    if major != ABI_VERSION_MAJOR || min_minor > ABI_VERSION_MINOR {
+        tracing::warn!(
+            target: "abi",
+            "client asked for {}.{} but this build is {}.{}",
+            major, min_minor, ABI_VERSION_MAJOR, ABI_VERSION_MINOR
+        );
+        return core::ptr::null();
+    }
+    &host::API
+}
@@ -0,0 +1,499 @@
+/** Ownership arbitration between plugins that embed ssbusync
+ *
+ * Every copy of ssbusync exports `ssbusync_arbiter`. `nn::ro::LookupSymbol` resolves it to whichever copy was loaded
+ * first, so all contenders end up registering in the same table no matter which plugin they live in.
+ *
+ * Contenders register from `#[skyline::main]` with a priority. Plugins are all loaded before the game loads
+ * `common.nro`, so registration closes there: the highest priority wins, then the newest ssbusync version, then
+ * whoever registered first. The load hook only decides; each contender waits for the decision on its own thread, so
+ * the winner installs outside the game's module load. Everyone else is told who won and becomes a client of the
+ * winner's `abi::SsbuSyncApi` table. A contender that registers after `common.nro` loaded, before anyone has won,
+ * elects straight away instead of waiting for a load that already happened.
+ *
+ * `Install_SSBU_Sync` without a priority still installs straight away, but it now takes the election with it so that
+ * later contenders lose with a diagnostic instead of patching over it.
+ */
+use core::sync::atomic::{AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, OnceLock};
+
+use crate::abi::SsbuSyncApi;
+use crate::SsbuSyncConfig;
+
+pub const SSBUSYNC_ARBITER_SYMBOL: &[u8] = b"ssbusync_arbiter\0";
+
+/// Bumped when `Arbiter` or `ArbiterSlot` change layout.
+pub const ARBITER_PROTOCOL: u32 = 2;
+
+pub const MAX_CONTENDERS: usize = 16;
+
+/// Priority ssbusync.nro registers with. Any embedding plugin that asks for more takes over.
+pub const STANDALONE_PRIORITY: u32 = 0;
+
+/// The game NRO whose load closes registration.
+const ELECTION_NRO: &str = "common";
+
+/// `winner` value before the election has run.
+const UNDECIDED: u32 = 0;
+
+#[repr(C)]
+pub struct ArbiterSlot {
+    /// Set last, once every other field is written.
+    ready: AtomicU32,
+    priority: AtomicU32,
+    /// `major << 16 | minor << 8 | patch` of the contender's ssbusync crate.
+    version: AtomicU32,
+    name_ptr: AtomicPtr<u8>,
+    name_len: AtomicUsize,
+    api: AtomicPtr<SsbuSyncApi>,
+}
+
+impl ArbiterSlot {
+    const EMPTY: Self = Self {
+        ready: AtomicU32::new(0),
+        priority: AtomicU32::new(0),
+        version: AtomicU32::new(0),
+        name_ptr: AtomicPtr::new(core::ptr::null_mut()),
+        name_len: AtomicUsize::new(0),
+        api: AtomicPtr::new(core::ptr::null_mut()),
+    };
+}
+
+#[repr(C)]
+pub struct Arbiter {
+    protocol: u32,
+    count: AtomicU32,
+    /// Slot index + 1 of the winner, or `UNDECIDED`.
+    winner: AtomicU32,
+    /// Set by whichever contender saw `common.nro` load, even if nobody had registered yet.
+    closed: AtomicU32,
+    slots: [ArbiterSlot; MAX_CONTENDERS],
+}
+
+static ARBITER: Arbiter = Arbiter {
+    protocol: ARBITER_PROTOCOL,
+    count: AtomicU32::new(0),
+    winner: AtomicU32::new(UNDECIDED),
+    closed: AtomicU32::new(0),
+    slots: [ArbiterSlot::EMPTY; MAX_CONTENDERS],
+};
+
+#[no_mangle]
+pub extern "C" fn ssbusync_arbiter() -> *const Arbiter {
+    &ARBITER
+}
+
+/// A registered contender, as read back from the shared table.
+#[derive(Debug, Clone, Copy)]
+pub struct Contender {
+    pub name: &'static str,
+    pub priority: u32,
+    pub version: u32,
+    api: *const SsbuSyncApi,
+}
+
+impl Contender {
+    pub fn version_string(&self) -> String {
+        format!(
+            "{}.{}.{}",
+            self.version >> 16,
+            (self.version >> 8) & 0xff,
+            self.version & 0xff
+        )
+    }
+
+    /// The contender's function table, if it has one.
+    pub fn api(&self) -> Option<&'static SsbuSyncApi> {
+        unsafe { self.api.as_ref() }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome {
+    /// Registration is still open.
+    Pending,
+    Won,
+    Lost(Contender),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArbiterError {
+    /// Every contender slot is taken.
+    Full,
+    /// The shared arbiter was built with a different protocol.
+    ProtocolMismatch { ours: u32, theirs: u32 },
+    /// Someone already won; we were too late to take part.
+    AlreadyDecided,
+    /// This plugin already owns ssbusync and installs (or has installed) it through the election.
+    AlreadyInstalled,
+}
+
+impl std::fmt::Display for ArbiterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Full => write!(f, "all {} arbiter slots are taken", MAX_CONTENDERS),
+            Self::ProtocolMismatch { ours, theirs } => write!(
+                f,
+                "arbiter protocol {} does not match ours ({}); update the older plugin",
+                theirs, ours
+            ),
+            Self::AlreadyDecided => write!(f, "another plugin already owns ssbusync"),
+            Self::AlreadyInstalled => write!(f, "this plugin already owns ssbusync"),
+        }
+    }
+}
+
+struct Registration {
+    index: u32,
+    config: SsbuSyncConfig,
+}
+
+static REGISTRATION: Mutex<Option<Registration>> = Mutex::new(None);
+
+/// Set once this copy has seen registration close.
+static CLOSED: Mutex<bool> = Mutex::new(false);
+static CLOSED_CHANGED: Condvar = Condvar::new();
+
+fn crate_version() -> u32 {
+    let part = |s: &str| s.parse::<u32>().unwrap_or(0).min(0xff);
+    (part(env!("CARGO_PKG_VERSION_MAJOR")) << 16)
+        | (part(env!("CARGO_PKG_VERSION_MINOR")) << 8)
+        | part(env!("CARGO_PKG_VERSION_PATCH"))
+}
+
+static SHARED: OnceLock<&'static Arbiter> = OnceLock::new();
+
+/// The arbiter every contender shares, i.e. the copy `LookupSymbol` resolves first.
+fn shared() -> Result<&'static Arbiter, ArbiterError> {
+    let arbiter = *SHARED.get_or_init(|| {
+        match crate::compatibility::lookup_symbol_addr(SSBUSYNC_ARBITER_SYMBOL, "arbiter") {
+            Some(addr) => unsafe {
+                let get: extern "C" fn() -> *const Arbiter = core::mem::transmute(addr);
+                &*get()
+            },
+            None => &ARBITER,
+        }
+    });
+    if arbiter.protocol != ARBITER_PROTOCOL {
+        return Err(ArbiterError::ProtocolMismatch {
+            ours: ARBITER_PROTOCOL,
+            theirs: arbiter.protocol,
+        });
+    }
+    Ok(arbiter)
+}
+
+fn contender(arbiter: &Arbiter, index: u32) -> Contender {
+    let slot = &arbiter.slots[index as usize];
+    let name = unsafe {
+        let bytes = core::slice::from_raw_parts(
+            slot.name_ptr.load(Ordering::Acquire),
+            slot.name_len.load(Ordering::Acquire),
+        );
+        core::str::from_utf8(bytes).unwrap_or("<invalid name>")
+    };
+    Contender {
+        name,
+        priority: slot.priority.load(Ordering::Acquire),
+        version: slot.version.load(Ordering::Acquire),
+        api: slot.api.load(Ordering::Acquire),
+    }
+}
+
+fn register_slot(arbiter: &Arbiter, name: &'static str, priority: u32) -> Result<u32, ArbiterError> {
+    if arbiter.winner.load(Ordering::Acquire) != UNDECIDED {
+        return Err(ArbiterError::AlreadyDecided);
+    }
+    let index = arbiter.count.fetch_add(1, Ordering::AcqRel);
+    if index as usize >= MAX_CONTENDERS {
+        arbiter.count.fetch_sub(1, Ordering::AcqRel);
+        return Err(ArbiterError::Full);
+    }
+    let slot = &arbiter.slots[index as usize];
+    slot.priority.store(priority, Ordering::Release);
+    slot.version.store(crate_version(), Ordering::Release);
+    slot.name_ptr.store(name.as_ptr() as *mut u8, Ordering::Release);
+    slot.name_len.store(name.len(), Ordering::Release);
+    slot.api
+        .store(crate::abi::api() as *const SsbuSyncApi as *mut SsbuSyncApi, Ordering::Release);
+    slot.ready.store(1, Ordering::Release);
+    Ok(index)
+}
+
+/// Picks the best ready contender: highest priority, then newest version, then first registered.
+fn best(arbiter: &Arbiter) -> Option<u32> {
+    let count = (arbiter.count.load(Ordering::Acquire) as usize).min(MAX_CONTENDERS) as u32;
+    (0..count)
+        .filter(|&index| arbiter.slots[index as usize].ready.load(Ordering::Acquire) != 0)
+        .map(|index| (index, contender(arbiter, index)))
+        .max_by(|(a_index, a), (b_index, b)| {
+            (a.priority, a.version)
+                .cmp(&(b.priority, b.version))
+                .then(b_index.cmp(a_index))
+        })
+        .map(|(index, _)| index)
+}
+
+fn our_index() -> Option<u32> {
+    REGISTRATION
+        .lock()
+        .ok()
+        .and_then(|registration| registration.as_ref().map(|r| r.index))
+}
+
+pub fn outcome() -> Outcome {
+    let (Ok(arbiter), Some(ours)) = (shared(), our_index()) else {
+        return Outcome::Pending;
+    };
+    match arbiter.winner.load(Ordering::Acquire) {
+        UNDECIDED => Outcome::Pending,
+        winner if winner - 1 == ours => Outcome::Won,
+        winner => Outcome::Lost(contender(arbiter, winner - 1)),
+    }
+}
+
+/// The winning contender, once the election has run.
+pub fn winner() -> Option<Contender> {
+    let arbiter = shared().ok()?;
+    match arbiter.winner.load(Ordering::Acquire) {
+        UNDECIDED => None,
+        winner => Some(contender(arbiter, winner - 1)),
+    }
+}
+
+pub fn lost() -> bool {
+    matches!(outcome(), Outcome::Lost(_))
+}
+
+fn report_loss(name: &str, winner: &Contender) {
+    tracing::info!(
+        target: "arbiter",
+        "{} did not install ssbusync: {} (priority {}, ssbusync {}) owns it. Runtime control from {} is forwarded to {}.",
+        name,
+        winner.name,
+        winner.priority,
+        winner.version_string(),
+        name,
+        winner.name
+    );
+}
+
+/// Registers as a contender and installs once the election has been won.
+pub fn contend(name: &'static str, priority: u32, config: SsbuSyncConfig) -> Result<(), ArbiterError> {
+    let arbiter = match shared() {
+        Ok(arbiter) => arbiter,
+        Err(err) => {
+            tracing::info!(target: "arbiter", "{} did not install ssbusync: {}", name, err);
+            lose();
+            return Err(err);
+        }
+    };
+    let index = match register_slot(arbiter, name, priority) {
+        Ok(index) => index,
+        Err(err) => {
+            match winner() {
+                Some(winner) => report_loss(name, &winner),
+                None => tracing::info!(target: "arbiter", "{} did not install ssbusync: {}", name, err),
+            }
+            lose();
+            return Err(err);
+        }
+    };
+    if let Ok(mut registration) = REGISTRATION.lock() {
+        *registration = Some(Registration { index, config });
+    }
+    tracing::info!(
+        target: "arbiter",
+        "{} registered for ssbusync ownership with priority {}",
+        name, priority
+    );
+
+    if skyline::nro::add_hook(on_nro_load).is_err() {
+        tracing::info!(target: "arbiter", "nro hook unavailable; electing now.");
+        elect();
+        settle();
+        return Ok(());
+    }
+    // Checked after the hook is in, so `common.nro` loading in between is seen by one or the other.
+    if arbiter.closed.load(Ordering::Acquire) != 0 {
+        tracing::info!(target: "arbiter", "{}.nro already loaded; electing now.", ELECTION_NRO);
+        elect();
+        settle();
+        return Ok(());
+    }
+    std::thread::spawn(|| {
+        wait_for_election();
+        settle();
+    });
+    Ok(())
+}
+
+/// Registers as an immediate winner. Used by `Install_SSBU_Sync`, which has always installed on the spot.
+pub fn claim_now(name: &'static str) -> Result<(), ArbiterError> {
+    let arbiter = shared()?;
+    if let Some(index) = our_index() {
+        // Already registered through `contend`. Take the election now if it's still open; if we already won it, the
+        // election installs and this call must not do it a second time.
+        return match arbiter
+            .winner
+            .compare_exchange(UNDECIDED, index + 1, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => Ok(()),
+            Err(winner) if winner == index + 1 => Err(ArbiterError::AlreadyInstalled),
+            Err(_) => Err(ArbiterError::AlreadyDecided),
+        };
+    }
+    let index = register_slot(arbiter, name, u32::MAX)?;
+    if let Ok(mut registration) = REGISTRATION.lock() {
+        *registration = Some(Registration {
+            index,
+            config: SsbuSyncConfig::default(),
+        });
+    }
+    arbiter
+        .winner
+        .compare_exchange(UNDECIDED, index + 1, Ordering::AcqRel, Ordering::Acquire)
+        .map(|_| ())
+        .map_err(|_| ArbiterError::AlreadyDecided)
+}
+
+fn on_nro_load(info: &skyline::nro::NroInfo) {
+    if info.name == ELECTION_NRO {
+        elect();
+    }
+}
+
+/// Closes registration and wakes the thread waiting on it. Runs inside the game's module load, so it only decides.
+fn elect() {
+    if let Ok(arbiter) = shared() {
+        decide(arbiter);
+    }
+    let mut closed = CLOSED.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *closed = true;
+    CLOSED_CHANGED.notify_all();
+}
+
+/// Marks registration closed and picks the winner if nobody has yet. Only the first contender to decide counts;
+/// everyone else reads the same answer.
+fn decide(arbiter: &Arbiter) {
+    arbiter.closed.store(1, Ordering::Release);
+    if let Some(best) = best(arbiter) {
+        let _ = arbiter
+            .winner
+            .compare_exchange(UNDECIDED, best + 1, Ordering::AcqRel, Ordering::Acquire);
+    }
+}
+
+fn wait_for_election() {
+    let mut closed = CLOSED.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    while !*closed {
+        closed = CLOSED_CHANGED
+            .wait(closed)
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+    }
+}
+
+/// Installs if we won, otherwise reports who did.
+fn settle() {
+    let Ok(arbiter) = shared() else {
+        return;
+    };
+    let Some(ours) = our_index() else {
+        return;
+    };
+    let us = contender(arbiter, ours);
+    match outcome() {
+        Outcome::Won => {
+            let config = REGISTRATION
+                .lock()
+                .ok()
+                .and_then(|registration| registration.as_ref().map(|r| r.config))
+                .unwrap_or_default();
+            tracing::info!(
+                target: "arbiter",
+                "{} won ssbusync ownership (priority {}); installing.",
+                us.name, us.priority
+            );
+            crate::install_unchecked(config);
+        }
+        Outcome::Lost(winner) => {
+            report_loss(us.name, &winner);
+            lose();
+        }
+        Outcome::Pending => {}
+    }
+}
+
+fn lose() {
+    #[cfg(feature = "nro-entry")]
+    crate::compatibility::SSBUSyncHost::set_claimed();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arbiter(contenders: &[(&'static str, u32, u32)]) -> Arbiter {
+        let arbiter = Arbiter {
+            protocol: ARBITER_PROTOCOL,
+            count: AtomicU32::new(contenders.len() as u32),
+            winner: AtomicU32::new(UNDECIDED),
+            closed: AtomicU32::new(0),
+            slots: [ArbiterSlot::EMPTY; MAX_CONTENDERS],
+        };
+        for (slot, &(name, priority, version)) in arbiter.slots.iter().zip(contenders) {
+            slot.priority.store(priority, Ordering::Release);
+            slot.version.store(version, Ordering::Release);
+            slot.name_ptr.store(name.as_ptr() as *mut u8, Ordering::Release);
+            slot.name_len.store(name.len(), Ordering::Release);
+            slot.ready.store(1, Ordering::Release);
+        }
+        arbiter
+    }
+
+    fn best_name(arbiter: &Arbiter) -> Option<&'static str> {
+        best(arbiter).map(|index| contender(arbiter, index).name)
+    }
+
+    #[test]
+    fn highest_priority_wins_over_a_newer_version() {
+        let arbiter = arbiter(&[("old", 0, 0x0201), ("hdr", 10, 0x0100), ("new", 0, 0x0300)]);
+        assert_eq!(best_name(&arbiter), Some("hdr"));
+    }
+
+    #[test]
+    fn newest_version_breaks_a_priority_tie() {
+        let arbiter = arbiter(&[("a", 5, 0x0100), ("b", 5, 0x0102), ("c", 1, 0x0200)]);
+        assert_eq!(best_name(&arbiter), Some("b"));
+    }
+
+    #[test]
+    fn first_registered_breaks_a_full_tie() {
+        let arbiter = arbiter(&[("first", 5, 0x0100), ("second", 5, 0x0100), ("third", 5, 0x0100)]);
+        assert_eq!(best_name(&arbiter), Some("first"));
+    }
+
+    #[test]
+    fn slots_still_being_written_are_skipped() {
+        let arbiter = arbiter(&[("ready", 1, 0x0100), ("writing", 9, 0x0100)]);
+        arbiter.slots[1].ready.store(0, Ordering::Release);
+        assert_eq!(best_name(&arbiter), Some("ready"));
+        assert_eq!(best(&Arbiter { count: AtomicU32::new(0), ..arbiter }), None);
+    }
+
+    #[test]
+    fn deciding_with_nobody_registered_closes_without_a_winner() {
+        let arbiter = arbiter(&[]);
+        decide(&arbiter);
+        assert_ne!(arbiter.closed.load(Ordering::Acquire), 0);
+        assert_eq!(arbiter.winner.load(Ordering::Acquire), UNDECIDED);
+    }
+
+    #[test]
+    fn the_first_decision_stands() {
+        let arbiter = arbiter(&[("low", 1, 0x0100), ("high", 2, 0x0100)]);
+        decide(&arbiter);
+        arbiter.slots[0].priority.store(3, Ordering::Release);
+        decide(&arbiter);
+        assert_eq!(arbiter.winner.load(Ordering::Acquire), 2);
+    }
+}
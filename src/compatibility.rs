@@ -48,6 +48,10 @@ pub mod SSBUSyncHost {
             .is_ok()
     }
     
+    pub fn set_installed() {
+        STATUS.store(Status::INSTALLED, Ordering::Release);
+    }
+    
     pub fn set_disabled() {
         STATUS.store(Status::DISABLED, Ordering::Release);
     }
@@ -59,12 +63,13 @@ pub mod SSBUSyncHost {
 
 // ── Symbol lookup ──
 
-fn lookup_symbol_addr(sym_nul: &[u8], caller: &str) -> Option<usize> {
+pub(crate) fn lookup_symbol_addr(sym_nul: &[u8], caller: &str) -> Option<usize> {
     let mut addr = 0usize;
     unsafe {
         let rc = ro::LookupSymbol(&mut addr, sym_nul.as_ptr());
-        println!(
-            "[ssbusync] LookupSymbol caller={} sym={:?} rc={} addr=0x{:x}",
+        tracing::debug!(
+            target: "compatibility",
+            "LookupSymbol caller={} sym={:?} rc={} addr=0x{:x}",
             caller,
             sym_nul,
             rc,
@@ -86,8 +91,9 @@ fn lookup_symbol_exists(sym_nul: &[u8], caller: &str) -> bool {
 pub fn check_external_disabler() -> bool {
     let sym = SSBUSYNC_EXPORTED_DISABLE_SYMBOL;
     let sym_str = unsafe { core::ffi::CStr::from_ptr(sym.as_ptr().cast()) };
-    println!(
-        "[ssbusync] external_disabler sym bytes={:?} str={:?}",
+    tracing::debug!(
+        target: "compatibility",
+        "external_disabler sym bytes={:?} str={:?}",
         sym,
         sym_str.to_str().ok()
     );
@@ -117,17 +123,113 @@ pub fn check_ssbusync_status() -> Option<u32> {
     match query_remote_status() {
         Some(status) => {
             match status as u8 {
-                Status::PENDING   => println!("[ssbusync] status: PENDING (not yet installed)"),
-                Status::CLAIMED   => println!("[ssbusync] status: CLAIMED (owned by another plugin)"),
-                Status::INSTALLED => println!("[ssbusync] status: INSTALLED (running)"),
-                Status::DISABLED  => println!("[ssbusync] status: DISABLED"),
-                other             => println!("[ssbusync] status: UNKNOWN ({})", other),
+                Status::PENDING   => tracing::info!(target: "compatibility", "status: PENDING (not yet installed)"),
+                Status::CLAIMED   => tracing::info!(target: "compatibility", "status: CLAIMED (owned by another plugin)"),
+                Status::INSTALLED => tracing::info!(target: "compatibility", "status: INSTALLED (running)"),
+                Status::DISABLED  => tracing::info!(target: "compatibility", "status: DISABLED"),
+                other             => tracing::info!(target: "compatibility", "status: UNKNOWN ({})", other),
             }
             Some(status)
         }
         None => {
-            println!("[ssbusync] remote ssbusync.nro not found");
+            tracing::info!(target: "compatibility", "remote ssbusync.nro not found");
+            None
+        }
+    }
+}
+
+// ── Remote Control (C ABI) ──
+
+/// The function table of whoever owns ssbusync: the arbiter's winner if another plugin won, otherwise a loaded
+/// ssbusync.nro. Returns `None` if neither is around or ssbusync.nro speaks a different major version.
+pub fn remote_api() -> Option<&'static crate::abi::SsbuSyncApi> {
+    use crate::abi::*;
+    if let crate::arbiter::Outcome::Lost(winner) = crate::arbiter::outcome() {
+        if let Some(api) = winner.api() {
+            if api.version_major == ABI_VERSION_MAJOR {
+                return Some(api);
+            }
+        }
+    }
+    let addr = lookup_symbol_addr(SSBUSYNC_ABI_NEGOTIATE_SYMBOL, "remote_api")?;
+    unsafe {
+        let negotiate: extern "C" fn(u32, u32) -> *const SsbuSyncApi = core::mem::transmute(addr);
+        let api = negotiate(ABI_VERSION_MAJOR, ABI_VERSION_MINOR);
+        if api.is_null() {
+            tracing::warn!(
+                target: "compatibility",
+                "remote ssbusync.nro rejected abi {}.{}",
+                ABI_VERSION_MAJOR, ABI_VERSION_MINOR
+            );
             None
+        } else {
+            Some(&*api)
         }
     }
 }
+
+fn remote_result(
+    api: &crate::abi::SsbuSyncApi,
+    code: i32,
+    requested: Option<crate::render::buffer_swap::BufferMode>,
+) -> Result<(), crate::control::ControlError> {
+    use crate::abi::AbiCode;
+    use crate::control::ControlError;
+    match code {
+        AbiCode::OK => Ok(()),
+        AbiCode::NOT_ALLOWED_ON_EMULATOR => Err(ControlError::NotAllowedOnEmulator),
+        AbiCode::BUFFER_SWAP_DISABLED => Err(ControlError::BufferSwapDisabled),
+        AbiCode::COOLDOWN => Err(ControlError::Cooldown),
+        AbiCode::ALREADY_IN_MODE => match requested {
+            Some(mode) => Err(ControlError::AlreadyInMode(mode)),
+            None => Err(ControlError::WindowNotReady),
+        },
+        AbiCode::WINDOW_NOT_READY => Err(ControlError::WindowNotReady),
+        AbiCode::INSUFFICIENT_CAPACITY => Err(ControlError::InsufficientCapacity {
+            requested: requested.map_or(0, |mode| mode.texture_count()),
+            capacity: (api.window_texture_capacity)(),
+        }),
+        _ => Err(ControlError::NotInstalled),
+    }
+}
+
+/// `control::request_buffer_mode` against whichever copy of ssbusync owns the game.
+pub fn remote_request_buffer_mode(
+    mode: crate::render::buffer_swap::BufferMode,
+) -> Result<(), crate::control::ControlError> {
+    let api = remote_api().ok_or(crate::control::ControlError::NotInstalled)?;
+    remote_result(api, (api.request_buffer_mode)(mode as u8), Some(mode))
+}
+
+/// `control::set_online_fix` against whichever copy of ssbusync owns the game.
+pub fn remote_set_online_fix(enabled: bool) -> Result<(), crate::control::ControlError> {
+    let api = remote_api().ok_or(crate::control::ControlError::NotInstalled)?;
+    match (api.set_online_fix)(enabled) {
+        crate::abi::AbiCode::ONLINE_FIX_ALREADY_SET => {
+            Err(crate::control::ControlError::OnlineFixAlreadySet(enabled))
+        }
+        code => remote_result(api, code, None),
+    }
+}
+
+/// `control::effective_config` against whichever copy of ssbusync owns the game.
+pub fn remote_effective_config() -> Result<crate::abi::SsbuSyncAbiConfig, crate::control::ControlError> {
+    let api = remote_api().ok_or(crate::control::ControlError::NotInstalled)?;
+    let mut config = crate::abi::SsbuSyncAbiConfig {
+        size: core::mem::size_of::<crate::abi::SsbuSyncAbiConfig>() as u32,
+        ..Default::default()
+    };
+    let code = unsafe { (api.effective_config)(&mut config) };
+    remote_result(api, code, None).map(|()| config)
+}
+
+/// Subscribes to buffer mode changes made by whichever copy of ssbusync owns the game.
+pub fn remote_subscribe_buffer_mode(
+    callback: crate::abi::BufferModeCallback,
+    user_data: *mut core::ffi::c_void,
+) -> bool {
+    match remote_api() {
+        Some(api) => (api.subscribe_buffer_mode)(callback, user_data) == crate::abi::AbiCode::OK,
+        None => false,
+    }
+}
@@ -0,0 +1,155 @@
+/** Runtime control for plugins that embed ssbusync
+ *
+ * Everything here is safe to call from any thread once `Install_SSBU_Sync` has run.
+ */
+use std::sync::RwLock;
+
+use crate::render::buffer_swap::{self, BufferMode};
+use crate::validation::{Diagnostic, Validation};
+use crate::{SsbuSyncConfig, SyncEnv};
+
+/// Why a control request was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlError {
+    /// `Install_SSBU_Sync` has not run (or refused to patch this game build).
+    NotInstalled,
+    /// Buffer modes are fixed on emulator.
+    NotAllowedOnEmulator,
+    /// The effective config has `allow_buffer_swap = false`.
+    BufferSwapDisabled,
+    /// A swap finished recently; try again in a few frames.
+    Cooldown,
+    /// The game is already running in this mode.
+    AlreadyInMode(BufferMode),
+    /// The online fix is already in the requested state.
+    OnlineFixAlreadySet(bool),
+    /// The NVN window has not been seen yet, or rejected the texture count.
+    WindowNotReady,
+    /// The NVN window was created with fewer textures than the mode needs.
+    InsufficientCapacity { requested: i32, capacity: i32 },
+}
+
+impl std::fmt::Display for ControlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotInstalled => write!(f, "ssbusync is not installed"),
+            Self::NotAllowedOnEmulator => write!(f, "buffer swap not allowed on emulator"),
+            Self::BufferSwapDisabled => write!(f, "allow_buffer_swap is disabled"),
+            Self::Cooldown => write!(f, "buffer swap is cooling down"),
+            Self::AlreadyInMode(mode) => write!(f, "already in {:?} buffer mode", mode),
+            Self::OnlineFixAlreadySet(true) => write!(f, "online fix is already enabled"),
+            Self::OnlineFixAlreadySet(false) => write!(f, "online fix is already disabled"),
+            Self::WindowNotReady => write!(f, "window is not ready for a texture count change"),
+            Self::InsufficientCapacity {
+                requested,
+                capacity,
+            } => write!(
+                f,
+                "window has {} textures but {} were requested",
+                capacity, requested
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+/// Snapshot of what ssbusync is currently doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncStatus {
+    pub installed: bool,
+    pub emulator: bool,
+    pub buffer_mode: BufferMode,
+    pub buffer_swapping: bool,
+    pub allow_buffer_swap: bool,
+    pub online_fix: bool,
+    /// Active window textures, `None` until the window has been seen.
+    pub active_textures: Option<i32>,
+}
+
+static VALIDATION: RwLock<Option<Validation>> = RwLock::new(None);
+
+pub(crate) fn set_validation(validation: Validation) {
+    if let Ok(mut installed) = VALIDATION.write() {
+        *installed = Some(validation);
+    }
+}
+
+/// Swaps in the config a live change (hot reload, scene overrides) left running, keeping the install diagnostics.
+pub(crate) fn set_effective_config(config: SsbuSyncConfig) {
+    if let Ok(mut installed) = VALIDATION.write() {
+        if let Some(validation) = installed.as_mut() {
+            validation.effective = config;
+        }
+    }
+}
+
+fn ensure_installed() -> Result<(), ControlError> {
+    if SyncEnv::installed() {
+        Ok(())
+    } else {
+        Err(ControlError::NotInstalled)
+    }
+}
+
+/// The config ssbusync is running with: the installed one after emulator overrides and `validation::validate`, plus
+/// whatever hot reload and scene overrides have changed since.
+pub fn effective_config() -> Result<SsbuSyncConfig, ControlError> {
+    validation().map(|validation| validation.effective)
+}
+
+/// The install-time validation: the effective config and why it differs from the one passed in.
+pub fn validation() -> Result<Validation, ControlError> {
+    VALIDATION
+        .read()
+        .ok()
+        .and_then(|installed| installed.clone())
+        .ok_or(ControlError::NotInstalled)
+}
+
+/// Shorthand for `validation()?.diagnostics`.
+pub fn diagnostics() -> Result<Vec<Diagnostic>, ControlError> {
+    validation().map(|validation| validation.diagnostics)
+}
+
+pub fn status() -> SyncStatus {
+    SyncStatus {
+        installed: SyncEnv::installed(),
+        emulator: SyncEnv::emulator_value(),
+        buffer_mode: buffer_swap::current_buffer_mode(),
+        buffer_swapping: buffer_swap::is_buffer_swapping(),
+        allow_buffer_swap: SyncEnv::allow_buffer_swap(),
+        online_fix: SyncEnv::online_fix_enabled(),
+        active_textures: buffer_swap::get_active_texture_count(),
+    }
+}
+
+/// Starts a swap to `mode`. The swap finishes over the next few frames; subscribe to
+/// `buffer_swap::subscribe_buffer_mode_change` to be told when it lands.
+///
+/// If another plugin won ownership (see `arbiter`), the request goes to its copy of ssbusync.
+pub fn request_buffer_mode(mode: BufferMode) -> Result<(), ControlError> {
+    if crate::arbiter::lost() {
+        return crate::compatibility::remote_request_buffer_mode(mode);
+    }
+    ensure_installed()?;
+    if SyncEnv::emulator_value() {
+        return Err(ControlError::NotAllowedOnEmulator);
+    }
+    if !SyncEnv::allow_buffer_swap() {
+        return Err(ControlError::BufferSwapDisabled);
+    }
+    buffer_swap::try_start_swap_buffer(mode)
+}
+
+pub fn set_online_fix(enabled: bool) -> Result<(), ControlError> {
+    if crate::arbiter::lost() {
+        return crate::compatibility::remote_set_online_fix(enabled);
+    }
+    ensure_installed()?;
+    if SyncEnv::online_fix_enabled() == enabled {
+        return Err(ControlError::OnlineFixAlreadySet(enabled));
+    }
+    crate::online::ToggleOnlineFix(enabled);
+    Ok(())
+}
@@ -1,13 +1,25 @@
+/** Structured JSON-lines trace of frame events
+ *
+ * The `frametracer` profiling backend. Hooks record markers (a named tick) and frame entries (a named tick that
+ * belongs to a frame number); when a frame finishes, it is written as one line holding all of its entries. Every
+ * boot starts a new `frametrace-NNNN.jsonl` in the configured directory, and the session ends with a summary line when
+ * it hits `profiling_capture_max_seconds` or hot reload turns `profiling` off.
+ */
 use std::{
     collections::BTreeMap,
     fs::File,
-    io::Write,
+    io::{BufWriter, Write},
     sync::{
         mpsc::{Receiver, Sender},
         OnceLock,
     },
 };
 
+use camino::Utf8PathBuf;
+use nprof::stats::TICK_FREQUENCY;
+
+use crate::SsbuSyncConfig;
+
 pub type Tick = i64;
 
 struct FrameElement {
@@ -26,17 +38,26 @@ enum Element {
     Frame(FrameElement),
     Marker(MarkerElement),
     FinishFrame(usize),
+    Finish,
 }
 
 static SUBMISSION_CHANNEL: OnceLock<Sender<Element>> = OnceLock::new();
 
-pub fn initialize() {
+/// Starts the session thread. Later calls leave the running session as it is.
+pub fn initialize(config: &SsbuSyncConfig) {
+    if SUBMISSION_CHANNEL.get().is_some() {
+        tracing::debug!(target: "frametracer", "already initialized.");
+        return;
+    }
+    let dir = crate::Config::frametracer_dir();
+    let max_ticks = config.profiling_capture_max_seconds as i64 * TICK_FREQUENCY;
+
     let (sender, receiver) = std::sync::mpsc::channel();
-    SUBMISSION_CHANNEL
-        .set(sender)
-        .expect("Only call frametracer::initialize() once");
+    if SUBMISSION_CHANNEL.set(sender).is_err() {
+        return;
+    }
 
-    let _ = std::thread::spawn(move || frametracer_thread(receiver));
+    let _ = std::thread::spawn(move || frametracer_thread(receiver, dir, max_ticks));
 }
 
 pub fn marker(name: &'static str, tick: Tick) {
@@ -61,20 +82,122 @@ pub fn finish_frame(frame: usize) {
     }
 }
 
-fn frametracer_thread(receiver: Receiver<Element>) {
-    #[derive(serde::Serialize)]
-    struct NamedTick {
-        name: &'static str,
-        tick: Tick,
+/// Writes the summary and closes the session file. Anything recorded afterwards is ignored. Hot reload calls this when
+/// `profiling` is turned off.
+pub fn finish() {
+    if let Some(channel) = SUBMISSION_CHANNEL.get() {
+        let _ = channel.send(Element::Finish);
     }
+}
 
-    let mut frames_in_flight: BTreeMap<usize, Vec<NamedTick>> = BTreeMap::new();
+#[derive(serde::Serialize)]
+struct NamedTick {
+    name: &'static str,
+    tick: Tick,
+}
+
+#[derive(serde::Serialize, Default)]
+struct Summary {
+    frames: u64,
+    markers: u64,
+    /// Frames that got entries but were never finished.
+    unfinished_frames: usize,
+    first_tick: Option<Tick>,
+    last_tick: Option<Tick>,
+    duration_ms: f64,
+    /// Why the session ended: `finish`, `max_duration`, `write_error` or `disconnected`.
+    reason: &'static str,
+}
+
+struct Session {
+    path: Utf8PathBuf,
+    file: BufWriter<File>,
+    summary: Summary,
+    last_flush: Tick,
+    /// 0 for no limit.
+    max_ticks: Tick,
+}
+
+impl Session {
+    fn open(dir: &Utf8PathBuf, max_ticks: Tick) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir.as_std_path())?;
+        let session = crate::util::file::paths::next_session(dir, "frametrace-");
+        let path = dir.join(format!("frametrace-{:04}.jsonl", session));
+        let file = File::create(path.as_std_path())?;
+        Ok(Self {
+            path,
+            file: BufWriter::with_capacity(32 * 1024, file),
+            summary: Summary::default(),
+            last_flush: 0,
+            max_ticks,
+        })
+    }
+
+    /// Tracks the session's time span. Returns false once it has run longer than the limit.
+    fn observe(&mut self, tick: Tick) -> bool {
+        let first = *self.summary.first_tick.get_or_insert(tick);
+        self.summary.last_tick = Some(self.summary.last_tick.map_or(tick, |last| last.max(tick)));
+        self.max_ticks == 0 || tick - first <= self.max_ticks
+    }
+
+    fn write_line(&mut self, value: &impl serde::Serialize, tick: Tick) -> bool {
+        let Ok(mut line) = serde_json::to_string(value) else {
+            return true;
+        };
+        line.push('\n');
+        if self.file.write_all(line.as_bytes()).is_err() {
+            return false;
+        }
+        // Flush about once a second rather than every line.
+        if tick - self.last_flush >= TICK_FREQUENCY {
+            let _ = self.file.flush();
+            self.last_flush = tick;
+        }
+        true
+    }
 
-    let mut file = File::create("sd:/frametrace.jsonl").unwrap();
+    fn close(mut self, reason: &'static str, unfinished_frames: usize) {
+        #[derive(serde::Serialize)]
+        struct SummaryEntry<'a> {
+            summary: &'a Summary,
+        }
 
-    loop {
+        self.summary.reason = reason;
+        self.summary.unfinished_frames = unfinished_frames;
+        if let (Some(first), Some(last)) = (self.summary.first_tick, self.summary.last_tick) {
+            self.summary.duration_ms = (last - first) as f64 * 1000.0 / TICK_FREQUENCY as f64;
+        }
+        if let Ok(mut line) = serde_json::to_string(&SummaryEntry { summary: &self.summary }) {
+            line.push('\n');
+            let _ = self.file.write_all(line.as_bytes());
+        }
+        let _ = self.file.flush();
+        tracing::info!(
+            target: "frametracer",
+            "closed {} ({}): {} frames, {} markers.",
+            self.path, reason, self.summary.frames, self.summary.markers
+        );
+    }
+}
+
+fn frametracer_thread(receiver: Receiver<Element>, dir: Utf8PathBuf, max_ticks: Tick) {
+    let mut session = match Session::open(&dir, max_ticks) {
+        Ok(session) => session,
+        Err(err) => {
+            tracing::warn!(target: "frametracer", "could not create a trace in {}: {}", dir, err);
+            return;
+        }
+    };
+    tracing::info!(target: "frametracer", "writing {}", session.path);
+
+    let mut frames_in_flight: BTreeMap<usize, Vec<NamedTick>> = BTreeMap::new();
+
+    let reason = loop {
         match receiver.recv() {
             Ok(Element::Frame(frame)) => {
+                if !session.observe(frame.tick) {
+                    break "max_duration";
+                }
                 frames_in_flight
                     .entry(frame.frame_index)
                     .or_default()
@@ -84,10 +207,12 @@ fn frametracer_thread(receiver: Receiver<Element>) {
                     });
             }
             Ok(Element::Marker(marker)) => {
-                if let Ok(mut line) = serde_json::to_string(&marker) {
-                    line.push('\n');
-                    let _ = file.write_all(line.as_bytes());
-                    let _ = file.flush();
+                if !session.observe(marker.tick) {
+                    break "max_duration";
+                }
+                session.summary.markers += 1;
+                if !session.write_line(&marker, marker.tick) {
+                    break "write_error";
                 }
             }
             Ok(Element::FinishFrame(frame)) => {
@@ -101,13 +226,16 @@ fn frametracer_thread(receiver: Receiver<Element>) {
                     frame,
                     elements: frames_in_flight.remove(&frame).unwrap_or_default(),
                 };
-                if let Ok(mut line) = serde_json::to_string(&frame) {
-                    line.push('\n');
-                    let _ = file.write_all(line.as_bytes());
-                    let _ = file.flush();
+                let tick = frame.elements.last().map_or(session.last_flush, |element| element.tick);
+                session.summary.frames += 1;
+                if !session.write_line(&frame, tick) {
+                    break "write_error";
                 }
             }
-            Err(_error) => break,
+            Ok(Element::Finish) => break "finish",
+            Err(_error) => break "disconnected",
         }
-    }
+    };
+
+    session.close(reason, frames_in_flight.len());
 }
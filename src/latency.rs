@@ -0,0 +1,83 @@
+/** Live present latency statistics
+ *
+ * With `latency_stats` on, every presented record from `nn::vi::ListFrameHistory` goes through `nprof::latency`'s
+ * `LatencyStats`, which keeps rolling aggregates over the last `latency_window_frames` presented frames. Read them with
+ * `summary()` from any thread. When the nprof profiler is running they are also sent down its stream every
+ * `PROFILER_INTERVAL` frames, and a scene change starts the window over.
+ */
+use std::sync::Mutex;
+
+pub use nprof::latency::{LatencyStats, PresentedFrame};
+pub use nprof::stats::Summary;
+pub use nprof::wire::LatencySummary;
+
+use crate::vsync_history::{FrameInfo, FrameStatus};
+use crate::{SsbuSyncConfig, SyncEnv};
+
+/// Presented frames between summaries sent to the profiler, about once a second.
+const PROFILER_INTERVAL: u32 = 60;
+
+struct Live {
+    stats: LatencyStats,
+    since_sent: u32,
+}
+
+static LIVE: Mutex<Option<Live>> = Mutex::new(None);
+
+/// Feeds a frame history record. Records that aren't newly presented frames are ignored.
+pub(crate) fn observe(info: &FrameInfo) {
+    if info.status != FrameStatus::Presented {
+        return;
+    }
+    let summary = match LIVE.lock() {
+        Ok(mut live) => live.as_mut().and_then(|live| {
+            let fed = live.stats.feed(PresentedFrame {
+                frame_number: info.frame_number,
+                enqueue: info.enqueue_time,
+                present: info.present_time,
+                vblank: info.vblank_time,
+            });
+            if !fed {
+                return None;
+            }
+            live.since_sent += 1;
+            if live.since_sent < PROFILER_INTERVAL {
+                return None;
+            }
+            live.since_sent = 0;
+            live.stats.summary()
+        }),
+        Err(_) => None,
+    };
+    if let Some(summary) = summary {
+        crate::profiling::latency(summary);
+    }
+}
+
+/// Aggregates over the recent presented frames. `None` when `latency_stats` is off or nothing has been presented yet.
+pub fn summary() -> Option<LatencySummary> {
+    LIVE.lock().ok()?.as_ref()?.stats.summary()
+}
+
+/// Starts the window over, e.g. after a scene change where gaps are expected.
+pub fn reset() {
+    if let Ok(mut live) = LIVE.lock() {
+        if let Some(live) = live.as_mut() {
+            live.stats.reset();
+            live.since_sent = 0;
+        }
+    }
+}
+
+pub fn install(config: &SsbuSyncConfig) {
+    if !config.latency_stats {
+        return;
+    }
+    if let Ok(mut live) = LIVE.lock() {
+        *live = Some(Live {
+            stats: LatencyStats::new(config.latency_window_frames as usize),
+            since_sent: 0,
+        });
+    }
+    SyncEnv::set_frame_history(true);
+}
@@ -1,70 +1,46 @@
 #![allow(warnings)]
-use std::sync::atomic::{Ordering};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::io;
 use skyline::error::*;
 use skyline::nro::{self, NroInfo};
+mod frametracer;
+mod logging;
 mod off_by_one;
 mod pacer;
+mod patches;
 mod profiling;
+mod report;
 mod sequencing;
 mod swapchain;
 mod util;
+mod watchdog;
 //mod vsync;
 mod vsync_history;
 
+pub mod abi;
+pub mod arbiter;
 pub mod online;
 pub mod render;
 pub mod compatibility;
+pub mod control;
+pub mod latency;
+pub mod overrides;
+pub mod reload;
+pub mod validation;
 #[cfg(feature = "nro-entry")]
 use crate::Config::DefaultProfileState;
+pub use util::migrate::{FieldChange, Migration, ProfileVersion};
 pub use crate::util::env as SyncEnv;
 pub use crate::util::file::config as Config;
 
 use render::buffer_swap::*;
 use swapchain::*;
 use symbaker::symbaker;
-use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "nro-entry")]
 use crate::compatibility::SSBUSyncHost::*;
 
-
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-#[serde(default)]
-#[non_exhaustive]
-pub struct SsbuSyncConfig {
-    pub disable_vsync: bool,
-    pub disable_pacer: bool,
-    pub slow_pacer_bias: bool,
-    pub enable_triple_buffer: bool,
-    pub allow_buffer_swap: bool,
-    pub smooth_ffa: bool,
-    pub online_only: bool,
-    pub profiling: bool,
-    #[serde(skip)]
-    pub emulator_check: bool,
-    #[serde(skip)]
-    pub override_config: bool,
-}
-
-impl Default for SsbuSyncConfig {
-    fn default() -> Self {
-        Self {
-            disable_vsync: true,
-            disable_pacer: false,
-            slow_pacer_bias: false,
-            enable_triple_buffer: true,
-            allow_buffer_swap: false,
-            smooth_ffa: false,
-            online_only: false,
-            profiling: false,
-            emulator_check: false,
-            override_config: false,
-        }
-    }
-}
-
-impl SsbuSyncConfig {}
+pub use ncore::config::{LogLevel, LogOutput, ProfilingBackend, ProfilingSink, SsbuSyncConfig};
 
 pub fn emulator_status() -> bool {
     if SyncEnv::emulator_known() {
@@ -83,10 +59,10 @@ pub fn is_emulator() -> bool {
     unsafe {
         let text_addr = skyline::hooks::getRegionAddress(skyline::hooks::Region::Text) as u64;
         if text_addr == 0x8504000 || text_addr == 0x80004000 {
-            println!("we are on Emulator");
+            tracing::debug!(target: "install", "we are on Emulator");
             return true;
         } else {
-            println!("we are not on Emulator");
+            tracing::debug!(target: "install", "we are not on Emulator");
             return false;
         }
     }
@@ -118,95 +94,180 @@ pub extern "C" fn ssbusync_status() -> u32 {
 /// Example TOML:
 /// ```toml
 /// [SsbuSync.Default]
-/// profile_version = 1.0
+/// profile_version = "1.0.0"
 /// disable_vsync = true
 /// disable_pacer = false
 /// slow_pacer_bias = false
+/// pacer_bias_double = 1
+/// pacer_bias_triple = 1
 /// enable_triple_buffer = false
 /// allow_buffer_swap: false,
 /// smooth_ffa: false,
 /// profiling = false
+/// profiling_sink = "file"
+/// profiling_backend = "both"
+/// log_level = "debug"
+/// log_output = "both"
+/// hot_reload = true
 ///
 /// [SsbuSync.HDR]
-/// profile_version = 1.1
+/// profile_version = "1.1.0"
 /// disable_vsync = true
+///
+/// [Frametracer]
+/// dir = "sd:/ultimate/ssbusync/traces"
 /// ```
 ///
-/// If `version` is higher than the stored `profile_version`, the profile is
-/// overwritten with `defaults` and the new version is written to disk.
+/// `version` is a semver string (`"1.2.0"`), a `semver::Version`, or the old float (`1.1`, read as `1.100.0`). If it
+/// is newer than the stored `profile_version`, fields missing from the profile are added from `defaults` and the
+/// user's values are kept; the previous file is copied to `ssbusync.toml.bak` first.
+///
+/// A float `x.y` stands for `x.(y * 1000).0`, so a plugin switching from floats to semver keeps counting from there:
+/// after `1.2` comes `"1.201.0"` or `"2.0.0"`. A semver version below the float a profile still stores (`"1.3.0"`
+/// after `1.2`) upgrades nothing and is logged under the `config` target.
 pub fn Get_Init_SsbuSync_Profile(
 plugin_name: &str,
 defaults: &SsbuSyncConfig,
-version: f32,) -> io::Result<SsbuSyncConfig> {
-    return Config::get_or_make_profile(plugin_name, defaults, version);
+version: impl ProfileVersion,) -> io::Result<SsbuSyncConfig> {
+    return Get_Init_SsbuSync_Profile_With_Migrations(plugin_name, defaults, version, &[]);
+}
+
+/// Like `Get_Init_SsbuSync_Profile`, running `migrations` newer than the stored version (up to `version`) before
+/// filling in defaults, so renamed or retyped fields keep the user's value.
+pub fn Get_Init_SsbuSync_Profile_With_Migrations(
+    plugin_name: &str,
+    defaults: &SsbuSyncConfig,
+    version: impl ProfileVersion,
+    migrations: &[Migration],
+) -> io::Result<SsbuSyncConfig> {
+    let Some(version) = version.profile_version() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "profile version is not a valid semver version",
+        ));
+    };
+    Config::get_or_make_profile(plugin_name, defaults, &version, migrations)
 }
 
+/// Installs straight away. If another plugin already owns ssbusync this logs who does and does nothing.
 pub fn Install_SSBU_Sync(config: SsbuSyncConfig) {
+    logging::init();
+    if let Err(err) = arbiter::claim_now("Install_SSBU_Sync") {
+        match arbiter::winner() {
+            Some(_) if err == arbiter::ArbiterError::AlreadyInstalled => {
+                tracing::info!(target: "install", "not installing again: {}.", err)
+            }
+            Some(winner) => tracing::info!(
+                target: "install",
+                "not installing: {} (priority {}) already owns ssbusync.",
+                winner.name, winner.priority
+            ),
+            None => tracing::info!(target: "install", "not installing: {}", err),
+        }
+        return;
+    }
+    install_unchecked(config);
+}
+
+/** Installs through the ownership arbiter
+ *
+ * Every plugin that embeds ssbusync registers with a name and priority; once all plugins have loaded the highest
+ * priority installs and the rest log who won. Losing plugins can keep using `control` (forwarded to the winner) and
+ * `compatibility::remote_*`.
+ */
+pub fn Install_SSBU_Sync_With_Priority(
+    name: &'static str,
+    priority: u32,
+    config: SsbuSyncConfig,
+) -> Result<(), arbiter::ArbiterError> {
+    logging::init();
+    arbiter::contend(name, priority, config)
+}
+
+/// Set by the first `install_unchecked`; hooks can't be removed, so a second install would stack on the first.
+static INSTALL_STARTED: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn install_unchecked(config: SsbuSyncConfig) {
+    logging::init();
+    if INSTALL_STARTED.swap(true, Ordering::AcqRel) {
+        tracing::warn!(target: "install", "ssbusync is already installed; ignoring the second install.");
+        return;
+    }
+    logging::configure(&config);
     Config::load_or_create();
     #[cfg(feature = "nro-entry")]
     {
-        Get_Init_SsbuSync_Profile("Default", &config, 0.15);
-        println!("[ssbusync] Main SsbuSync Module Installing. \n");
+        Get_Init_SsbuSync_Profile("Default", &config, Config::DEFAULT_PROFILE_VERSION);
+        tracing::info!(target: "install", "Main SsbuSync Module Installing.");
+    }
+    if let Err(err) = util::offsets::load() {
+        tracing::error!(target: "install", "{}; refusing to patch this game build.", err);
+        #[cfg(feature = "nro-entry")]
+        set_disabled();
+        return;
     }
-    let mut cfg = config.clone();
-    cfg.emulator_check = is_emulator();
-    if cfg.emulator_check {
-        println!("[ssbusync] Emulator Detected. \n");
+    let mut requested = config.clone();
+    requested.emulator_check = is_emulator();
+    if requested.emulator_check {
+        tracing::info!(target: "install", "Emulator Detected.");
     }
+    let validation = validation::validate(&requested);
+    validation.log();
+    let cfg = validation.effective;
     SyncEnv::set_emulator_value(cfg.emulator_check);
     SyncEnv::set_allow_buffer_swap(cfg.allow_buffer_swap);
 
+    if let Err(err) = patch_all(cfg) {
+        tracing::error!(target: "install", "{}; rolled back every patch and not installing.", err);
+        #[cfg(feature = "nro-entry")]
+        set_disabled();
+        return;
+    }
+
     if cfg.profiling {
-        profiling::setup();
+        if cfg.profiling_backend.nprof() {
+            profiling::setup(&cfg);
+            // Presented frames and vblanks come from the frame history.
+            SyncEnv::set_frame_history(true);
+        }
+        if cfg.profiling_backend.frametracer() {
+            frametracer::initialize(&cfg);
+            SyncEnv::set_frame_history(true);
+        }
     }
 
     vsync_history::install(cfg);
     swapchain::install(cfg);
     off_by_one::install();
-    pacer::install(cfg);
-    
+    render::policy::install(&cfg);
+    watchdog::install(&cfg);
+    latency::install(&cfg);
+
+    report::write(&requested, &validation);
+    control::set_validation(validation);
+    let profile = Config::matching_loaded_profile(&requested);
+    if profile.is_none() {
+        tracing::info!(target: "install", "config doesn't match a loaded profile; hot reload and overrides are off.");
+    }
+    overrides::install(&cfg, profile.as_ref().map(|profile| profile.name.as_str()));
+    reload::install(&cfg, profile);
+    SyncEnv::set_installed(true);
+    #[cfg(feature = "nro-entry")]
+    set_installed();
 }
 
-// #[cfg(not(feature = "nro-entry"))]
-// pub fn Enable_Online_Fix() {
-
-// }
-
-// #[cfg(not(feature = "nro-entry"))]
-// pub fn Disable_Online_Fix() {
-
-// }
-
-// #[cfg(not(feature = "nro-entry"))]
-// pub fn Enable_Double_Buffer() {
-//     let allow_buffer_swap = (!is_emulator() && SyncEnv::allow_buffer_swap());
-//     if allow_buffer_swap {
-//     start_swap_buffer(BufferMode::Double);
-//     } else {
-//         println!("[ssbusync] Swapping Buffer Mode Not Allowed!");
-//     }
-// }
-
-// #[cfg(not(feature = "nro-entry"))]
-// pub fn Enable_Triple_Buffer() {
-//     let allow_buffer_swap = (!is_emulator() && SyncEnv::allow_buffer_swap());
-//     if allow_buffer_swap {
-//     start_swap_buffer(BufferMode::Triple);
-//     } else {
-//         println!("[ssbusync] Swapping Buffer Mode Not Allowed!");
-//     }
-// }
-
-// #[cfg(not(feature = "nro-entry"))]
-// pub fn Check_Buffer_Swap() {
-//     render::buffer_swap::check_swap_finished();
-// }
+/// Writes every patch before any hook goes in, so a conflict with another plugin leaves the game as it was.
+fn patch_all(cfg: SsbuSyncConfig) -> Result<(), patches::PatchError> {
+    let mut registry = patches::registry();
+    let mut transaction = patches::Transaction::begin(&mut registry);
+    vsync_history::patch(cfg, &mut transaction)?;
+    swapchain::patch(cfg, &mut transaction)?;
+    off_by_one::patch(&mut transaction)?;
+    pacer::patch(cfg, &mut transaction)?;
+    transaction.commit();
+    Ok(())
+}
 
-// #[cfg(feature = "nro-entry")]
-// pub fn Check_Ssbusync_Disabled() -> bool {
-//     // is_disabled()
-// }
-    
 pub fn is_doubles_fix_enabled() -> bool {
     let allow_buffer_swap = (!is_emulator() && SyncEnv::allow_buffer_swap() == true);
     return allow_buffer_swap;
@@ -218,26 +279,28 @@ fn try_install() {
         return;
     }
     
-    if try_claim_install() {
-        println!("[ssbusync] ssbusync.nro installing");
-        let config = match Config::load_or_create() {
-            Ok((config, DefaultProfileState::Created)) => {
-                println!("[ssbusync] Created new Default profile in ssbusync.toml.");
-                config
-            }
-            Ok((config, DefaultProfileState::Loaded)) => {
-                println!("[ssbusync] Loaded existing Default profile.");
-                config
-            }
-            Err(err) => {
-                println!(
-                    "[ssbusync] Failed to load Default profile ({}). Using built-in defaults.",
-                    err
-                );
-                SsbuSyncConfig::default()
-            }
-        };
-        Install_SSBU_Sync(config);
+    // Only registers; `STATUS` becomes INSTALLED once the election has picked us and `install_unchecked` ran.
+    tracing::info!(target: "install", "ssbusync.nro registering for install");
+    let config = match Config::load_or_create() {
+        Ok((config, DefaultProfileState::Created)) => {
+            tracing::info!(target: "install", "Created new Default profile in ssbusync.toml.");
+            config
+        }
+        Ok((config, DefaultProfileState::Loaded)) => {
+            tracing::info!(target: "install", "Loaded existing Default profile.");
+            config
+        }
+        Err(err) => {
+            tracing::warn!(
+                target: "install",
+                "Failed to load Default profile ({}). Using built-in defaults.",
+                err
+            );
+            SsbuSyncConfig::default()
+        }
+    };
+    if Install_SSBU_Sync_With_Priority("ssbusync.nro", arbiter::STANDALONE_PRIORITY, config).is_err() {
+        set_claimed();
     }
 }
 
@@ -298,6 +361,7 @@ fn panic_hook() {
 #[cfg(feature = "nro-entry")]
 #[skyline::main(name = "ssbusync")]
 pub fn main() {
+    logging::init();
     panic_hook();
     // register_nro_hook();
     if !should_skip_install() {
@@ -307,7 +371,7 @@ pub fn main() {
         }
         if compatibility::check_external_disabler() {
             set_disabled();
-            println!("[ssbusync] external symbol disabler detected; skipping install.");
+            tracing::info!(target: "install", "external symbol disabler detected; skipping install.");
             return;
         }
     }
@@ -0,0 +1,186 @@
+/** `tracing` subscriber for ssbusync's own logs
+ *
+ * Every module logs through `tracing` with a short target (`config`, `disablers`, `swapchain`, `buffer_swap`,
+ * `compatibility`, ...). This subscriber prints each event as `[ssbusync][target] LEVEL message`, to the skyline log,
+ * to `sd:/ultimate/ssbusync/ssbusync.log`, or both. The file rotates once it passes `MAX_FILE_BYTES`, keeping
+ * `KEPT_FILES` old copies as `ssbusync.1.log` (newest) and up.
+ *
+ * `log_level` and `log_output` come from the profile in `ssbusync.toml` and are applied by `configure`, so they can
+ * change after the subscriber is installed. Until then everything at `info` and above goes to the skyline log.
+ */
+use std::{
+    fmt::{self, Write as _},
+    fs::File,
+    io::Write,
+    sync::{
+        atomic::{AtomicU8, AtomicU64, Ordering},
+        Mutex, Once,
+    },
+};
+
+use camino::Utf8PathBuf;
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Level, Metadata, Subscriber,
+};
+
+use crate::{LogLevel, LogOutput, SsbuSyncConfig};
+
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+const KEPT_FILES: u32 = 3;
+
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+static OUTPUT: AtomicU8 = AtomicU8::new(LogOutput::Skyline as u8);
+static FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+static NEXT_SPAN: AtomicU64 = AtomicU64::new(1);
+
+/// Installs the subscriber as the global default. Does nothing if the host plugin already installed its own, in
+/// which case our events go to that one instead.
+/// Safe to call more than once.
+pub fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let _ = tracing::subscriber::set_global_default(SsbuSyncSubscriber);
+    });
+}
+
+/// Applies `log_level` and `log_output` from a profile.
+pub fn configure(config: &SsbuSyncConfig) {
+    LEVEL.store(config.log_level as u8, Ordering::Release);
+    OUTPUT.store(config.log_output as u8, Ordering::Release);
+}
+
+fn level_enabled(level: &Level) -> bool {
+    let wanted = match *level {
+        Level::ERROR => LogLevel::Error,
+        Level::WARN => LogLevel::Warn,
+        Level::INFO => LogLevel::Info,
+        Level::DEBUG => LogLevel::Debug,
+        Level::TRACE => LogLevel::Trace,
+    };
+    wanted as u8 <= LEVEL.load(Ordering::Acquire)
+}
+
+fn log_path(index: u32) -> Utf8PathBuf {
+    let dir = crate::util::file::paths::ssbusync();
+    if index == 0 {
+        dir.join("ssbusync.log")
+    } else {
+        dir.join(format!("ssbusync.{}.log", index))
+    }
+}
+
+struct LogFile {
+    file: File,
+    written: u64,
+}
+
+impl LogFile {
+    fn open() -> std::io::Result<Self> {
+        crate::util::file::paths::ensure_paths_exist()?;
+        let path = log_path(0);
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_std_path())?;
+        let written = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+        Ok(Self { file, written })
+    }
+
+    /// Shifts `ssbusync.log` to `ssbusync.1.log` and so on, dropping the oldest, then starts a new file.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let _ = self.file.flush();
+        let _ = std::fs::remove_file(log_path(KEPT_FILES).as_std_path());
+        for index in (0..KEPT_FILES).rev() {
+            let _ = std::fs::rename(log_path(index).as_std_path(), log_path(index + 1).as_std_path());
+        }
+        *self = Self::open()?;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.written >= MAX_FILE_BYTES && self.rotate().is_err() {
+            return;
+        }
+        if self.file.write_all(line.as_bytes()).is_ok() {
+            self.written += line.len() as u64;
+        }
+    }
+}
+
+fn write_to_file(line: &str) {
+    // A log call from inside the file code would deadlock here, so those are dropped instead.
+    let Ok(mut file) = FILE.try_lock() else {
+        return;
+    };
+    if file.is_none() {
+        match LogFile::open() {
+            Ok(opened) => *file = Some(opened),
+            Err(_) => return,
+        }
+    }
+    if let Some(file) = file.as_mut() {
+        file.write_line(line);
+    }
+}
+
+/// Collects an event's message and any extra fields as `key=value`.
+struct Line(String);
+
+impl Visit for Line {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.0.push_str(value);
+        } else {
+            let _ = write!(self.0, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, "{:?}", value);
+        } else {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+struct SsbuSyncSubscriber;
+
+impl Subscriber for SsbuSyncSubscriber {
+    fn register_callsite(&self, _metadata: &'static Metadata<'static>) -> tracing::subscriber::Interest {
+        // The level can change at runtime, so every callsite is asked each time rather than cached.
+        tracing::subscriber::Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        level_enabled(metadata.level())
+    }
+
+    fn new_span(&self, _span: &span::Attributes<'_>) -> span::Id {
+        span::Id::from_u64(NEXT_SPAN.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let metadata = event.metadata();
+        let mut line = Line(format!("[ssbusync][{}] {} ", metadata.target(), metadata.level()));
+        event.record(&mut line);
+        let line = line.0;
+
+        let output = OUTPUT.load(Ordering::Acquire);
+        if output != LogOutput::File as u8 {
+            println!("{}", line);
+        }
+        if output != LogOutput::Skyline as u8 {
+            write_to_file(&(line + "\n"));
+        }
+    }
+
+    fn enter(&self, _span: &span::Id) {}
+
+    fn exit(&self, _span: &span::Id) {}
+}
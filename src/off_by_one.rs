@@ -1,22 +1,25 @@
 use skyline::hooks::InlineCtx;
 use symbaker::symbaker;
 
+use crate::patches::{self, PatchError, PatchSpec, Transaction};
+use crate::util::offsets::offsets;
+
 /** Updates the models of every battle object to match their animation
  *
  * We have to call this manually, normally it's called from TaskWorker2
  */
-#[skyline::from_offset(0x3ab590)]
+#[skyline::from_offset(offsets().update_models)]
 fn update_models(p: *const ());
 
 /** This initializes a task worker, I'm not sure what the arguments do entirely but I believe that one of them
  * is a TlsSlot
  */
-#[skyline::from_offset(0x3549170)]
+#[skyline::from_offset(offsets().initialize_task_worker)]
 unsafe fn initialize_task_worker(ptr: *mut u8, func: *const (), arg1: u32, arg2: i32);
 
 /** I believe this waits on a task worker to finish running, but I'm not entirely sure
  */
-#[skyline::from_offset(0x354c720)]
+#[skyline::from_offset(offsets().wait_task_worker)]
 unsafe fn wait_task_worker(arg1: u32, arg2: *mut u32);
 
 unsafe fn start_task_worker_queue(pointer: *mut (), function_ptr: *const ()) {
@@ -71,7 +74,7 @@ unsafe fn start_task_worker_queue(pointer: *mut (), function_ptr: *const ()) {
  * it's decently fast but I don't know
  */
 #[symbaker]
-#[skyline::hook(offset = 0x374c7b4, inline)]
+#[skyline::hook(offset = offsets().post_scene_update, inline)]
 pub unsafe fn post_scene_update_submit_render(ctx: &InlineCtx) {
     // SAFETY: These are basically local variables or global constants, and we cache them so that we don't have to fetch them every time from the skyline API
     static mut P_BATTLE_OBJECT_MANAGER: *const *const () = std::ptr::null();
@@ -87,14 +90,14 @@ pub unsafe fn post_scene_update_submit_render(ctx: &InlineCtx) {
     if !DID_INIT {
         DID_INIT = true;
         let base = skyline::hooks::getRegionAddress(skyline::hooks::Region::Text).cast::<u8>();
-        P_BATTLE_OBJECT_MANAGER = base.add(0x5332120).cast::<*const ()>();
-        P_TASK_WORKER_QUEUE = base.add(0x5332558).cast();
-        SUBMIT_COMMANDS_FN_PTR = base.add(0x3548240).cast();
-        EFF_RENDER_COMMANDS_FN_PTR = base.add(0x374d270).cast();
-        UI_RENDER_COMMANDS_FN_PTR = base.add(0x374d550).cast();
-        FIGHTER_RENDER_COMMANDS_FN_PTR = base.add(0x374f050).cast();
-        TLS_SLOT_ARRAY_START = base.add(0x5332f58).cast();
-        SYNC_WAIT = Some(*base.add(0x5940878).cast::<extern "C" fn(u64, u64) -> u64>());
+        P_BATTLE_OBJECT_MANAGER = base.add(offsets().battle_object_manager).cast::<*const ()>();
+        P_TASK_WORKER_QUEUE = base.add(offsets().task_worker_queue).cast();
+        SUBMIT_COMMANDS_FN_PTR = base.add(offsets().submit_commands).cast();
+        EFF_RENDER_COMMANDS_FN_PTR = base.add(offsets().eff_render_commands).cast();
+        UI_RENDER_COMMANDS_FN_PTR = base.add(offsets().ui_render_commands).cast();
+        FIGHTER_RENDER_COMMANDS_FN_PTR = base.add(offsets().fighter_render_commands).cast();
+        TLS_SLOT_ARRAY_START = base.add(offsets().tls_slot_array).cast();
+        SYNC_WAIT = Some(*base.add(offsets().nvn_sync_wait).cast::<extern "C" fn(u64, u64) -> u64>());
     }
 
     if !(*P_BATTLE_OBJECT_MANAGER).is_null() {
@@ -143,10 +146,12 @@ pub unsafe fn post_scene_update_submit_render(ctx: &InlineCtx) {
  *
  * This is an inlined call to an equivalent of `start_task_worker_queue`, which we reimplement for `post_scene_update_submit_render`
  */
-fn prevent_render_dispatch_signal() {
-    skyline::patching::Patch::in_text(0x374c054)
-        .data(0x14000029u32)
-        .unwrap();
+fn prevent_render_dispatch_signal(transaction: &mut Transaction) -> Result<(), PatchError> {
+    transaction.apply(PatchSpec::data(
+        "render_dispatch_signal",
+        offsets().render_dispatch_signal,
+        0x14000029u32,
+    ))
 }
 
 /** This patches a vtable function pointer that calls BattleObjectManager::UpdateObjectModels
@@ -155,28 +160,36 @@ fn prevent_render_dispatch_signal() {
  * so they cannot be moved. Instead of moving the task worker updates, we just stub that function and call it ourself in
  * `post_scene_update_submit_render`
  */
-fn prevent_task_worker_updating_models() {
+fn prevent_task_worker_updating_models(transaction: &mut Transaction) -> Result<(), PatchError> {
     extern "C" fn stub() {}
 
-    skyline::patching::Patch::in_text(0x4f623d0)
-        .data(stub as *const () as u64)
-        .unwrap();
+    transaction.apply(PatchSpec::pointer(
+        "update_object_models_vtable",
+        offsets().update_object_models_vtable,
+        stub as *const () as u64,
+    ))
 }
 
 /** This prevents adding fighter render command recording tasks to the task worker
  *
  * By default this is called before updating the scene's state for the frame. We do this manually in `post_scene_update_submit_render`
  */
-fn prevent_fighter_render_command_recording() {
-    skyline::patching::Patch::in_text(0x374b554).nop().unwrap();
+fn prevent_fighter_render_command_recording(transaction: &mut Transaction) -> Result<(), PatchError> {
+    transaction.apply(PatchSpec::nop(
+        "fighter_render_command_recording",
+        offsets().fighter_render_command_recording,
+    ))
 }
 
 // TODO: Patch VFX
 
-pub fn install() {
-    prevent_render_dispatch_signal();
-    prevent_task_worker_updating_models();
-    prevent_fighter_render_command_recording();
+/// Writes this module's patches; `install` puts the hook in once every module's patches went in.
+pub fn patch(transaction: &mut Transaction) -> Result<(), PatchError> {
+    prevent_render_dispatch_signal(transaction)?;
+    prevent_task_worker_updating_models(transaction)?;
+    prevent_fighter_render_command_recording(transaction)
+}
 
-    skyline::install_hook!(post_scene_update_submit_render);
+pub fn install() {
+    patches::install_hooks!(post_scene_update_submit_render);
 }
@@ -1,6 +1,10 @@
 use crate::SyncEnv;
 
 pub fn ToggleOnlineFix(toggle: bool) {
+        // Nothing to toggle when install was refused for this game build.
+        if crate::util::offsets::loaded().is_none() {
+            return;
+        }
         if SyncEnv::emulator_value() {
             EmuNetplay::toggle_online_fix_emu(toggle);
         } else {
@@ -0,0 +1,257 @@
+/** Live per-stage, per-mode and per-scene overrides
+ *
+ * The override tables and how they layer are in `ncore::overrides`. ssbusync follows the scene and stage itself: every
+ * presented frame it asks the game's `FighterManager` whether a match is running (scene `battle`, with the stage from
+ * `app::stage::get_stage_id`) or showing its results (scene `results`). The game mode isn't detected. A plugin can
+ * report any of the three with `report_scene`, `report_mode` and `report_stage`, the same way it reports the match
+ * load to the buffer policy; a reported value wins over the detected one until the plugin reports `None` again.
+ *
+ * When the context changes, the matching tables are layered onto the base profile and the result is applied live like
+ * a hot reload on the next presented frame.
+ */
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use crate::{reload, validation, Config, SsbuSyncConfig};
+
+pub use ncore::overrides::{parse, ConfigPatch, Context, GameMode, Overrides};
+
+struct State {
+    profile: Option<String>,
+    tables: Overrides,
+    /// The profile's own settings, which the tables are layered on.
+    base: SsbuSyncConfig,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+/// What the plugin reported.
+static CONTEXT: Mutex<Context> = Mutex::new(Context {
+    scene: None,
+    mode: None,
+    stage: None,
+});
+/// What `detect` saw on the last presented frame.
+static DETECTED: Mutex<Context> = Mutex::new(Context {
+    scene: None,
+    mode: None,
+    stage: None,
+});
+/// Set when the context changes; the next presented frame resolves the overrides again.
+static DIRTY: AtomicBool = AtomicBool::new(false);
+
+fn load(profile: Option<&str>) -> Overrides {
+    let Some(profile) = profile else {
+        return Overrides::default();
+    };
+    match Config::profile_overrides(profile) {
+        Ok(Some(table)) => parse(profile, &table),
+        // Either there are none or `load_file` already logged why it couldn't read them.
+        _ => Overrides::default(),
+    }
+}
+
+fn describe(context: &Context) -> String {
+    let mut parts = Vec::new();
+    if let Some(scene) = &context.scene {
+        parts.push(format!("scene {}", scene));
+    }
+    if let Some(mode) = context.mode {
+        parts.push(format!("mode {}", mode.name()));
+    }
+    if let Some(stage) = context.stage {
+        parts.push(format!("stage {}", stage));
+    }
+    if parts.is_empty() {
+        "no context".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+/// `state`'s base with the tables for the current context on top.
+fn resolve(state: &State) -> SsbuSyncConfig {
+    let context = context();
+    validation::validate(&state.tables.resolve(&state.base, &context)).effective
+}
+
+fn update_context(change: impl FnOnce(&mut Context)) {
+    let mut context = CONTEXT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let before = context.clone();
+    change(&mut context);
+    if *context != before {
+        DIRTY.store(true, Ordering::Release);
+    }
+}
+
+/// Reports the scene the game switched to, e.g. `"results"`. `None` when it isn't one the plugin tracks, which goes
+/// back to the detected scene.
+pub fn report_scene(scene: Option<&str>) {
+    update_context(|context| context.scene = scene.map(str::to_string));
+}
+
+pub fn report_mode(mode: Option<GameMode>) {
+    update_context(|context| context.mode = mode);
+}
+
+/// Reports the stage ID of the match that is starting, or `None` once it's over (or to go back to the detected one).
+pub fn report_stage(stage: Option<u32>) {
+    update_context(|context| context.stage = stage);
+}
+
+/// The reported context, with what ssbusync detected filling in whatever the plugin didn't report.
+pub fn context() -> Context {
+    let reported = CONTEXT.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+    let detected = DETECTED.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+    reported.or(detected)
+}
+
+/// The game functions `detect` reads the scene through.
+struct SceneSymbols {
+    /// `lib::Singleton<app::FighterManager>::instance_`
+    manager: usize,
+    entry_count: extern "C" fn(u64) -> i32,
+    is_result_mode: extern "C" fn(u64) -> bool,
+    stage_id: extern "C" fn() -> i32,
+}
+
+fn scene_symbols() -> Option<&'static SceneSymbols> {
+    static SYMBOLS: OnceLock<Option<SceneSymbols>> = OnceLock::new();
+    SYMBOLS
+        .get_or_init(|| unsafe {
+            let lookup = |sym: &[u8]| crate::compatibility::lookup_symbol_addr(sym, "overrides");
+            let symbols = (|| {
+                Some(SceneSymbols {
+                    manager: lookup(b"_ZN3lib9SingletonIN3app14FighterManagerEE9instance_E\0")?,
+                    entry_count: core::mem::transmute::<usize, extern "C" fn(u64) -> i32>(lookup(
+                        b"_ZN3app8lua_bind32FighterManager__entry_count_implEPNS_14FighterManagerE\0",
+                    )?),
+                    is_result_mode: core::mem::transmute::<usize, extern "C" fn(u64) -> bool>(lookup(
+                        b"_ZN3app8lua_bind35FighterManager__is_result_mode_implEPNS_14FighterManagerE\0",
+                    )?),
+                    stage_id: core::mem::transmute::<usize, extern "C" fn() -> i32>(lookup(
+                        b"_ZN3app5stage12get_stage_idEv\0",
+                    )?),
+                })
+            })();
+            if symbols.is_none() {
+                tracing::warn!(target: "overrides", "FighterManager symbols not found; scene and stage need report_scene and report_stage");
+            }
+            symbols
+        })
+        .as_ref()
+}
+
+/// The scene and stage the game is on: `battle` and the stage while fighters are loaded, `results` once the match
+/// is over, nothing in menus.
+fn detect() -> (Option<&'static str>, Option<u32>) {
+    let Some(symbols) = scene_symbols() else {
+        return (None, None);
+    };
+    unsafe {
+        let manager = *(symbols.manager as *const u64);
+        if manager == 0 || (symbols.entry_count)(manager) <= 0 {
+            return (None, None);
+        }
+        if (symbols.is_result_mode)(manager) {
+            return (Some("results"), None);
+        }
+        (Some("battle"), u32::try_from((symbols.stage_id)()).ok())
+    }
+}
+
+/// Records what `detect` sees now, marking the overrides dirty when it changed. Runs on the present hook, so it skips
+/// the frame instead of waiting while another thread reads the detected context. A new scene also starts the latency
+/// window over, since its loading gaps say nothing about the scene that follows.
+fn update_detected() {
+    let (scene, stage) = detect();
+    let Ok(mut current) = DETECTED.try_lock() else {
+        return;
+    };
+    let scene_changed = current.scene.as_deref() != scene;
+    if scene_changed || current.stage != stage {
+        current.scene = scene.map(str::to_string);
+        current.stage = stage;
+        DIRTY.store(true, Ordering::Release);
+    }
+    drop(current);
+    if scene_changed {
+        crate::latency::reset();
+    }
+}
+
+/// The profile settings the overrides are layered on, `None` before install.
+pub(crate) fn base() -> Option<SsbuSyncConfig> {
+    STATE.lock().ok()?.as_ref().map(|state| state.base)
+}
+
+/// Takes a new base after a hot reload, re-reads the tables and applies the result right away. Returns the settings
+/// applied and the ones that need a restart, like `reload::apply_running`.
+pub(crate) fn rebase(base: SsbuSyncConfig) -> (Vec<String>, Vec<String>) {
+    let next = {
+        let mut state = STATE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Some(state) = state.as_mut() else {
+            return (Vec::new(), Vec::new());
+        };
+        state.base = base;
+        state.tables = load(state.profile.as_deref());
+        resolve(state)
+    };
+    reload::apply_running(&next)
+}
+
+/// Runs once per presented frame: retries a buffer swap an earlier change couldn't start yet, checks the scene, and
+/// applies the overrides for the new context after it changed.
+pub(crate) fn tick() {
+    reload::retry_pending_swap();
+    update_detected();
+    if !DIRTY.swap(false, Ordering::AcqRel) {
+        return;
+    }
+
+    let next = match STATE.try_lock() {
+        Ok(state) => match state.as_ref() {
+            Some(state) if !state.tables.is_empty() => resolve(state),
+            _ => return,
+        },
+        // A hot reload is rebasing; try again next frame.
+        Err(_) => {
+            DIRTY.store(true, Ordering::Release);
+            return;
+        }
+    };
+    let (applied, restart_required) = reload::apply_running(&next);
+    let context = describe(&context());
+    if !applied.is_empty() {
+        tracing::info!(target: "overrides", "{}: applied {}", context, applied.join(", "));
+    }
+    if !restart_required.is_empty() {
+        tracing::warn!(target: "overrides", "{}: could not apply {} live", context, restart_required.join(", "));
+    }
+}
+
+/// Loads the override tables of `profile`, the one the installed config came from.
+pub(crate) fn install(config: &SsbuSyncConfig, profile: Option<&str>) {
+    let tables = load(profile);
+    if let Some(profile) = profile.filter(|_| !tables.is_empty()) {
+        tracing::info!(
+            target: "overrides",
+            "profile {}: {} scene, {} mode and {} stage overrides",
+            profile,
+            tables.scene.len(),
+            tables.mode.len(),
+            tables.stage.len()
+        );
+    }
+    if !tables.mode.is_empty() && CONTEXT.lock().map_or(true, |context| context.mode.is_none()) {
+        tracing::info!(target: "overrides", "mode overrides only apply once a plugin calls report_mode");
+    }
+    if let Ok(mut state) = STATE.lock() {
+        *state = Some(State {
+            profile: profile.map(str::to_string),
+            tables,
+            base: *config,
+        });
+    }
+    // The plugin may have reported a context before install.
+    DIRTY.store(true, Ordering::Release);
+}
@@ -1,36 +1,92 @@
+use std::sync::atomic::{AtomicU16, Ordering};
+
+use crate::patches::{self, PatchError, PatchSpec, Transaction};
+use crate::render::buffer_swap::BufferMode;
+use crate::util::offsets::{self, offsets};
 use crate::SsbuSyncConfig;
 
+pub use ncore::pacer::{encode_movz_x, encode_pacer_bias, pacer_bias_for, PacerBiasError, MAX_PACER_BIAS};
+
+static DOUBLE_BIAS: AtomicU16 = AtomicU16::new(0);
+static TRIPLE_BIAS: AtomicU16 = AtomicU16::new(0);
+
 /** This replaces a check for how many frames we are behind with always assuming we are 0 frames behind
  *
  * This is to keep input latency measurements consistent until we can implement a better frame pacer
- * 
+ *
  * Enabling this turns frame drops to frame skips.
  */
-fn disable_frame_pacer() {
-    skyline::patching::Patch::in_text(0x374c640)
-        .data(0x52800008u32)
-        .unwrap();
+fn disable_frame_pacer(transaction: &mut Transaction) -> Result<(), PatchError> {
+    transaction.apply(PatchSpec::data(
+        "frame_pacer_check",
+        offsets().frame_pacer_check,
+        0x52800008u32,
+    ))
+}
+
+/// Puts the original frame pacer check back.
+pub fn restore_frame_pacer() -> Result<(), PatchError> {
+    patches::revert("frame_pacer_check")
+}
+
+pub fn patch_pacer_bias(bias: u16) -> Result<(), PacerBiasError> {
+    let instruction = encode_pacer_bias(bias)?;
+    let offsets = offsets::loaded().ok_or(PacerBiasError::NotInstalled)?;
+    patches::apply_or_log(PatchSpec::data("pacer_bias", offsets.pacer_bias, instruction));
+    Ok(())
+}
+
+fn stored_bias(mode: BufferMode) -> u16 {
+    match mode {
+        BufferMode::Double => DOUBLE_BIAS.load(Ordering::Acquire),
+        BufferMode::Triple => TRIPLE_BIAS.load(Ordering::Acquire),
+    }
+}
+
+/// Re-applies the configured bias for `mode`, used when the buffer mode changes at runtime.
+pub fn apply_pacer_bias(mode: BufferMode) {
+    if let Err(err) = patch_pacer_bias(stored_bias(mode)) {
+        tracing::warn!(target: "pacer", "failed to apply pacer bias for {:?}: {}", mode, err);
+    }
+}
+
+/// Takes new biases from `config` and re-applies the one for the current buffer mode, for hot reload.
+pub fn reload_bias(config: &SsbuSyncConfig) {
+    store_bias(&DOUBLE_BIAS, pacer_bias_for(config, BufferMode::Double), BufferMode::Double);
+    store_bias(&TRIPLE_BIAS, pacer_bias_for(config, BufferMode::Triple), BufferMode::Triple);
+    apply_pacer_bias(crate::render::buffer_swap::current_buffer_mode());
 }
 
-pub fn patch_pacer_bias(slow_pacer_bias: bool) {
-    let imm = 0u32;
-    // mov x8, #imm (imm16 in bits [20:5], rd=8)
-    let instruction = 0xD2800008u32 | (imm << 5);
-    skyline::patching::Patch::in_text(0x22deb84)
-        .data(instruction)
-        .unwrap();
+fn store_bias(slot: &AtomicU16, bias: u16, mode: BufferMode) {
+    if bias > MAX_PACER_BIAS {
+        tracing::warn!(
+            target: "pacer",
+            "pacer bias {} for {:?} is out of range (0..={}); using 0",
+            bias, mode, MAX_PACER_BIAS
+        );
+        slot.store(0, Ordering::Release);
+    } else {
+        slot.store(bias, Ordering::Release);
+    }
 }
 
-pub fn install(config: SsbuSyncConfig) {
+/// Writes the pacer patches and the bias for the starting buffer mode.
+pub fn patch(config: SsbuSyncConfig, transaction: &mut Transaction) -> Result<(), PatchError> {
     let emulator = config.emulator_check;
     // Emulator always forces pacer-disable.
     if config.disable_pacer || emulator {
-        disable_frame_pacer();
+        disable_frame_pacer(transaction)?;
     }
 
-    if emulator {
-        patch_pacer_bias(false);
+    store_bias(&DOUBLE_BIAS, pacer_bias_for(&config, BufferMode::Double), BufferMode::Double);
+    store_bias(&TRIPLE_BIAS, pacer_bias_for(&config, BufferMode::Triple), BufferMode::Triple);
+
+    let initial = if config.enable_triple_buffer || config.online_only {
+        BufferMode::Triple
     } else {
-        patch_pacer_bias(config.slow_pacer_bias);
-    }
+        BufferMode::Double
+    };
+    let instruction =
+        encode_pacer_bias(stored_bias(initial)).expect("store_bias clamps the bias to MAX_PACER_BIAS");
+    transaction.apply(PatchSpec::data("pacer_bias", offsets().pacer_bias, instruction))
 }
@@ -0,0 +1,130 @@
+/** Patching the game's `.text`
+ *
+ * The registry and its checks are in `ncore::patches`; this wires them to the game's `.text` and fills in what each
+ * site should hold from the offset table for the running build.
+ */
+use std::sync::Mutex;
+
+pub use ncore::patches::{
+    encode_bl, AppliedPatch, Memory, Original, PatchData, PatchError, PatchRegistry, PatchSpec, NOP, RET,
+};
+
+/// The game's `.text`.
+pub struct Text;
+
+impl Memory for Text {
+    fn read(&self, offset: usize, like: PatchData) -> PatchData {
+        unsafe {
+            let addr = skyline::hooks::getRegionAddress(skyline::hooks::Region::Text)
+                .cast::<u8>()
+                .add(offset);
+            match like {
+                PatchData::U32(_) => PatchData::U32(addr.cast::<u32>().read_unaligned()),
+                PatchData::U64(_) => PatchData::U64(addr.cast::<u64>().read_unaligned()),
+            }
+        }
+    }
+
+    fn write(&mut self, offset: usize, data: PatchData) -> bool {
+        let patch = skyline::patching::Patch::in_text(offset);
+        match data {
+            PatchData::U32(value) => patch.data(value).is_ok(),
+            PatchData::U64(value) => patch.data(value).is_ok(),
+        }
+    }
+
+    fn in_text(&self, pointer: u64) -> bool {
+        unsafe {
+            let start = skyline::hooks::getRegionAddress(skyline::hooks::Region::Text) as u64;
+            let end = skyline::hooks::getRegionAddress(skyline::hooks::Region::Rodata) as u64;
+            (start..end).contains(&pointer)
+        }
+    }
+}
+
+/// `spec` with `expected` taken from the offset table when it doesn't name one.
+fn with_original(spec: PatchSpec) -> PatchSpec {
+    PatchSpec {
+        expected: spec
+            .expected
+            .or_else(|| crate::util::offsets::loaded().and_then(|offsets| offsets.original(spec.name))),
+        ..spec
+    }
+}
+
+static REGISTRY: Mutex<PatchRegistry> = Mutex::new(PatchRegistry::new());
+
+pub(crate) fn registry() -> std::sync::MutexGuard<'static, PatchRegistry> {
+    REGISTRY.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub fn apply(spec: PatchSpec) -> Result<(), PatchError> {
+    registry().apply(&mut Text, with_original(spec))
+}
+
+/// Applies `spec`, logging instead of returning the error. Returns whether the patch is in place.
+pub fn apply_or_log(spec: PatchSpec) -> bool {
+    match apply(spec) {
+        Ok(()) => true,
+        Err(err) => {
+            tracing::warn!(target: "patches", "{}", err);
+            false
+        }
+    }
+}
+
+pub fn revert(name: &'static str) -> Result<(), PatchError> {
+    registry().revert(&mut Text, name)
+}
+
+pub fn revert_all() -> Vec<PatchError> {
+    registry().revert_all(&mut Text)
+}
+
+pub fn is_applied(name: &str) -> bool {
+    registry().is_applied(name)
+}
+
+/// Snapshot of every applied patch.
+pub fn applied() -> Vec<AppliedPatch> {
+    registry().applied().to_vec()
+}
+
+/// `ncore::patches::Transaction` over the game's `.text`; install holds the registry lock for the whole of it.
+pub struct Transaction<'a>(ncore::patches::Transaction<'a, Text>);
+
+impl<'a> Transaction<'a> {
+    pub fn begin(registry: &'a mut PatchRegistry) -> Self {
+        Self(ncore::patches::Transaction::begin(registry, Text))
+    }
+
+    pub fn apply(&mut self, spec: PatchSpec) -> Result<(), PatchError> {
+        self.0.apply(with_original(spec))
+    }
+
+    /// Keeps every patch applied.
+    pub fn commit(self) {
+        self.0.commit();
+    }
+}
+
+static HOOKS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+pub fn record_hooks(names: &[&'static str]) {
+    let mut hooks = HOOKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    hooks.extend_from_slice(names);
+}
+
+/// Every hook installed through `install_hooks!`, in install order.
+pub fn hooks() -> Vec<&'static str> {
+    HOOKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
+
+/// `skyline::install_hooks!` that also records the hook names for the install report.
+macro_rules! install_hooks {
+    ($($hook:ident),+ $(,)?) => {{
+        skyline::install_hooks!($($hook),+);
+        $crate::patches::record_hooks(&[$(stringify!($hook)),+]);
+    }};
+}
+pub(crate) use install_hooks;
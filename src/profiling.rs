@@ -1,13 +1,26 @@
 use std::{
-    collections::BTreeMap,
-    io::Write,
-    net::{Ipv4Addr, SocketAddrV4, TcpListener},
+    collections::{BTreeMap, HashMap, VecDeque},
+    ffi::{c_char, CStr},
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::{Ipv4Addr, SocketAddrV4, TcpListener, TcpStream},
     sync::{
-        mpsc::{Receiver, Sender},
+        atomic::{AtomicBool, Ordering},
         OnceLock,
     },
+    time::Duration,
 };
 
+use camino::Utf8PathBuf;
+
+use nprof::wire::LatencySummary;
+use nprof::ring::Ring;
+
+use nprof::spans::SpanStack;
+use nprof::wire::{self, LaneRef, SpanRef};
+
+use crate::{ProfilingSink, SsbuSyncConfig};
+
 #[repr(transparent)]
 #[derive(Copy, Clone)]
 pub struct OsTick(pub i64);
@@ -38,243 +51,651 @@ enum Item {
     /// Submits the current processing frame with a frame index, this allows us to begin
     /// recording a new frame
     SubmitFrame { frame: usize, tick: OsTick },
-    /// Starts a span on the current frame with the given name
-    StartSpan { name: &'static str, tick: OsTick },
-    /// Ends the current span being processed
-    EndSpan { tick: OsTick },
+    /// Starts a span on the current frame with the given name, nested inside whatever span `thread` has open
+    StartSpan {
+        name: &'static str,
+        tick: OsTick,
+        thread: u64,
+    },
+    /// Ends the innermost span `thread` has open, which should be called `name`
+    EndSpan {
+        name: &'static str,
+        tick: OsTick,
+        thread: u64,
+    },
     /// Indicates that a frame has been presented
     FinishFrame { frame: usize, tick: OsTick },
     /// Records the timestamp of a vblank
     VBlank { tick: OsTick },
+    /// A present latency summary from `crate::latency`. Boxed so it doesn't grow every slot of the queue; it's only
+    /// sent about once a second, from the vblank thread rather than a span hook.
+    Latency(Box<LatencySummary>),
+    /// Reopen the sinks with new `profiling_sink` and capture settings, from `crate::reload`.
+    Reconfigure(Box<SsbuSyncConfig>),
 }
 
-static SENDER: OnceLock<Sender<Item>> = OnceLock::new();
+/// Events queued by whichever thread hit a hook. Fixed size so recording never allocates or blocks; when the
+/// profiler thread falls behind (or nobody is connected) new events are dropped and counted instead.
+static QUEUE: OnceLock<Ring<Item>> = OnceLock::new();
+const QUEUE_CAPACITY: usize = 8192;
+/// Whether the profiler thread is up and has somewhere to send the stream.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+/// How long the profiler thread sleeps when the queue is empty.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
 
 #[inline]
-fn sender() -> Option<&'static Sender<Item>> {
-    SENDER.get()
+fn record(item: Item) {
+    if let Some(queue) = QUEUE.get() {
+        let _ = queue.push(item);
+    }
+}
+
+/// Whether `setup` has run, so hooks can skip work that only feeds the profiler.
+pub fn is_enabled() -> bool {
+    QUEUE.get().is_some()
 }
 
 pub fn start_frame(tick: OsTick) {
-    if let Some(sender) = sender() {
-        let _ = sender.send(Item::FrameStart { tick });
-    }
+    record(Item::FrameStart { tick });
 }
 
 pub fn submit_frame(frame: usize, tick: OsTick) {
-    if let Some(sender) = sender() {
-        let _ = sender.send(Item::SubmitFrame { frame, tick });
-    }
+    record(Item::SubmitFrame { frame, tick });
 }
 
 pub fn finish_frame(frame: usize, tick: OsTick) {
-    if let Some(sender) = sender() {
-        let _ = sender.send(Item::FinishFrame { frame, tick });
-    }
+    record(Item::FinishFrame { frame, tick });
+}
+
+/// Spans belong to the thread that records them; this is its `nn::os::ThreadType`, resolved to an id and name on
+/// the profiler thread.
+#[inline]
+fn current_thread() -> u64 {
+    unsafe { crate::get_current_thread() }
 }
 
 pub fn start_span(name: &'static str, tick: OsTick) {
-    if let Some(sender) = sender() {
-        let _ = sender.send(Item::StartSpan { name, tick });
-    }
+    record(Item::StartSpan {
+        name,
+        tick,
+        thread: current_thread(),
+    });
 }
 
-pub fn end_span(tick: OsTick) {
-    if let Some(sender) = sender() {
-        let _ = sender.send(Item::EndSpan { tick });
-    }
+pub fn end_span(name: &'static str, tick: OsTick) {
+    record(Item::EndSpan {
+        name,
+        tick,
+        thread: current_thread(),
+    });
 }
 
 pub fn span(name: &'static str, start: OsTick, end: OsTick) {
     start_span(name, start);
-    end_span(end);
+    end_span(name, end);
 }
 
 pub fn vblank(tick: OsTick) {
-    if let Some(sender) = sender() {
-        let _ = sender.send(Item::VBlank { tick });
+    record(Item::VBlank { tick });
+}
+
+pub fn latency(summary: LatencySummary) {
+    if QUEUE.get().is_some() {
+        record(Item::Latency(Box::new(summary)));
     }
 }
 
-struct FrameSpan {
-    name: &'static str,
-    start: OsTick,
-    end: OsTick,
+/// One thread's spans within a frame.
+struct Lane {
+    thread: u64,
+    spans: Vec<SpanRef<'static>>,
 }
 
 struct SubmittedFrame {
     start: OsTick,
     submitted_at: OsTick,
-    spans: Vec<FrameSpan>,
+    lanes: Vec<Lane>,
+}
+
+unsafe extern "C" {
+    #[link_name = "_ZN2nn2os22GetSystemTickFrequencyEv"]
+    fn get_system_tick_frequency() -> i64;
+
+    #[link_name = "_ZN2nn2os11GetThreadIdEPKNS0_10ThreadTypeE"]
+    fn get_thread_id(thread: u64) -> u64;
+
+    #[link_name = "_ZN2nn2os20GetThreadNamePointerEPKNS0_10ThreadTypeE"]
+    fn get_thread_name_pointer(thread: u64) -> *const c_char;
 }
 
-fn serialize_span(span: &FrameSpan, buffer: &mut Vec<u8>) {
-    buffer.extend_from_slice(&span.start.0.to_le_bytes());
-    buffer.extend_from_slice(&span.end.0.to_le_bytes());
-    buffer.extend_from_slice(&span.name.len().to_le_bytes());
-    buffer.extend_from_slice(span.name.as_bytes());
+struct ThreadInfo {
+    id: u64,
+    name: String,
+}
+
+impl ThreadInfo {
+    /// Only called while the thread is alive, since it just sent us a span. Game threads live for the whole
+    /// session, so the result is cached by `ThreadType` address.
+    fn of(thread: u64) -> Self {
+        unsafe {
+            let name = get_thread_name_pointer(thread);
+            Self {
+                id: get_thread_id(thread),
+                name: if name.is_null() {
+                    String::new()
+                } else {
+                    CStr::from_ptr(name).to_string_lossy().into_owned()
+                },
+            }
+        }
+    }
+}
+
+/// Sent first on every connection and at the start of every capture file so each reader knows what it's reading.
+fn handshake() -> wire::Handshake {
+    wire::Handshake {
+        protocol: wire::v2::PROTOCOL_VERSION,
+        tick_frequency: unsafe { get_system_tick_frequency() } as u64,
+        build: crate::util::offsets::running_display_version(),
+        plugin_version: env!("CARGO_PKG_VERSION").to_string(),
+    }
 }
 
+/// The stream format lives in `nprof::wire` so the host-side decoder reads exactly what we write.
 fn serialize_frame(
-    frame: &SubmittedFrame,
-    frame_index: u64,
-    dangled: bool,
-    presented: OsTick,
+    encoder: &mut wire::v2::Encoder,
+    threads: &HashMap<u64, ThreadInfo>,
+    header: wire::FrameHeader,
+    lanes: &[Lane],
     buffer: &mut Vec<u8>,
 ) {
-    buffer.push(0u8);
-    buffer.extend_from_slice(&frame_index.to_le_bytes());
-    buffer.extend_from_slice(&frame.start.0.to_le_bytes());
-    buffer.extend_from_slice(&frame.submitted_at.0.to_le_bytes());
-    buffer.extend_from_slice(&presented.0.to_le_bytes());
-    buffer.extend_from_slice(&[dangled as u8]);
-    buffer.extend_from_slice(&frame.spans.len().to_le_bytes());
-    for span in frame.spans.iter() {
-        serialize_span(span, buffer);
+    let lanes: Vec<LaneRef> = lanes
+        .iter()
+        .map(|lane| {
+            let thread = threads.get(&lane.thread);
+            LaneRef {
+                thread_id: thread.map_or(0, |thread| thread.id),
+                name: thread.map_or("", |thread| thread.name.as_str()),
+                spans: &lane.spans,
+            }
+        })
+        .collect();
+    if let Err(err) = encoder.frame(header, &lanes, buffer) {
+        // Keep the frame's timing; only its spans are lost.
+        tracing::warn!(target: "profiler", "{}; sending it without spans", err);
+        let _ = encoder.frame(header, &[], buffer);
+    }
+}
+
+/// A presented (or dangled) frame or a vblank, ready to be encoded for each sink.
+enum Packet {
+    Frame {
+        header: wire::FrameHeader,
+        lanes: Vec<Lane>,
+    },
+    VBlank(OsTick),
+    Latency(Box<LatencySummary>),
+}
+
+impl Packet {
+    fn frame(frame: SubmittedFrame, index: usize, dangled: bool, presented: OsTick) -> Self {
+        Self::Frame {
+            header: wire::FrameHeader {
+                index: index as u64,
+                start: frame.start.0,
+                submitted_at: frame.submitted_at.0,
+                presented: presented.0,
+                dangled,
+            },
+            lanes: frame.lanes,
+        }
+    }
+
+    fn tick(&self) -> i64 {
+        match self {
+            Self::Frame { header, .. } => header.presented,
+            Self::VBlank(tick) => tick.0,
+            Self::Latency(latency) => latency.last_vblank,
+        }
+    }
+}
+
+/// One reader of the stream. Span names are interned per reader, so each gets its own encoder.
+struct Output<W: Write> {
+    writer: W,
+    encoder: wire::v2::Encoder,
+    buffer: Vec<u8>,
+    /// `Ring::dropped` when the handshake went out; the reader only hears about drops after that.
+    dropped_baseline: u64,
+    dropped_reported: u64,
+}
+
+impl<W: Write> Output<W> {
+    fn open(mut writer: W, dropped: u64) -> io::Result<Self> {
+        let mut encoder = wire::v2::Encoder::new();
+        let mut buffer = Vec::new();
+        encoder.handshake(&handshake(), &mut buffer);
+        writer.write_all(&buffer)?;
+        Ok(Self {
+            writer,
+            encoder,
+            buffer,
+            dropped_baseline: dropped,
+            dropped_reported: 0,
+        })
     }
+
+    /// Writes `packet`, preceded by the drop count if it changed, and returns how many bytes that took.
+    fn send(&mut self, packet: &Packet, threads: &HashMap<u64, ThreadInfo>, dropped: u64) -> io::Result<usize> {
+        self.buffer.clear();
+        let dropped = dropped - self.dropped_baseline;
+        if dropped != self.dropped_reported {
+            self.encoder.dropped(dropped, &mut self.buffer);
+            self.dropped_reported = dropped;
+        }
+        match packet {
+            Packet::Frame { header, lanes } => {
+                serialize_frame(&mut self.encoder, threads, *header, lanes, &mut self.buffer)
+            }
+            Packet::VBlank(tick) => self.encoder.vblank(tick.0, &mut self.buffer),
+            Packet::Latency(latency) => self.encoder.latency(latency, &mut self.buffer),
+        }
+        self.writer.write_all(&self.buffer)?;
+        Ok(self.buffer.len())
+    }
+}
+
+/** Serves the stream on TCP port 1776
+ *
+ * The listener is polled rather than blocked on, so frames keep being processed while nobody is connected. Those
+ * packets wait in a bounded backlog (oldest dropped first) and are sent to the next client after its handshake.
+ */
+struct TcpSink {
+    listener: TcpListener,
+    client: Option<Output<TcpStream>>,
+    backlog: VecDeque<Packet>,
+    /// Roughly one frame and one vblank per game frame.
+    backlog_limit: usize,
+    /// Backlogged packets pushed out by newer ones since the last client connected.
+    discarded: u64,
 }
 
-fn thread(receiver: Receiver<Item>) {
+impl TcpSink {
+    fn bind(backlog_frames: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 1776))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            client: None,
+            backlog: VecDeque::new(),
+            backlog_limit: backlog_frames as usize * 2,
+            discarded: 0,
+        })
+    }
+
+    fn poll(&mut self, threads: &HashMap<u64, ThreadInfo>, dropped: u64) {
+        if self.client.is_some() {
+            return;
+        }
+        let (stream, address) = match self.listener.accept() {
+            Ok(accepted) => accepted,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+            Err(err) => {
+                tracing::warn!(target: "profiler", "failed to accept a connection: {}", err);
+                return;
+            }
+        };
+        // Writes to the client block; a slow reader backs up the queue and shows up as dropped events.
+        let _ = stream.set_nonblocking(false);
+        let Ok(mut client) = Output::open(stream, dropped) else {
+            return;
+        };
+        tracing::info!(
+            target: "profiler",
+            "client {} connected; sending {} backlogged packets ({} older ones discarded).",
+            address,
+            self.backlog.len(),
+            self.discarded
+        );
+        self.discarded = 0;
+        while let Some(packet) = self.backlog.pop_front() {
+            if client.send(&packet, threads, dropped).is_err() {
+                return;
+            }
+        }
+        self.client = Some(client);
+    }
+
+    fn send(&mut self, packet: Packet, threads: &HashMap<u64, ThreadInfo>, dropped: u64) {
+        if let Some(client) = self.client.as_mut() {
+            if client.send(&packet, threads, dropped).is_err() {
+                tracing::info!(target: "profiler", "client disconnected.");
+                self.client = None;
+            }
+            return;
+        }
+        self.backlog.push_back(packet);
+        while self.backlog.len() > self.backlog_limit {
+            self.backlog.pop_front();
+            self.discarded += 1;
+        }
+    }
+}
+
+/** Writes the stream to `sd:/ultimate/ssbusync/captures/`
+ *
+ * Each boot is one session, `capture-NNNN-PP.nprof`. Once a part reaches the rotation size the next one starts with
+ * its own handshake, so every part replays on its own, and parts beyond the configured count are deleted oldest
+ * first. Recording stops for good once the session is longer than the maximum duration.
+ */
+struct FileSink {
+    dir: Utf8PathBuf,
+    session: u32,
+    part: u32,
+    output: Option<Output<BufWriter<File>>>,
+    written: u64,
+    parts: VecDeque<Utf8PathBuf>,
+    rotate_bytes: u64,
+    max_files: usize,
+    /// 0 for no limit.
+    max_ticks: i64,
+    tick_frequency: i64,
+    started: Option<i64>,
+    last_flush: i64,
+    stopped: bool,
+}
+
+impl FileSink {
+    fn open(config: &SsbuSyncConfig, tick_frequency: i64) -> io::Result<Self> {
+        let dir = crate::util::file::paths::captures();
+        std::fs::create_dir_all(dir.as_std_path())?;
+        let session = crate::util::file::paths::next_session(&dir, "capture-");
+        Ok(Self {
+            dir,
+            session,
+            part: 0,
+            output: None,
+            written: 0,
+            parts: VecDeque::new(),
+            rotate_bytes: (config.profiling_capture_rotate_mb.max(1) as u64) << 20,
+            max_files: config.profiling_capture_max_files.max(1) as usize,
+            max_ticks: config.profiling_capture_max_seconds as i64 * tick_frequency,
+            tick_frequency,
+            started: None,
+            last_flush: 0,
+            stopped: false,
+        })
+    }
+
+    fn rotate(&mut self, dropped: u64) -> io::Result<()> {
+        if let Some(mut output) = self.output.take() {
+            output.writer.flush()?;
+            self.part += 1;
+        }
+        let path = self
+            .dir
+            .join(format!("capture-{:04}-{:02}.nprof", self.session, self.part));
+        let file = File::create(path.as_std_path())?;
+        self.output = Some(Output::open(BufWriter::with_capacity(64 * 1024, file), dropped)?);
+        self.written = 0;
+        tracing::info!(target: "profiler", "capturing to {}", path);
+        self.parts.push_back(path);
+        while self.parts.len() > self.max_files {
+            if let Some(oldest) = self.parts.pop_front() {
+                let _ = std::fs::remove_file(oldest.as_std_path());
+            }
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self, reason: &str) {
+        if let Some(mut output) = self.output.take() {
+            let _ = output.writer.flush();
+        }
+        self.stopped = true;
+        tracing::info!(target: "profiler", "capture stopped: {}", reason);
+    }
+
+    fn send(&mut self, packet: &Packet, threads: &HashMap<u64, ThreadInfo>, dropped: u64) {
+        if self.stopped {
+            return;
+        }
+        let tick = packet.tick();
+        let started = *self.started.get_or_insert(tick);
+        if self.max_ticks != 0 && tick - started > self.max_ticks {
+            self.stop("reached profiling_capture_max_seconds");
+            return;
+        }
+        if self.output.is_none() || self.written >= self.rotate_bytes {
+            if let Err(err) = self.rotate(dropped) {
+                self.stop(&format!("could not open the next capture file ({})", err));
+                return;
+            }
+        }
+        let Some(output) = self.output.as_mut() else {
+            return;
+        };
+        match output.send(packet, threads, dropped) {
+            Ok(written) => self.written += written as u64,
+            Err(err) => {
+                self.stop(&format!("write failed ({})", err));
+                return;
+            }
+        }
+        if tick - self.last_flush >= self.tick_frequency {
+            let _ = output.writer.flush();
+            self.last_flush = tick;
+        }
+    }
+}
+
+/// The sinks `profiling_sink` asked for that could be opened.
+struct Sinks {
+    tcp: Option<TcpSink>,
+    file: Option<FileSink>,
+}
+
+impl Sinks {
+    fn open(config: &SsbuSyncConfig, tick_frequency: i64) -> Self {
+        let (tcp, file) = match config.profiling_sink {
+            ProfilingSink::Tcp => (true, false),
+            ProfilingSink::File => (false, true),
+            ProfilingSink::Both => (true, true),
+        };
+        let tcp = tcp
+            .then(|| TcpSink::bind(config.profiling_backlog_frames))
+            .and_then(|sink| {
+                sink.map_err(|err| tracing::warn!(target: "profiler", "could not listen on port 1776: {}", err))
+                    .ok()
+            });
+        let file = file
+            .then(|| FileSink::open(config, tick_frequency))
+            .and_then(|sink| {
+                sink.map_err(|err| tracing::warn!(target: "profiler", "could not open the captures directory: {}", err))
+                    .ok()
+            });
+        Self { tcp, file }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tcp.is_none() && self.file.is_none()
+    }
+
+    fn send(&mut self, packet: Packet, threads: &HashMap<u64, ThreadInfo>, dropped: u64) {
+        if let Some(file) = self.file.as_mut() {
+            file.send(&packet, threads, dropped);
+        }
+        if let Some(tcp) = self.tcp.as_mut() {
+            tcp.send(packet, threads, dropped);
+        }
+    }
+
+    /// Called whenever the queue runs dry.
+    fn idle(&mut self, threads: &HashMap<u64, ThreadInfo>, dropped: u64) {
+        if let Some(tcp) = self.tcp.as_mut() {
+            tcp.poll(threads, dropped);
+        }
+    }
+}
+
+fn thread(queue: &'static Ring<Item>, config: SsbuSyncConfig) {
     struct FrameInFlight {
         start: OsTick,
-        completed_spans: Vec<FrameSpan>,
-        current_span: Option<FrameSpan>,
+        /// One stack per thread, in the order they first recorded a span this frame.
+        lanes: Vec<(u64, SpanStack)>,
+    }
+
+    impl FrameInFlight {
+        fn lane(&mut self, thread: u64) -> &mut SpanStack {
+            let index = match self.lanes.iter().position(|(t, _)| *t == thread) {
+                Some(index) => index,
+                None => {
+                    self.lanes.push((thread, SpanStack::new()));
+                    self.lanes.len() - 1
+                }
+            };
+            &mut self.lanes[index].1
+        }
     }
 
-    let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 1776)).unwrap();
+    let tick_frequency = unsafe { get_system_tick_frequency() };
+    let mut sinks = Sinks::open(&config, tick_frequency);
+    if sinks.is_empty() {
+        tracing::warn!(target: "profiler", "nowhere to send the stream; not recording.");
+        return;
+    }
+    RUNNING.store(true, Ordering::Release);
 
     let mut frame_in_flight = None;
     let mut submitted_frames: BTreeMap<usize, SubmittedFrame> = BTreeMap::new();
+    let mut threads: HashMap<u64, ThreadInfo> = HashMap::new();
+
+    loop {
+        let Some(item) = queue.pop() else {
+            sinks.idle(&threads, queue.dropped());
+            std::thread::sleep(POLL_INTERVAL);
+            continue;
+        };
+        match item {
+            Item::FrameStart { tick } => {
+                if frame_in_flight.is_some() {
+                    tracing::warn!(target: "profiler", "skipping in-flight frame because another was started before it was submitted");
+                }
 
-    'outer: loop {
-        let (mut next, _) = listener.accept().unwrap();
-
-        let mut buffer = vec![];
-
-        loop {
-            match receiver.recv() {
-                Ok(item) => {
-                    buffer.clear();
-                    match item {
-                        Item::FrameStart { tick } => {
-                            if frame_in_flight.is_some() {
-                                println!("[WARN] Profiler is skipping in-flight frame because another was started before it was submitted");
-                            }
-
-                            frame_in_flight = Some(FrameInFlight {
-                                start: tick,
-                                completed_spans: vec![],
-                                current_span: None,
-                            });
-                        }
-                        Item::SubmitFrame {
-                            frame: frame_index,
-                            tick,
-                        } => {
-                            let Some(mut frame_in_flight) = frame_in_flight.take() else {
-                                println!("[WARN] Profile has submitted frame but no in-flight frame found");
-                                continue;
-                            };
-
-                            if let Some(span) = frame_in_flight.current_span.take() {
-                                frame_in_flight.completed_spans.push(span);
-                            }
-
-                            submitted_frames.insert(
-                                frame_index,
-                                SubmittedFrame {
-                                    start: frame_in_flight.start,
-                                    spans: frame_in_flight.completed_spans,
-                                    submitted_at: tick,
-                                },
-                            );
-                        }
-                        Item::FinishFrame {
-                            frame: frame_index,
-                            tick,
-                        } => {
-                            let Some(frame) = submitted_frames.remove(&frame_index) else {
-                                println!("[WARN] Finished frame but it is not in the submitted frames list");
-                                continue;
-                            };
-
-                            while submitted_frames
-                                .first_entry()
-                                .is_some_and(|frame| *frame.key() < frame_index)
-                            {
-                                let (frame_index, dangled) =
-                                    unsafe { submitted_frames.pop_first().unwrap_unchecked() };
-                                println!("[ERROR] Dangling frame {frame_index} left in profiler");
-                                serialize_frame(
-                                    &dangled,
-                                    frame_index as u64,
-                                    true,
-                                    tick,
-                                    &mut buffer,
-                                );
-                            }
-
-                            serialize_frame(&frame, frame_index as u64, false, tick, &mut buffer);
-                        }
-                        Item::StartSpan { name, tick } => {
-                            let Some(frame) = frame_in_flight.as_mut() else {
-                                println!(
-                                    "[WARN] Cannot start span for frame since one is not in flight"
-                                );
-                                continue;
-                            };
-
-                            if let Some(mut old) = frame.current_span.take() {
-                                if old.name == name {
-                                    frame.current_span = Some(old);
-                                    continue;
-                                } else {
-                                    old.end = tick;
-                                    frame.completed_spans.push(old);
-                                }
-                            }
-
-                            frame.current_span = Some(FrameSpan {
-                                name,
-                                start: tick,
-                                end: tick,
-                            });
-                        }
-                        Item::EndSpan { tick } => {
-                            let Some(frame) = frame_in_flight.as_mut() else {
-                                println!(
-                                    "[WARN] Cannot end span for frame since one is not in flight"
-                                );
-                                continue;
-                            };
-
-                            if let Some(mut old) = frame.current_span.take() {
-                                old.end = tick;
-                                frame.completed_spans.push(old);
-                            }
-                        }
-                        Item::VBlank { tick } => {
-                            buffer.push(1u8);
-                            buffer.extend_from_slice(&tick.0.to_le_bytes());
+                frame_in_flight = Some(FrameInFlight {
+                    start: tick,
+                    lanes: Vec::new(),
+                });
+            }
+            Item::SubmitFrame {
+                frame: frame_index,
+                tick,
+            } => {
+                let Some(frame_in_flight) = frame_in_flight.take() else {
+                    tracing::warn!(target: "profiler", "submitted frame but no in-flight frame found");
+                    continue;
+                };
+
+                let lanes = frame_in_flight
+                    .lanes
+                    .into_iter()
+                    .map(|(thread, stack)| {
+                        let (spans, unclosed) = stack.finish(tick.0);
+                        if !unclosed.is_empty() {
+                            let name = threads.get(&thread).map_or("", |t| t.name.as_str());
+                            tracing::warn!(target: "profiler", "Frame {frame_index} submitted with spans {unclosed:?} still open on thread {name}");
                         }
-                    }
+                        Lane { thread, spans }
+                    })
+                    .collect();
+
+                submitted_frames.insert(
+                    frame_index,
+                    SubmittedFrame {
+                        start: frame_in_flight.start,
+                        lanes,
+                        submitted_at: tick,
+                    },
+                );
+            }
+            Item::FinishFrame {
+                frame: frame_index,
+                tick,
+            } => {
+                let Some(frame) = submitted_frames.remove(&frame_index) else {
+                    tracing::warn!(target: "profiler", "Finished frame but it is not in the submitted frames list");
+                    continue;
+                };
+
+                while submitted_frames
+                    .first_entry()
+                    .is_some_and(|frame| *frame.key() < frame_index)
+                {
+                    let (frame_index, dangled) =
+                        unsafe { submitted_frames.pop_first().unwrap_unchecked() };
+                    tracing::error!(target: "profiler", "Dangling frame {frame_index} left in profiler");
+                    sinks.send(Packet::frame(dangled, frame_index, true, tick), &threads, queue.dropped());
+                }
 
-                    if next.write_all(&buffer).is_err() {
-                        break;
-                    }
+                sinks.send(Packet::frame(frame, frame_index, false, tick), &threads, queue.dropped());
+            }
+            Item::StartSpan { name, tick, thread } => {
+                let Some(frame) = frame_in_flight.as_mut() else {
+                    tracing::warn!(
+                        target: "profiler",
+                        "Cannot start span for frame since one is not in flight"
+                    );
+                    continue;
+                };
+
+                threads.entry(thread).or_insert_with(|| ThreadInfo::of(thread));
+                frame.lane(thread).start(name, tick.0);
+            }
+            Item::EndSpan { name, tick, thread } => {
+                let Some(frame) = frame_in_flight.as_mut() else {
+                    tracing::warn!(
+                        target: "profiler",
+                        "Cannot end span for frame since one is not in flight"
+                    );
+                    continue;
+                };
+
+                if let Err(mismatch) = frame.lane(thread).end(name, tick.0) {
+                    tracing::warn!(target: "profiler", "{mismatch}");
+                }
+            }
+            Item::VBlank { tick } => {
+                sinks.send(Packet::VBlank(tick), &threads, queue.dropped());
+            }
+            Item::Latency(latency) => {
+                sinks.send(Packet::Latency(latency), &threads, queue.dropped());
+            }
+            Item::Reconfigure(config) => {
+                // Close the old sinks first so the TCP port is free to bind again.
+                drop(std::mem::replace(&mut sinks, Sinks { tcp: None, file: None }));
+                sinks = Sinks::open(&config, tick_frequency);
+                if sinks.is_empty() {
+                    tracing::warn!(target: "profiler", "nowhere to send the stream after reconfiguring; dropping it until the next change.");
                 }
-                Err(_) => break 'outer,
             }
         }
     }
 }
 
-pub fn setup() {
-    let (sender, receiver) = std::sync::mpsc::channel();
+/// Reopens the sinks for `config`'s `profiling_sink` and capture settings. Returns false if the profiler thread isn't
+/// running (profiling was off at boot, or it never had anywhere to send the stream) or the request couldn't be queued.
+pub fn reconfigure(config: &SsbuSyncConfig) -> bool {
+    RUNNING.load(Ordering::Acquire)
+        && QUEUE
+            .get()
+            .is_some_and(|queue| queue.push(Item::Reconfigure(Box::new(*config))).is_ok())
+}
 
-    SENDER
-        .set(sender)
-        .expect("only call profiling::setup() once");
+pub fn setup(config: &SsbuSyncConfig) {
+    if QUEUE.set(Ring::new(QUEUE_CAPACITY)).is_err() {
+        panic!("only call profiling::setup() once");
+    }
 
-    std::thread::spawn(move || thread(receiver));
+    let config = *config;
+    std::thread::spawn(move || thread(QUEUE.get().unwrap(), config));
 }
@@ -0,0 +1,379 @@
+/** Hot reload of `ssbusync.toml`
+ *
+ * With `hot_reload` on, a background thread checks `ssbusync.toml`'s modification time once a second and re-reads the
+ * profile the installed config came from whenever it changes. Settings that changed in the file are applied live where
+ * that's possible and otherwise reported as needing a restart:
+ *
+ * - `log_level`, `log_output`: handed to the logger
+ * - `enable_triple_buffer`, `online_only`: a swap through `control::request_buffer_mode`, retried while a swap is
+ *   cooling down
+ * - `slow_pacer_bias`, `pacer_bias_double`, `pacer_bias_triple`: the bias is re-patched for the current mode
+ * - `auto_buffer_policy` and its thresholds: the policy starts over with the new ones
+ * - `profiling_sink` and the capture settings: the profiler reopens its sinks
+ * - `profiling = false` with the `frametracer` backend: the trace session writes its summary and closes
+ * - `hot_reload = false` stops the watcher
+ *
+ * Everything else is hooked or patched once at boot. Only a config that is an exact copy of a loaded profile is
+ * watched; one the calling plugin changed in code after loading isn't tied to any profile. The new values go through
+ * `validation::validate` first and become the base that `overrides` layers the current scene's tables on;
+ * `control::effective_config` picks up whatever was applied.
+ *
+ * `apply_running` is shared with `overrides`, which applies its layered config the same way at scene transitions.
+ */
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+use camino::Utf8PathBuf;
+
+use crate::control::{self, ControlError};
+use crate::render::buffer_swap::BufferMode;
+use crate::util::file::config::LoadedProfile;
+use crate::util::file::paths;
+use crate::validation::{self, Diagnostic, Validation};
+use crate::{Config, SsbuSyncConfig};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What one reload did.
+#[derive(Debug, Clone, Default)]
+pub struct Reload {
+    pub profile: String,
+    /// Changed settings that are now in effect.
+    pub applied: Vec<String>,
+    /// Changed settings that only take effect after restarting the game.
+    pub restart_required: Vec<String>,
+    /// What `validation::validate` had to say about the new profile.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+static LAST: Mutex<Option<Reload>> = Mutex::new(None);
+
+/// The most recent reload; `None` until the file has changed.
+pub fn last() -> Option<Reload> {
+    LAST.lock().ok()?.clone()
+}
+
+/// Settings that can change while the game runs, grouped by what applies them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Live {
+    Logging,
+    BufferMode,
+    PacerBias,
+    BufferPolicy,
+    ProfilingSinks,
+    Profiling,
+    HotReload,
+}
+
+impl Live {
+    const ALL: [Self; 7] = [
+        Self::Logging,
+        Self::BufferMode,
+        Self::PacerBias,
+        Self::BufferPolicy,
+        Self::ProfilingSinks,
+        Self::Profiling,
+        Self::HotReload,
+    ];
+
+    fn fields(self) -> &'static [&'static str] {
+        match self {
+            Self::Logging => &["log_level", "log_output"],
+            Self::BufferMode => &["enable_triple_buffer", "online_only"],
+            Self::PacerBias => &["slow_pacer_bias", "pacer_bias_double", "pacer_bias_triple"],
+            Self::BufferPolicy => &[
+                "auto_buffer_policy",
+                "triple_fighter_threshold",
+                "double_fighter_threshold",
+                "heavy_fighter_weight",
+                "buffer_policy_hold_frames",
+            ],
+            Self::ProfilingSinks => &[
+                "profiling_sink",
+                "profiling_capture_rotate_mb",
+                "profiling_capture_max_files",
+                "profiling_capture_max_seconds",
+                "profiling_backlog_frames",
+            ],
+            Self::Profiling => &["profiling"],
+            Self::HotReload => &["hot_reload"],
+        }
+    }
+
+    /// Copies this group's settings from `from`.
+    fn take(self, into: &mut SsbuSyncConfig, from: &SsbuSyncConfig) {
+        match self {
+            Self::Logging => {
+                into.log_level = from.log_level;
+                into.log_output = from.log_output;
+            }
+            Self::BufferMode => {
+                into.enable_triple_buffer = from.enable_triple_buffer;
+                into.online_only = from.online_only;
+            }
+            Self::PacerBias => {
+                into.slow_pacer_bias = from.slow_pacer_bias;
+                into.pacer_bias_double = from.pacer_bias_double;
+                into.pacer_bias_triple = from.pacer_bias_triple;
+            }
+            Self::BufferPolicy => {
+                into.auto_buffer_policy = from.auto_buffer_policy;
+                into.triple_fighter_threshold = from.triple_fighter_threshold;
+                into.double_fighter_threshold = from.double_fighter_threshold;
+                into.heavy_fighter_weight = from.heavy_fighter_weight;
+                into.buffer_policy_hold_frames = from.buffer_policy_hold_frames;
+            }
+            Self::ProfilingSinks => {
+                into.profiling_sink = from.profiling_sink;
+                into.profiling_capture_rotate_mb = from.profiling_capture_rotate_mb;
+                into.profiling_capture_max_files = from.profiling_capture_max_files;
+                into.profiling_capture_max_seconds = from.profiling_capture_max_seconds;
+                into.profiling_backlog_frames = from.profiling_backlog_frames;
+            }
+            Self::Profiling => into.profiling = from.profiling,
+            Self::HotReload => into.hot_reload = from.hot_reload,
+        }
+    }
+
+    fn covers(self, field: &str) -> bool {
+        self.fields().contains(&field)
+    }
+
+    /// Puts this group's settings from `config` into effect. False if that takes a restart.
+    fn apply(self, config: &SsbuSyncConfig) -> bool {
+        match self {
+            Self::Logging => crate::logging::configure(config),
+            Self::BufferMode => {
+                // Emulator keeps its own frame index policy, so there's nothing to switch.
+                if config.emulator_check {
+                    return true;
+                }
+                let mode = if config.enable_triple_buffer || config.online_only {
+                    BufferMode::Triple
+                } else {
+                    BufferMode::Double
+                };
+                let mut pending = PENDING_SWAP.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                match swap_to(mode) {
+                    Swap::Done => *pending = None,
+                    Swap::Retry => *pending = Some(mode),
+                    Swap::Refused(err) => {
+                        tracing::warn!(target: "reload", "can't switch to {:?} buffering live: {}", mode, err);
+                        return false;
+                    }
+                }
+            }
+            Self::PacerBias => crate::pacer::reload_bias(config),
+            Self::BufferPolicy => crate::render::policy::install(config),
+            Self::ProfilingSinks => {
+                // Without a running nprof profiler the sink settings don't do anything yet either way.
+                if config.profiling && config.profiling_backend.nprof() {
+                    return crate::profiling::reconfigure(config);
+                }
+            }
+            Self::Profiling => {
+                // Starting a tracer and stopping the nprof profiler both take a restart; a frametracer session can end.
+                if config.profiling || config.profiling_backend.nprof() {
+                    return false;
+                }
+                crate::frametracer::finish();
+            }
+            Self::HotReload => {}
+        }
+        true
+    }
+}
+
+enum Swap {
+    Done,
+    /// A swap is cooling down or the window isn't ready; try again on the next poll.
+    Retry,
+    Refused(ControlError),
+}
+
+fn swap_to(mode: BufferMode) -> Swap {
+    match control::request_buffer_mode(mode) {
+        Ok(()) | Err(ControlError::AlreadyInMode(_)) => Swap::Done,
+        Err(ControlError::Cooldown | ControlError::WindowNotReady) => Swap::Retry,
+        Err(err) => Swap::Refused(err),
+    }
+}
+
+/// A swap that was asked for while another one was cooling down.
+static PENDING_SWAP: Mutex<Option<BufferMode>> = Mutex::new(None);
+
+/// Tries the pending swap again, if there is one. Called every presented frame.
+pub(crate) fn retry_pending_swap() {
+    let Ok(mut pending) = PENDING_SWAP.try_lock() else {
+        return;
+    };
+    let Some(mode) = *pending else {
+        return;
+    };
+    *pending = match swap_to(mode) {
+        Swap::Retry => Some(mode),
+        Swap::Done => None,
+        Swap::Refused(err) => {
+            tracing::warn!(target: "reload", "gave up switching to {:?} buffering: {}", mode, err);
+            None
+        }
+    };
+}
+
+/// Settings whose values differ between `before` and `after`.
+pub(crate) fn changed_fields(before: &SsbuSyncConfig, after: &SsbuSyncConfig) -> Vec<String> {
+    let before = Config::config_fields(before);
+    Config::config_fields(after)
+        .into_iter()
+        .filter(|(field, value)| before.get(field) != Some(value))
+        .map(|(field, _)| field)
+        .collect()
+}
+
+/// Makes `next` the running config. Each group with a setting that differs from the running config is applied live;
+/// returns the settings that were and the ones that need a restart, which keep their running value.
+pub(crate) fn apply_running(next: &SsbuSyncConfig) -> (Vec<String>, Vec<String>) {
+    let Ok(mut running) = control::effective_config() else {
+        return (Vec::new(), Vec::new());
+    };
+    let changed = changed_fields(&running, next);
+    let mut applied = Vec::new();
+    let mut restart_required: Vec<String> = changed
+        .iter()
+        .filter(|field| !Live::ALL.iter().any(|group| group.covers(field)))
+        .cloned()
+        .collect();
+    for group in Live::ALL {
+        let fields: Vec<String> = changed.iter().filter(|field| group.covers(field)).cloned().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        let mut candidate = running;
+        group.take(&mut candidate, next);
+        if group.apply(&candidate) {
+            running = candidate;
+            applied.extend(fields);
+        } else {
+            restart_required.extend(fields);
+        }
+    }
+    control::set_effective_config(running);
+    (applied, restart_required)
+}
+
+struct Watcher {
+    path: Utf8PathBuf,
+    /// The profile as it was last read from the file.
+    profile: LoadedProfile,
+    modified: Option<u128>,
+}
+
+/// Modification time in milliseconds, `None` if the file can't be read.
+fn modified(path: &Utf8PathBuf) -> Option<u128> {
+    let modified = std::fs::metadata(path.as_std_path()).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_millis())
+}
+
+impl Watcher {
+    fn run(mut self) {
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let modified = modified(&self.path);
+            if modified == self.modified {
+                continue;
+            }
+            self.modified = modified;
+            if let Some(reload) = self.reload() {
+                if let Ok(mut last) = LAST.lock() {
+                    *last = Some(reload);
+                }
+                if !self.profile.config.hot_reload {
+                    tracing::info!(target: "reload", "hot_reload turned off; no longer watching {}", self.path);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn reload(&mut self) -> Option<Reload> {
+        let installed = control::validation().ok()?;
+        let name = self.profile.name.clone();
+        let read = match Config::read_profile(&name, &self.profile.config) {
+            Ok(Some(config)) => config,
+            Ok(None) => {
+                tracing::warn!(target: "reload", "profile {} is no longer in {}; keeping the current settings", name, self.path);
+                return None;
+            }
+            // `read_profile` already logged why.
+            Err(_) => return None,
+        };
+
+        let changed = changed_fields(&self.profile.config, &read);
+        self.profile.config = read;
+        if changed.is_empty() {
+            return None;
+        }
+
+        let mut requested = read;
+        requested.emulator_check = installed.effective.emulator_check;
+        let validation = validation::validate(&requested);
+        validation.log();
+
+        // Only the groups edited in the file move; settings the plugin set in code stay put.
+        let mut base = crate::overrides::base().unwrap_or(installed.effective);
+        for group in Live::ALL {
+            if changed.iter().any(|field| group.covers(field)) {
+                group.take(&mut base, &validation.effective);
+            }
+        }
+        let (_, refused) = crate::overrides::rebase(base);
+
+        let (applied, restart_required) = changed
+            .into_iter()
+            .partition(|field| Live::ALL.iter().any(|group| group.covers(field)) && !refused.contains(field));
+        let reload = Reload {
+            profile: name,
+            applied,
+            restart_required,
+            diagnostics: validation.diagnostics.clone(),
+        };
+
+        if !reload.applied.is_empty() {
+            tracing::info!(target: "reload", "profile {}: applied {}", reload.profile, reload.applied.join(", "));
+        }
+        if !reload.restart_required.is_empty() {
+            tracing::warn!(
+                target: "reload",
+                "profile {}: {} changed but only take effect after restarting the game",
+                reload.profile,
+                reload.restart_required.join(", ")
+            );
+        }
+        if let Ok(effective) = control::effective_config() {
+            control::set_validation(Validation {
+                effective,
+                diagnostics: validation.diagnostics,
+            });
+        }
+        Some(reload)
+    }
+}
+
+/// Starts watching `profile`, the one the installed config came from.
+pub(crate) fn install(config: &SsbuSyncConfig, profile: Option<LoadedProfile>) {
+    if !config.hot_reload {
+        return;
+    }
+    let Some(profile) = profile else {
+        tracing::warn!(target: "reload", "hot_reload needs the config to come from a profile in ssbusync.toml; not watching");
+        return;
+    };
+    let path = paths::ssbusync_config();
+    tracing::info!(target: "reload", "watching {} for changes to profile {}", path, profile.name);
+    let watcher = Watcher {
+        modified: modified(&path),
+        path,
+        profile,
+    };
+    std::thread::spawn(move || watcher.run());
+}
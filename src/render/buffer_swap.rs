@@ -2,31 +2,9 @@ use std::sync::atomic::{AtomicU8, Ordering};
 use crate::pacer::*;
 use crate::swapchain::*;
 use crate::SyncEnv;
+use crate::control::ControlError;
 
-/// Current swapchain buffer mode.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u8)]
-pub enum BufferMode {
-    /// lower latency
-    Double = 2,
-    /// default SSBU behaviour, higher latency.
-    Triple = 3,
-}
-
-impl BufferMode {
-    fn from_u8(v: u8) -> Option<Self> {
-        match v {
-            2 => Some(Self::Double),
-            3 => Some(Self::Triple),
-            _ => None,
-        }
-    }
-
-    /// Number of active textures for this mode.
-    pub fn texture_count(self) -> i32 {
-        self as i32
-    }
-}
+pub use ncore::buffer::BufferMode;
 
 /// Guard against immediate mode thrashing after a completed swap.
 static SWAP_COOLDOWN_FRAMES: AtomicU8 = AtomicU8::new(0);
@@ -96,53 +74,77 @@ pub fn get_window_texture_capacity() -> Option<i32> {
 
 /// Begin changing buffer
 pub fn start_swap_buffer(mode: BufferMode) -> bool {
-    if crate::is_emulator() {
-        println!("[ssbu-sync] buffer swap not allowed on emulator!");
-        return false;
+    match try_start_swap_buffer(mode) {
+        Ok(()) => true,
+        Err(ControlError::NotAllowedOnEmulator) => {
+            tracing::warn!(target: "buffer_swap", "buffer swap not allowed on emulator!");
+            false
+        }
+        Err(_) => false,
+    }
+}
+
+/// Begin changing buffer, reporting why the swap could not start.
+pub fn try_start_swap_buffer(mode: BufferMode) -> Result<(), ControlError> {
+    if SyncEnv::emulator_value() {
+        return Err(ControlError::NotAllowedOnEmulator);
     }
     if SWAP_COOLDOWN_FRAMES.load(Ordering::Acquire) != 0 {
-        return false;
+        return Err(ControlError::Cooldown);
     }
     let prev_triple = crate::SyncEnv::triple_enabled();
     if prev_triple == (mode == BufferMode::Triple) && !is_buffer_swapping() {
-        return false; // already in this mode
+        return Err(ControlError::AlreadyInMode(mode));
     }
 
     let texture_count = get_active_texture_count();
     let capacity = get_window_texture_capacity();
     if let Some(active) = texture_count {
-        println!("[ssbu-sync] window texture count: {}\n", active);
+        tracing::debug!(target: "buffer_swap", "window texture count: {}", active);
     }
     if let Some(total) = capacity {
-        println!("[ssbu-sync] window texture capacity: {}\n", total);
+        tracing::debug!(target: "buffer_swap", "window texture capacity: {}", total);
     }
 
     let desired = mode.texture_count();
     if texture_count == Some(desired) {
         SyncEnv::set_swapping_buffer(false);
         SyncEnv::set_triple_enabled(mode == BufferMode::Triple);
-        println!("[ssbu-sync] window already correct texture count\n");
-        return false;
+        tracing::info!(target: "buffer_swap", "window already correct texture count");
+        return Err(ControlError::AlreadyInMode(mode));
     }
 
-    println!(
-        "[ssbu-sync] texture count before swap: {}\n",
+    tracing::debug!(
+        target: "buffer_swap",
+        "texture count before swap: {}",
         texture_count
             .map(|v| v.to_string())
             .unwrap_or_else(|| "unknown".to_string())
     );
 
+    if let Some(total) = capacity {
+        if total < desired {
+            return Err(ControlError::InsufficientCapacity {
+                requested: desired,
+                capacity: total,
+            });
+        }
+    }
+
     if !try_set_window_textures(desired) {
-        println!("[ssbu-sync] unable to start swap for {:?}\n", mode);
-        return false;
+        tracing::warn!(target: "buffer_swap", "unable to start swap for {:?}", mode);
+        return Err(ControlError::WindowNotReady);
     }
 
     crate::SyncEnv::set_swapping_buffer(true);
     crate::SyncEnv::set_triple_enabled(mode == BufferMode::Triple);
-    println!("[ssbu-sync] Swapping buffer mode to {:?} ...\n", mode);
-    true
+    tracing::info!(target: "buffer_swap", "Swapping buffer mode to {:?} ...", mode);
+    Ok(())
 }
 
+/// Finishes a pending swap once the window reports the new texture count.
+///
+/// ssbusync calls this once per frame from the present hook; calling it again from a plugin is harmless.
 pub fn check_swap_finished() {
     let cooldown = SWAP_COOLDOWN_FRAMES.load(Ordering::Acquire);
     if cooldown != 0 {
@@ -161,13 +163,12 @@ pub fn check_swap_finished() {
 }
 
 fn finish_install_buffer(mode: BufferMode) {
-    let triple = mode == BufferMode::Triple;
-    //set_runtime_frame_index_mode(triple);
-    patch_pacer_bias(triple);
+    set_frame_index_policy(FrameIndexPolicy::for_buffer_mode(mode));
+    apply_pacer_bias(mode);
     SyncEnv::set_swapping_buffer(false);
     SyncEnv::set_triple_enabled(mode == BufferMode::Triple);
     SWAP_COOLDOWN_FRAMES.store(6, Ordering::Release);
-    println!("[ssbu-sync] patched new buffer mode {:?} ...\n", mode);
+    tracing::info!(target: "buffer_swap", "patched new buffer mode {:?} ...", mode);
     fire_callbacks(mode);
 }
 
@@ -192,10 +193,10 @@ pub fn init_buffer_mode(mode: BufferMode) {
     SWAP_COOLDOWN_FRAMES.store(0, Ordering::Release);
     
     if crate::is_emulator() {
-        println!("[ssbu-sync] cant set buffer mode on emulator");
+        tracing::warn!(target: "buffer_swap", "cant set buffer mode on emulator");
     }
     
-    println!("[ssbu-sync] Initializing Buffer Mode {:?}", mode);
+    tracing::info!(target: "buffer_swap", "Initializing Buffer Mode {:?}", mode);
 }
 
 fn fire_callbacks(mode: BufferMode) {
@@ -1 +1,2 @@
 pub mod buffer_swap;
+pub mod policy;
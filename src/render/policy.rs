@@ -0,0 +1,159 @@
+/** Runtime driver for the buffer policy
+ *
+ * `BufferPolicy` (in `ncore::policy`) is the decision logic. `tick` samples the match load every frame, asks the policy
+ * what it wants and starts the swap through `buffer_swap`.
+ */
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use super::buffer_swap::*;
+use crate::{SsbuSyncConfig, SyncEnv};
+
+pub use ncore::policy::{is_heavy_fighter_kind, BufferPolicy, MatchLoad, PolicyThresholds};
+
+static POLICY: Mutex<Option<BufferPolicy>> = Mutex::new(None);
+
+const REPORTED_BIT: u32 = 1 << 16;
+static REPORTED_LOAD: AtomicU32 = AtomicU32::new(0);
+
+/// Reports the current match load, for plugins that know more about the match than a fighter count.
+///
+/// Overrides the automatic fighter count until [`clear_match_load`] is called.
+pub fn report_match_load(load: MatchLoad) {
+    let packed = REPORTED_BIT | (load.heavy_fighters as u32) << 8 | load.fighters as u32;
+    REPORTED_LOAD.store(packed, Ordering::Release);
+}
+
+pub fn clear_match_load() {
+    REPORTED_LOAD.store(0, Ordering::Release);
+}
+
+fn reported_load() -> Option<MatchLoad> {
+    let packed = REPORTED_LOAD.load(Ordering::Acquire);
+    if packed & REPORTED_BIT == 0 {
+        return None;
+    }
+    Some(MatchLoad::new(packed as u8, (packed >> 8) as u8))
+}
+
+unsafe fn lookup(sym_nul: &[u8]) -> Option<usize> {
+    let mut addr = 0usize;
+    let rc = skyline::nn::ro::LookupSymbol(&mut addr, sym_nul.as_ptr());
+    if rc == 0 && addr != 0 {
+        Some(addr)
+    } else {
+        None
+    }
+}
+
+/// The game functions `fighter_load` reads the match through.
+struct FighterSymbols {
+    /// `lib::Singleton<app::FighterManager>::instance_`
+    manager: usize,
+    entry_count: extern "C" fn(u64) -> i32,
+    id_from_entry_id: extern "C" fn(i32) -> u32,
+    is_active: extern "C" fn(u32) -> bool,
+    module_accessor: extern "C" fn(u32) -> u64,
+    get_kind: extern "C" fn(u64) -> i32,
+}
+
+fn fighter_symbols() -> Option<&'static FighterSymbols> {
+    static SYMBOLS: OnceLock<Option<FighterSymbols>> = OnceLock::new();
+    SYMBOLS
+        .get_or_init(|| unsafe {
+            let symbols = (|| {
+                Some(FighterSymbols {
+                    manager: lookup(b"_ZN3lib9SingletonIN3app14FighterManagerEE9instance_E\0")?,
+                    entry_count: core::mem::transmute::<usize, extern "C" fn(u64) -> i32>(lookup(
+                        b"_ZN3app8lua_bind32FighterManager__entry_count_implEPNS_14FighterManagerE\0",
+                    )?),
+                    id_from_entry_id: core::mem::transmute::<usize, extern "C" fn(i32) -> u32>(lookup(
+                        b"_ZN3app7Fighter20get_id_from_entry_idEi\0",
+                    )?),
+                    is_active: core::mem::transmute::<usize, extern "C" fn(u32) -> bool>(lookup(
+                        b"_ZN3app16sv_battle_object9is_activeEj\0",
+                    )?),
+                    module_accessor: core::mem::transmute::<usize, extern "C" fn(u32) -> u64>(lookup(
+                        b"_ZN3app16sv_battle_object15module_accessorEj\0",
+                    )?),
+                    get_kind: core::mem::transmute::<usize, extern "C" fn(u64) -> i32>(lookup(
+                        b"_ZN3app7utility8get_kindEPKNS_26BattleObjectModuleAccessorE\0",
+                    )?),
+                })
+            })();
+            if symbols.is_none() {
+                tracing::warn!(target: "buffer_policy", "FighterManager symbols not found; buffer policy needs report_match_load");
+            }
+            symbols
+        })
+        .as_ref()
+}
+
+/// Fighter entries in the current match and how many of them are heavy, read from the game's `FighterManager`.
+fn fighter_load() -> Option<MatchLoad> {
+    let symbols = fighter_symbols()?;
+    unsafe {
+        let manager = *(symbols.manager as *const u64);
+        if manager == 0 {
+            return None;
+        }
+        let fighters = (symbols.entry_count)(manager).clamp(0, u8::MAX as i32) as u8;
+        let mut heavy_fighters = 0u8;
+        for entry in 0..fighters {
+            let id = (symbols.id_from_entry_id)(entry as i32);
+            if !(symbols.is_active)(id) {
+                continue;
+            }
+            let accessor = (symbols.module_accessor)(id);
+            if accessor != 0 && is_heavy_fighter_kind((symbols.get_kind)(accessor)) {
+                heavy_fighters += 1;
+            }
+        }
+        Some(MatchLoad::new(fighters, heavy_fighters))
+    }
+}
+
+fn sample_load() -> Option<MatchLoad> {
+    reported_load().or_else(fighter_load)
+}
+
+/// Runs once per frame, after `check_swap_finished`. Starts a swap when the policy asks for one.
+pub fn tick() {
+    if !SyncEnv::auto_buffer_policy() {
+        return;
+    }
+    if is_buffer_swapping() {
+        return;
+    }
+
+    let Some(load) = sample_load() else {
+        return;
+    };
+    let active = current_buffer_mode();
+    // This runs on the present hook; if install or a reload holds the policy, skip this frame rather than wait.
+    let switch_to = match POLICY.try_lock() {
+        Ok(mut policy) => policy.as_mut().and_then(|policy| policy.update(load, active)),
+        Err(_) => None,
+    };
+
+    if let Some(mode) = switch_to {
+        tracing::info!(
+            target: "buffer_policy",
+            "{} fighters ({} heavy) -> {:?}",
+            load.fighters, load.heavy_fighters, mode
+        );
+        start_swap_buffer(mode);
+    }
+}
+
+pub fn install(config: &SsbuSyncConfig) {
+    // `validation` already turned this off (and said why) when the rest of the config can't support it.
+    let enabled = config.auto_buffer_policy
+        && config.allow_buffer_swap
+        && !config.emulator_check;
+
+    if let Ok(mut policy) = POLICY.lock() {
+        *policy = Some(BufferPolicy::new(PolicyThresholds::from_config(config)));
+    }
+    SyncEnv::set_auto_buffer_policy(enabled);
+}
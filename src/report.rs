@@ -0,0 +1,144 @@
+/** Install report
+ *
+ * `Install_SSBU_Sync` is handed a finished `SsbuSyncConfig`, so afterwards there was no telling which profile it came
+ * from or what the emulator check and validation changed. `write` records it in
+ * `sd:/ultimate/ssbusync/last_install.toml`: every setting with its value and where that value came from, then the
+ * hooks and patches that went in. It is rewritten on every install.
+ *
+ * A setting's source is worked out by comparing values, since the config doesn't carry where it came from:
+ *
+ * - `emulator`: changed because the game is running on emulator
+ * - `validation`: changed by `validation::validate`, with the diagnostic as `reason`
+ * - `profile:<name>`: matches the loaded profile the caller's config agrees with most
+ * - `default`: matches `SsbuSyncConfig::default()`
+ * - `caller`: anything else, i.e. set by the plugin that called `Install_SSBU_Sync`
+ */
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use toml::Value;
+
+use crate::util::file::paths;
+use crate::validation::{self, Validation};
+use crate::Config::config_fields as fields;
+use crate::{patches, swapchain, Config, SsbuSyncConfig};
+
+#[derive(Debug, Serialize)]
+struct Report {
+    install: InstallInfo,
+    settings: BTreeMap<String, Setting>,
+    patches: Vec<PatchEntry>,
+}
+
+#[derive(Debug, Serialize)]
+struct InstallInfo {
+    ssbusync_version: &'static str,
+    game_version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    game_build_id: Option<String>,
+    emulator: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    frame_index_policy: String,
+    hooks: Vec<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+struct Setting {
+    value: Value,
+    source: String,
+    /// What the caller asked for, when something overrode it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requested: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct PatchEntry {
+    name: &'static str,
+    offset: String,
+    original: String,
+    replacement: String,
+}
+
+fn build(requested: &SsbuSyncConfig, validation: &Validation) -> Report {
+    let asked = fields(requested);
+    let effective = fields(&validation.effective);
+    let defaults = fields(&SsbuSyncConfig::default());
+    // What validation alone would have done; anything past that is down to the emulator.
+    let mut console = *requested;
+    console.emulator_check = false;
+    let console = fields(&validation::validate(&console).effective);
+    let profile = Config::matching_loaded_profile(requested)
+        .map(|profile| (profile.name, fields(&profile.config)));
+
+    let settings = effective
+        .into_iter()
+        .map(|(key, value)| {
+            let requested = asked.get(&key).cloned();
+            let source = if requested.as_ref() != Some(&value) {
+                if console.get(&key) == Some(&value) {
+                    "validation".to_string()
+                } else {
+                    "emulator".to_string()
+                }
+            } else if let Some((name, _)) = profile.as_ref().filter(|(_, table)| table.get(&key) == Some(&value)) {
+                format!("profile:{}", name)
+            } else if defaults.get(&key) == Some(&value) {
+                "default".to_string()
+            } else {
+                "caller".to_string()
+            };
+            let reason = validation
+                .diagnostics
+                .iter()
+                .find(|diagnostic| diagnostic.field == key)
+                .map(|diagnostic| diagnostic.message.clone());
+            let setting = Setting {
+                requested: requested.filter(|requested| requested != &value),
+                value,
+                source,
+                reason,
+            };
+            (key, setting)
+        })
+        .collect();
+
+    Report {
+        install: InstallInfo {
+            ssbusync_version: env!("CARGO_PKG_VERSION"),
+            game_version: crate::util::offsets::running_display_version(),
+            game_build_id: crate::util::offsets::running_build_id(),
+            emulator: requested.emulator_check,
+            profile: profile.map(|(name, _)| name),
+            frame_index_policy: format!("{:?}", swapchain::frame_index_policy()),
+            hooks: patches::hooks(),
+        },
+        settings,
+        patches: patches::applied()
+            .into_iter()
+            .map(|patch| PatchEntry {
+                name: patch.spec.name,
+                offset: format!("0x{:x}", patch.spec.offset),
+                original: patch.original.to_string(),
+                replacement: patch.spec.replacement.to_string(),
+            })
+            .collect(),
+    }
+}
+
+/// Writes `last_install.toml`. Failing to is only worth a warning; the install itself already happened.
+pub(crate) fn write(requested: &SsbuSyncConfig, validation: &Validation) {
+    let path = paths::last_install_report();
+    let text = match toml::to_string_pretty(&build(requested, validation)) {
+        Ok(text) => text,
+        Err(err) => {
+            tracing::warn!(target: "install", "failed serializing the install report: {}", err);
+            return;
+        }
+    };
+    if let Err(err) = paths::ensure_paths_exist().and_then(|()| std::fs::write(path.as_std_path(), text)) {
+        tracing::warn!(target: "install", "failed writing {}: {}", path, err);
+    }
+}
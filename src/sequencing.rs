@@ -3,17 +3,23 @@ use std::{
     time::Instant,
 };
 use symbaker::symbaker;
-use skyline::{hooks::InlineCtx, patching::Patch};
+use skyline::hooks::InlineCtx;
 
-use crate::{profiling::OsTick, vsync_history::get_system_tick};
+use crate::{
+    patches::{self, PatchError, PatchSpec, Transaction},
+    profiling::OsTick,
+    util::offsets::offsets,
+    vsync_history::get_system_tick,
+};
 
-fn patch_scene_manager_calls() {
-    Patch::in_text(0x374c624).nop().unwrap();
-    Patch::in_text(0x374c2dc).nop().unwrap();
-    Patch::in_text(0x374c410).nop().unwrap();
+fn patch_scene_manager_calls(transaction: &mut Transaction) -> Result<(), PatchError> {
+    let [first, second, third] = offsets().scene_manager_calls;
+    transaction.apply(PatchSpec::nop("scene_manager_call_0", first))?;
+    transaction.apply(PatchSpec::nop("scene_manager_call_1", second))?;
+    transaction.apply(PatchSpec::nop("scene_manager_call_2", third))
 }
 
-#[skyline::from_offset(0x3724a80)]
+#[skyline::from_offset(offsets().run_scene_manager)]
 unsafe fn run_scene_manager_impl(ptr: u64);
 
 static SHOULD_RUN: AtomicBool = AtomicBool::new(true);
@@ -34,11 +40,11 @@ static mut TOP: Option<Instant> = None;
 // 374b4d4
 // Deprecated scene manager hook 
 #[symbaker]
-#[skyline::hook(offset = 0x374b290, inline)]
+#[skyline::hook(offset = offsets().run_scene_manager_call, inline)]
 pub unsafe fn run_scene_manager(ctx: &InlineCtx) {
     let ptr = *skyline::hooks::getRegionAddress(skyline::hooks::Region::Text)
         .cast::<u8>()
-        .add(0x593a4c0)
+        .add(offsets().scene_manager)
         .cast::<u64>();
 
     TOP = Some(Instant::now());
@@ -60,7 +66,7 @@ pub unsafe fn run_scene_manager(ctx: &InlineCtx) {
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x374c050, inline)]
+#[skyline::hook(offset = offsets().frame_end, inline)]
 unsafe fn log_frame_duration(ctx: &InlineCtx) {
     // println!(
     //     "CPU Frame took {:.3}ms",
@@ -69,7 +75,7 @@ unsafe fn log_frame_duration(ctx: &InlineCtx) {
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x374b130, inline)]
+#[skyline::hook(offset = offsets().looping_begin, inline)]
 unsafe fn run_scene_wait(ctx: &InlineCtx) {
     // println!(
     //     "in paralle: {:.3}ms",
@@ -80,12 +86,12 @@ unsafe fn run_scene_wait(ctx: &InlineCtx) {
     // }
 }
 
-// #[skyline::hook(offset = 0x374b290, inline)]
+// #[skyline::hook(offset = offsets().run_scene_manager_call, inline)]
 // unsafe fn log_thing(_: &InlineCtx) {
 //     TOP = Some(Instant::now());
 // }
 
-// #[skyline::hook(offset = 0x374b4f4, inline)]
+// #[skyline::hook(offset = offsets().init_ui_task_worker_call, inline)]
 // unsafe fn log_thing_2(_: &InlineCtx) {
 //     println!(
 //         "Duration: {:.3}ms",
@@ -94,22 +100,22 @@ unsafe fn run_scene_wait(ctx: &InlineCtx) {
 // }
 
 #[symbaker]
-#[skyline::hook(offset = 0x386fc80, inline)]
+#[skyline::hook(offset = offsets().present_texture_call, inline)]
 unsafe fn wait_on_present_sync(ctx: &InlineCtx) {
     static mut NVN_SYNC_WAIT: Option<extern "C" fn(u64, u64)> = None;
     if NVN_SYNC_WAIT.is_none() {
         let base = skyline::hooks::getRegionAddress(skyline::hooks::Region::Text).cast::<u8>();
-        NVN_SYNC_WAIT = Some(*base.add(0x5940878).cast::<extern "C" fn(u64, u64)>())
+        NVN_SYNC_WAIT = Some(*base.add(offsets().nvn_sync_wait).cast::<extern "C" fn(u64, u64)>())
     }
     let now = Instant::now();
     let nvn_sync_wait = NVN_SYNC_WAIT.unwrap_unchecked();
     let sync = *((ctx.registers[23].x() + 0x28) as *const u64);
     nvn_sync_wait(sync, u64::MAX);
-    println!("Stalled {:.3}ms", now.elapsed().as_micros() as f32 / 1000.0);
+    tracing::debug!(target: "sequencing", "Stalled {:.3}ms", now.elapsed().as_micros() as f32 / 1000.0);
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x386fcdc, inline)]
+#[skyline::hook(offset = offsets().queue_wait_sync_call, inline)]
 unsafe fn queue_wait_sync(ctx: &InlineCtx) {
     static mut NVN_QUEUE_WAIT_SYNC: Option<extern "C" fn(u64, u64) -> bool> = None;
 
@@ -117,7 +123,7 @@ unsafe fn queue_wait_sync(ctx: &InlineCtx) {
         let base = skyline::hooks::getRegionAddress(skyline::hooks::Region::Text).cast::<u8>();
         NVN_QUEUE_WAIT_SYNC = Some(
             *base
-                .add(0x5940880)
+                .add(offsets().nvn_queue_wait_sync)
                 .cast::<extern "C" fn(u64, u64) -> bool>(),
         )
     }
@@ -125,15 +131,19 @@ unsafe fn queue_wait_sync(ctx: &InlineCtx) {
     let nvn_queue_wait_sync = NVN_QUEUE_WAIT_SYNC.unwrap_unchecked();
     let queue = *((ctx.registers[23].x() + 0x18) as *const u64);
     nvn_queue_wait_sync(queue, ctx.registers[0].x());
-    println!("here");
+    tracing::trace!(target: "sequencing", "here");
+}
+
+fn patch_sync_wait(transaction: &mut Transaction) -> Result<(), PatchError> {
+    transaction.apply(PatchSpec::nop("queue_wait_sync_call", offsets().queue_wait_sync_call))
 }
 
-fn patch_sync_wait() {
-    Patch::in_text(0x386fcdc).nop().unwrap();
+/// Writes this module's patches; `install` puts the hook in once every module's patches went in.
+pub fn patch(transaction: &mut Transaction) -> Result<(), PatchError> {
+    patch_scene_manager_calls(transaction)?;
+    patch_sync_wait(transaction)
 }
 
 pub fn install() {
-    patch_scene_manager_calls();
-    patch_sync_wait();
-    skyline::install_hooks!(run_scene_manager);
+    patches::install_hooks!(run_scene_manager);
 }
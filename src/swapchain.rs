@@ -1,7 +1,9 @@
 use super::*;
 use crate::render::buffer_swap::*;
-use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
 use symbaker::{symbaker};
+use crate::patches::{self, PatchError, PatchSpec, Transaction};
+use crate::util::offsets::offsets;
 
 static WINDOW_TARGET: AtomicU64 = AtomicU64::new(0);
 static PENDING_WINDOW_TEXTURES: AtomicU8 = AtomicU8::new(0);
@@ -37,14 +39,15 @@ pub(crate) fn observe_window_target(window_target: u64, source: &str) {
 
     if !valid {
         WINDOW_TARGET.store(encode_window_target(window_target), Ordering::Release);
-        println!("[ssbu-sync] cached window target from {source}: 0x{window_target:x}");
+        tracing::debug!(target: "swapchain", "cached window target from {source}: 0x{window_target:x}");
         return;
     }
 
     if current != window_target {
         WINDOW_TARGET.store(encode_window_target(window_target), Ordering::Release);
-        println!(
-            "[ssbu-sync] window target updated from {source}: 0x{current:x} -> 0x{window_target:x}"
+        tracing::debug!(
+            target: "swapchain",
+            "window target updated from {source}: 0x{current:x} -> 0x{window_target:x}"
         );
     }
 }
@@ -72,7 +75,7 @@ unsafe fn resolve_set_window_num_active_textures_fn() -> extern "C" fn(u64, i32)
 
     let func_ptr = *skyline::hooks::getRegionAddress(skyline::hooks::Region::Text)
         .cast::<u8>()
-        .add(0x593fb80)
+        .add(offsets().nvn_window_set_num_active_textures)
         .cast::<extern "C" fn(u64, i32)>();
     SET_WINDOW_NUM_ACTIVE_TEXTURES_FN = Some(func_ptr);
     func_ptr
@@ -85,7 +88,7 @@ unsafe fn resolve_get_window_num_active_textures_fn() -> extern "C" fn(u64) -> i
 
     let func_ptr = *skyline::hooks::getRegionAddress(skyline::hooks::Region::Text)
         .cast::<u8>()
-        .add(0x593fb88)
+        .add(offsets().nvn_window_get_num_active_textures)
         .cast::<extern "C" fn(u64) -> i32>();
     GET_WINDOW_NUM_ACTIVE_TEXTURES_FN = Some(func_ptr);
     func_ptr
@@ -98,7 +101,7 @@ unsafe fn resolve_get_window_num_textures_fn() -> extern "C" fn(u64) -> i32 {
 
     let func_ptr = *skyline::hooks::getRegionAddress(skyline::hooks::Region::Text)
         .cast::<u8>()
-        .add(0x593fb90)
+        .add(offsets().nvn_window_get_num_textures)
         .cast::<extern "C" fn(u64) -> i32>();
     GET_WINDOW_NUM_TEXTURES_FN = Some(func_ptr);
     func_ptr
@@ -130,8 +133,9 @@ pub fn apply_pending_window_texture_request(window_target: u64, source: &str) ->
         let current = resolve_get_window_num_active_textures_fn()(window_target);
         if current == requested as i32 {
             PENDING_WINDOW_TEXTURES.store(0, Ordering::Release);
-            println!(
-                "[ssbu-sync] applied queued window texture request from {source}: {requested}"
+            tracing::info!(
+                target: "swapchain",
+                "applied queued window texture request from {source}: {requested}"
             );
             return true;
         }
@@ -234,7 +238,7 @@ unsafe fn cache_window_target_from_ctx(ctx: &skyline::hooks::InlineCtx) -> u64 {
  * in situations where frame drops happen.
  */
 #[symbaker]
-#[skyline::hook(offset = 0x3747b78, inline)]
+#[skyline::hook(offset = offsets().present_flush_call, inline)]
 unsafe fn flush_swap_buffers_before_present(ctx: &skyline::hooks::InlineCtx) {
     // SAFETY: This method is only ever called in one spot, this is effectively a local variable that we are using
     //      to cache the pointer.
@@ -247,7 +251,7 @@ unsafe fn flush_swap_buffers_before_present(ctx: &skyline::hooks::InlineCtx) {
         POINTER = Some(
             *skyline::hooks::getRegionAddress(skyline::hooks::Region::Text)
                 .cast::<u8>()
-                .add(0x5334e90)
+                .add(offsets().swapchain_singleton)
                 .cast::<*const ()>(),
         );
     }
@@ -260,6 +264,7 @@ unsafe fn flush_swap_buffers_before_present(ctx: &skyline::hooks::InlineCtx) {
         .add(0x3)
         .cast::<extern "C" fn(*const (), u8)>();
 
+    crate::frametracer::marker("PresentFlush", crate::vsync_history::get_system_tick());
     function(sub_ptr, flag);
     *ptr.cast::<u8>().cast_mut().add(0xec) = 0;
 }
@@ -271,13 +276,14 @@ unsafe fn flush_swap_buffers_before_present(ctx: &skyline::hooks::InlineCtx) {
  * batch into the pDispatchNow batch (first invocation) then await the pDispatchNow batch
  */
 #[symbaker]
-#[skyline::hook(offset = 0x384f460)]
+#[skyline::hook(offset = offsets().await_submit_dispatches)]
 unsafe fn full_swapchain_flush(arg1: u64, arg2: u32) {
     if *(arg1 as *const u8).add(0x1d18) != 0 {
         *(arg1 as *mut u8).add(0x1d30).cast::<u64>() =
             (!*(arg1 as *const u8).add(0x1d20).cast::<u32>() & 1) as u64;
         *(arg1 as *mut u8).add(0x1d28) = 1;
     }
+    crate::frametracer::marker("AwaitSubmitDispatches", crate::vsync_history::get_system_tick());
     call_original!(arg1, arg2)
     // static mut RUN_COUNT: usize = 0;
     // if RUN_COUNT == 1 {
@@ -291,7 +297,7 @@ unsafe fn full_swapchain_flush(arg1: u64, arg2: u32) {
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x384f460)]
+#[skyline::hook(offset = offsets().await_submit_dispatches)]
 unsafe fn emu_full_swapchain_flush(arg1: u64, arg2: u32) {
     call_original!(arg1, arg2);
     call_original!(arg1, arg2);
@@ -303,9 +309,9 @@ unsafe fn emu_full_swapchain_flush(arg1: u64, arg2: u32) {
  * Because we provide a new call in the `flush_swap_buffers_before_present` hook, we need to prevent the other one from being run.
  * Technically it might do nothing? But it might also have unintendended side-effects so it's more reliable for us to prevent it altogether
  */
-fn patch_swap_flush_call() {
-    skyline::patching::Patch::in_text(0x37495c8).nop().unwrap();
-    skyline::patching::Patch::in_text(0x37495cc).nop().unwrap();
+fn patch_swap_flush_call(transaction: &mut Transaction) -> Result<(), PatchError> {
+    transaction.apply(PatchSpec::nop("swap_flush_call", offsets().swap_flush_call))?;
+    transaction.apply(PatchSpec::nop("swap_flush_call_next", offsets().swap_flush_call + 4))
 }
 
 /** This changes the behavior of the nu::FrameBufferRenderTarget render command to use the current frame index
@@ -316,35 +322,45 @@ fn patch_swap_flush_call() {
  *
  * This patches an instruction `add w9, w9, #0x2` to be `nop`
  */
-fn use_current_frame_index() {
-    skyline::patching::Patch::in_text(0x386ab4c).nop().unwrap();
+fn use_current_frame_index(transaction: &mut Transaction) -> Result<(), PatchError> {
+    transaction.apply(PatchSpec::nop("frame_index_add", offsets().frame_index_add))
 }
 
-// FRAMES IN FLIGHT MANAGEMENT:
-// SSBU default path is effectively (+2) over a triple-buffered ring.
-// Console double-buffer mode uses (+1) % 2.
+pub use ncore::buffer::{compute_frame_index, FrameIndexPolicy};
+
+static FRAME_INDEX_POLICY: AtomicU8 = AtomicU8::new(FrameIndexPolicy::Default as u8);
+static FRAME_INDEX_HOOK_INSTALLED: AtomicBool = AtomicBool::new(false);
+
 #[symbaker]
-#[skyline::hook(offset = 0x386ab4c, inline)]
-fn use_next_frame_index_double(ctx: &mut skyline::hooks::InlineCtx) {
-    ctx.registers[9].set_x((ctx.registers[9].x() + 1) % 2);
+#[skyline::hook(offset = offsets().frame_index_add, inline)]
+fn frame_index_hook(ctx: &mut skyline::hooks::InlineCtx) {
+    let policy = frame_index_policy();
+    ctx.registers[9].set_x(compute_frame_index(policy, ctx.registers[9].x()));
 }
 
-#[symbaker]
-#[skyline::hook(offset = 0x386ab4c, inline)]
-fn use_next_frame_index_triple(ctx: &mut skyline::hooks::InlineCtx) {
-    ctx.registers[9].set_x((ctx.registers[9].x() + 1) % 3);
+pub fn frame_index_policy() -> FrameIndexPolicy {
+    FrameIndexPolicy::from_u8(FRAME_INDEX_POLICY.load(Ordering::Acquire))
+        .unwrap_or(FrameIndexPolicy::Default)
 }
 
-#[symbaker]
-#[skyline::hook(offset = 0x386ab4c, inline)]
-fn use_default_frame_index(ctx: &mut skyline::hooks::InlineCtx) {
-    ctx.registers[9].set_x((ctx.registers[9].x() + 2) % 3);
+/// Switches the policy the frame index hook uses. Takes effect on the next frame.
+pub fn set_frame_index_policy(policy: FrameIndexPolicy) {
+    let previous = FRAME_INDEX_POLICY.swap(policy as u8, Ordering::AcqRel);
+    if previous != policy as u8 {
+        tracing::info!(target: "swapchain", "frame index policy set to {:?}", policy);
+    }
 }
 
-#[symbaker]
-#[skyline::hook(offset = 0x386ab4c, inline)]
-fn emu_use_imm_frame_index(ctx: &mut skyline::hooks::InlineCtx) {
-    ctx.registers[9].set_x((ctx.registers[9].x()) % 2);
+/// Installs the frame index hook once; later calls only update the policy.
+fn install_frame_index_hook(policy: FrameIndexPolicy) {
+    if crate::util::offsets::loaded().is_none() {
+        tracing::warn!(target: "swapchain", "not switching the frame index policy: install was refused");
+        return;
+    }
+    set_frame_index_policy(policy);
+    if !FRAME_INDEX_HOOK_INSTALLED.swap(true, Ordering::AcqRel) {
+        patches::install_hooks!(frame_index_hook);
+    }
 }
 
 /** This disables a sync that is signaled by rendering wrapping up
@@ -354,22 +370,17 @@ fn emu_use_imm_frame_index(ctx: &mut skyline::hooks::InlineCtx) {
  * appear to have any known side effects (at least not when the swapchain is being patched, hence why it is in this file)
  *
  */
-fn patch_render_sync_wait() {
-    skyline::patching::Patch::in_text(0x386fcec)
-        .data(0xD2800000u32)
-        .unwrap();
+fn patch_render_sync_wait(transaction: &mut Transaction) -> Result<(), PatchError> {
+    transaction.apply(PatchSpec::data("render_sync_wait", offsets().render_sync_wait, 0xD2800000u32))
 }
 
-// fn restore_render_sync_wait() {
-//     // Original instruction at 0x386fcec:
-//     //   00 01 3f d6  =>  blr x8
-//     skyline::patching::Patch::in_text(0x386fcec)
-//         .data(0xD63F0100u32)
-//         .unwrap();
-// }
+/// Puts the render sync wait back (`blr x8`), e.g. when falling back to vanilla behavior.
+pub fn restore_render_sync_wait() -> Result<(), PatchError> {
+    patches::revert("render_sync_wait")
+}
 
 #[symbaker]
-#[skyline::hook(offset = 0x38601f8, inline)]
+#[skyline::hook(offset = offsets().set_double_window_textures, inline)]
 unsafe fn set_double_window_textures(ctx: &skyline::hooks::InlineCtx) {
     let window_target = *((ctx.registers[23].x() + 0x10) as *const u64);
     observe_window_target(window_target, "set_double_window_textures");
@@ -392,8 +403,9 @@ pub fn try_set_window_textures(num: i32) -> bool {
     unsafe {
         let total = resolve_get_window_num_textures_fn()(window);
         if total < requested as i32 {
-            println!(
-                "[ssbu-sync] cannot set active textures to {} because capacity is {}",
+            tracing::warn!(
+                target: "swapchain",
+                "cannot set active textures to {} because capacity is {}",
                 requested, total
             );
             return false;
@@ -407,8 +419,9 @@ pub fn try_set_window_textures(num: i32) -> bool {
         }
 
         PENDING_WINDOW_TEXTURES.store(requested, Ordering::Release);
-        println!(
-            "[ssbu-sync] deferred window texture request: requested={} current={}",
+        tracing::info!(
+            target: "swapchain",
+            "deferred window texture request: requested={} current={}",
             requested, active
         );
     }
@@ -417,58 +430,49 @@ pub fn try_set_window_textures(num: i32) -> bool {
 }
 
 pub fn toggle_one_ahead_index(one_ahead: bool) {
-    if SyncEnv::emulator_value() {
-        if one_ahead {
-            skyline::install_hooks!(emu_use_imm_frame_index);
-            return
-        }
-        skyline::install_hook!(use_next_frame_index_double);
-    } else {
-        if one_ahead {
-            skyline::install_hooks!(use_next_frame_index_triple);
-            return
-        }
-        skyline::install_hook!(use_default_frame_index);
-    }
+    let policy = match (SyncEnv::emulator_value(), one_ahead) {
+        (true, true) => FrameIndexPolicy::EmulatorImmediate,
+        (true, false) => FrameIndexPolicy::Double,
+        (false, true) => FrameIndexPolicy::Triple,
+        (false, false) => FrameIndexPolicy::Default,
+    };
+    install_frame_index_hook(policy);
 }
 
-pub fn install(config: SsbuSyncConfig) {
-    let emulator = config.emulator_check;
-    
-    if emulator {
-        patch_swap_flush_call();
+/// Writes this module's patches; `install` puts the hooks in once every module's patches went in.
+pub fn patch(config: SsbuSyncConfig, transaction: &mut Transaction) -> Result<(), PatchError> {
+    if config.emulator_check {
+        patch_swap_flush_call(transaction)?;
     }
-    use_current_frame_index();
+    use_current_frame_index(transaction)?;
 
     if config.disable_vsync {
-        patch_render_sync_wait();
+        patch_render_sync_wait(transaction)?;
     }
+    Ok(())
+}
+
+pub fn install(config: SsbuSyncConfig) {
+    let emulator = config.emulator_check;
 
     if emulator {
-        skyline::install_hooks!(
+        patches::install_hooks!(
             flush_swap_buffers_before_present,
             emu_full_swapchain_flush,
-            emu_use_imm_frame_index,
             set_double_window_textures
         );
+        install_frame_index_hook(FrameIndexPolicy::EmulatorImmediate);
     } else {
         // Console path: keep emulator-only hooks disabled.
-        skyline::install_hooks!(full_swapchain_flush);
-        if (config.enable_triple_buffer || config.online_only) {
-            skyline::install_hooks!(use_next_frame_index_triple);
+        patches::install_hooks!(full_swapchain_flush);
+        let initial = if config.enable_triple_buffer || config.online_only {
+            install_frame_index_hook(FrameIndexPolicy::Triple);
+            BufferMode::Triple
         } else {
-            skyline::install_hooks!(
-                use_next_frame_index_double,
-                set_double_window_textures);
-        }
+            patches::install_hooks!(set_double_window_textures);
+            install_frame_index_hook(FrameIndexPolicy::Double);
+            BufferMode::Double
+        };
+        init_buffer_mode(initial);
     }
-
-    // // Seed logical runtime mode.
-    // let initial = if config.enable_triple_buffer {
-    //     BufferMode::Triple
-    // } else {
-    //     BufferMode::Double
-    // };
-    // init_buffer_mode(initial);
-
-}
\ No newline at end of file
+}
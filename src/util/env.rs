@@ -44,6 +44,9 @@ flag_accessors! {
     TRIPLE_ENABLED => 4;
     ONLINE_ONLY => 5;
     ONLINE_FIX_ENABLED => 6;
+    AUTO_BUFFER_POLICY => 7;
+    FRAME_HISTORY => 8;
+    INSTALLED => 9;
 }
     
     
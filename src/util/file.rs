@@ -4,8 +4,9 @@ pub mod paths {
 
     pub fn ensure_paths_exist() -> io::Result<()> {
         if let Err(err) = std::fs::create_dir_all(ssbusync()) {
-            println!(
-                "[ssbusync][config] failed to create config directory {}: {}",
+            tracing::error!(
+                target: "config",
+                "failed to create config directory {}: {}",
                 ssbusync(),
                 err
             );
@@ -22,19 +23,59 @@ pub mod paths {
         ssbusync().join("ssbusync.toml")
     }
 
+    /// The previous `ssbusync.toml`, copied there before every rewrite.
+    pub fn ssbusync_config_backup() -> Utf8PathBuf {
+        ssbusync().join("ssbusync.toml.bak")
+    }
+
+    /// What the last install applied; see `report`.
+    pub fn last_install_report() -> Utf8PathBuf {
+        ssbusync().join("last_install.toml")
+    }
+
     pub fn ssbusync_disablers() -> Utf8PathBuf {
         ssbusync().join("disablers.toml")
     }
+
+    pub fn captures() -> Utf8PathBuf {
+        ssbusync().join("captures")
+    }
+
+    pub fn traces() -> Utf8PathBuf {
+        ssbusync().join("traces")
+    }
+
+    /// One past the highest `<prefix>NNNN` session number already in `dir`, so every boot gets new files.
+    pub fn next_session(dir: &Utf8PathBuf, prefix: &str) -> u32 {
+        let Ok(entries) = std::fs::read_dir(dir.as_std_path()) else {
+            return 0;
+        };
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                name.to_str()?.strip_prefix(prefix)?.get(..4)?.parse::<u32>().ok()
+            })
+            .max()
+            .map_or(0, |newest| newest + 1)
+    }
 }
 
 pub mod config {
     use super::paths;
+    use crate::util::migrate::{self, Migration};
     use crate::SsbuSyncConfig;
+    use semver::Version;
     use serde::{Deserialize, Serialize};
     use std::collections::BTreeMap;
     use std::io;
+    use std::sync::Mutex;
+    use toml::{Table, Value};
 
-    const DEFAULT_PROFILE_VERSION: f32 = 1.0;
+    /// The version the Default profile is written and upgraded at. Bump it when `SsbuSyncConfig` gains fields, so
+    /// existing Default profiles get them.
+    pub const DEFAULT_PROFILE_VERSION: Version = Version::new(1, 0, 0);
+    const PROFILE_VERSION_KEY: &str = "profile_version";
 
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub enum DefaultProfileState {
@@ -42,34 +83,95 @@ pub mod config {
         Loaded,
     }
 
+    /// Profiles are kept as raw TOML tables so fields this build doesn't know (or that a migration is about to rename)
+    /// survive a rewrite.
     #[derive(Debug, Serialize, Deserialize, Default)]
     #[serde(default)]
     struct ConfigFile {
         #[serde(rename = "SsbuSync")]
-        ssbusync: BTreeMap<String, ProfileEntry>,
+        ssbusync: BTreeMap<String, Table>,
+        #[serde(rename = "Frametracer", skip_serializing_if = "Option::is_none")]
+        frametracer: Option<FrametracerEntry>,
     }
 
-    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    #[derive(Debug, Clone, Serialize, Deserialize, Default)]
     #[serde(default)]
-    struct ProfileEntry {
-        profile_version: f32,
-        #[serde(flatten)]
-        config: SsbuSyncConfig,
+    struct FrametracerEntry {
+        dir: Option<String>,
     }
 
-    impl Default for ProfileEntry {
-        fn default() -> Self {
-            Self {
-                profile_version: DEFAULT_PROFILE_VERSION,
-                config: SsbuSyncConfig::default(),
-            }
+    /// A profile handed out by `get_or_make_profile`, kept so the install report can say which settings came from it.
+    #[derive(Debug, Clone)]
+    pub struct LoadedProfile {
+        pub name: String,
+        pub config: SsbuSyncConfig,
+    }
+
+    static LOADED: Mutex<Vec<LoadedProfile>> = Mutex::new(Vec::new());
+
+    fn remember_loaded(name: &str, config: SsbuSyncConfig) {
+        let mut loaded = LOADED.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        loaded.retain(|profile| profile.name != name);
+        loaded.push(LoadedProfile {
+            name: name.to_string(),
+            config,
+        });
+    }
+
+    /// Profiles loaded this boot, oldest first. Loading one again moves it to the end.
+    pub fn loaded_profiles() -> Vec<LoadedProfile> {
+        LOADED.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    /// `config`'s settings by field name, as they'd be written to a profile.
+    pub fn config_fields(config: &SsbuSyncConfig) -> Table {
+        match Value::try_from(config) {
+            Ok(Value::Table(table)) => table,
+            _ => Table::new(),
         }
     }
 
+    /// The loaded profile `config` is a copy of, ignoring `emulator_check`, which install sets itself; the latest one
+    /// wins if several are identical. `None` if the caller built its config by hand or changed it after loading.
+    pub fn matching_loaded_profile(config: &SsbuSyncConfig) -> Option<LoadedProfile> {
+        let same = |profile: &LoadedProfile| {
+            let mut loaded = profile.config;
+            loaded.emulator_check = config.emulator_check;
+            config_fields(&loaded) == config_fields(config)
+        };
+        loaded_profiles().into_iter().rev().find(same)
+    }
+
     fn io_err(err: impl std::fmt::Display) -> io::Error {
         io::Error::new(io::ErrorKind::InvalidData, err.to_string())
     }
 
+    /// `config` as a profile table stamped with `version`.
+    fn profile_table(config: &SsbuSyncConfig, version: &Version) -> io::Result<Table> {
+        let mut table = match Value::try_from(config).map_err(io_err)? {
+            Value::Table(table) => table,
+            _ => Table::new(),
+        };
+        table.insert(PROFILE_VERSION_KEY.to_string(), Value::String(version.to_string()));
+        Ok(table)
+    }
+
+    /// Reads a profile table, falling back to `defaults` if a field has the wrong type.
+    fn profile_config(name: &str, table: &Table, defaults: &SsbuSyncConfig) -> SsbuSyncConfig {
+        match Value::Table(table.clone()).try_into::<SsbuSyncConfig>() {
+            Ok(config) => config,
+            Err(err) => {
+                tracing::error!(
+                    target: "config",
+                    "profile {} is invalid ({}); using defaults",
+                    name,
+                    err
+                );
+                *defaults
+            }
+        }
+    }
+
     fn load_file(path: &camino::Utf8PathBuf) -> io::Result<ConfigFile> {
         if !path.exists() {
             return Ok(ConfigFile::default());
@@ -77,8 +179,9 @@ pub mod config {
         let data = match std::fs::read_to_string(path.as_std_path()) {
             Ok(data) => data,
             Err(err) => {
-                println!(
-                    "[ssbusync][config] failed reading {}: {}",
+                tracing::error!(
+                    target: "config",
+                    "failed reading {}: {}",
                     path,
                     err
                 );
@@ -89,8 +192,9 @@ pub mod config {
         match toml::from_str(&data) {
             Ok(parsed) => Ok(parsed),
             Err(err) => {
-                println!(
-                    "[ssbusync][config] failed parsing TOML {}: {}",
+                tracing::error!(
+                    target: "config",
+                    "failed parsing TOML {}: {}",
                     path,
                     err
                 );
@@ -99,12 +203,14 @@ pub mod config {
         }
     }
 
+    /// Copies the current file to `ssbusync.toml.bak` first, so a rewrite never loses what the user had.
     fn write_file(path: &camino::Utf8PathBuf, data: &ConfigFile) -> io::Result<()> {
         let toml = match toml::to_string_pretty(data) {
             Ok(toml) => toml,
             Err(err) => {
-                println!(
-                    "[ssbusync][config] failed serializing TOML {}: {}",
+                tracing::error!(
+                    target: "config",
+                    "failed serializing TOML {}: {}",
                     path,
                     err
                 );
@@ -112,9 +218,24 @@ pub mod config {
             }
         };
 
+        if path.exists() {
+            let backup = paths::ssbusync_config_backup();
+            if let Err(err) = std::fs::copy(path.as_std_path(), backup.as_std_path()) {
+                tracing::error!(
+                    target: "config",
+                    "failed backing up {} to {}: {}",
+                    path,
+                    backup,
+                    err
+                );
+                return Err(err);
+            }
+        }
+
         if let Err(err) = std::fs::write(path.as_std_path(), toml) {
-            println!(
-                "[ssbusync][config] failed writing {}: {}",
+            tracing::error!(
+                target: "config",
+                "failed writing {}: {}",
                 path,
                 err
             );
@@ -123,12 +244,13 @@ pub mod config {
         Ok(())
     }
 
-    fn ensure_default_profile(data: &mut ConfigFile) -> bool {
+    fn ensure_default_profile(data: &mut ConfigFile) -> io::Result<bool> {
         if data.ssbusync.contains_key("Default") {
-            false
+            Ok(false)
         } else {
-            data.ssbusync.insert("Default".to_string(), ProfileEntry::default());
-            true
+            let table = profile_table(&SsbuSyncConfig::default(), &DEFAULT_PROFILE_VERSION)?;
+            data.ssbusync.insert("Default".to_string(), table);
+            Ok(true)
         }
     }
 
@@ -137,16 +259,17 @@ pub mod config {
         let path = paths::ssbusync_config();
         let mut data = load_file(&path)?;
 
-        let changed = ensure_default_profile(&mut data);
+        let changed = ensure_default_profile(&mut data)?;
         if changed {
             write_file(&path, &data)?;
         }
 
+        let defaults = SsbuSyncConfig::default();
         let config = data
             .ssbusync
             .get("Default")
-            .map(|entry| entry.config)
-            .unwrap_or_else(SsbuSyncConfig::default);
+            .map(|table| profile_config("Default", table, &defaults))
+            .unwrap_or(defaults);
 
         let state = if changed {
             DefaultProfileState::Created
@@ -157,47 +280,110 @@ pub mod config {
         Ok((config, state))
     }
 
+    /// Reads `name` as it is on disk right now, without creating, migrating or rewriting anything.
+    pub fn read_profile(name: &str, defaults: &SsbuSyncConfig) -> io::Result<Option<SsbuSyncConfig>> {
+        let data = load_file(&paths::ssbusync_config())?;
+        Ok(data
+            .ssbusync
+            .get(name)
+            .map(|table| profile_config(name, table, defaults)))
+    }
+
+    /// The `overrides` table of profile `name`, if it has one; see `overrides`.
+    pub fn profile_overrides(name: &str) -> io::Result<Option<Table>> {
+        let data = load_file(&paths::ssbusync_config())?;
+        Ok(data
+            .ssbusync
+            .get(name)
+            .and_then(|table| table.get("overrides"))
+            .and_then(|overrides| overrides.as_table())
+            .cloned())
+    }
+
+    /// `[Frametracer] dir` from ssbusync.toml, or `sd:/ultimate/ssbusync/traces`.
+    pub fn frametracer_dir() -> camino::Utf8PathBuf {
+        load_file(&paths::ssbusync_config())
+            .ok()
+            .and_then(|data| data.frametracer)
+            .and_then(|entry| entry.dir)
+            .filter(|dir| !dir.trim().is_empty())
+            .map(camino::Utf8PathBuf::from)
+            .unwrap_or_else(paths::traces)
+    }
+
+    /** Loads `name`, creating or upgrading it first
+     *
+     * A missing profile is written from `defaults`. One stored at an older version runs through `migrations` and gets
+     * any fields it lacks from `defaults`; everything the user set is kept. Versions still stored as floats are
+     * rewritten as semver strings.
+     */
     pub fn get_or_make_profile(
         name: &str,
         defaults: &SsbuSyncConfig,
-        version: f32,
+        version: &Version,
+        migrations: &[Migration],
     ) -> io::Result<SsbuSyncConfig> {
         paths::ensure_paths_exist()?;
         let path = paths::ssbusync_config();
         let mut data = load_file(&path)?;
 
-        let mut changed = ensure_default_profile(&mut data);
-        let entry = data.ssbusync.entry(name.to_string());
-        let mut should_write = false;
-
-        match entry {
-            std::collections::btree_map::Entry::Occupied(mut occ) => {
-                if occ.get().profile_version < version {
+        let mut should_write = ensure_default_profile(&mut data)?;
+        let default_table = profile_table(defaults, version)?;
+
+        match data.ssbusync.get_mut(name) {
+            Some(table) => {
+                let stored_value = table.get(PROFILE_VERSION_KEY);
+                let stored = stored_value
+                    .and_then(migrate::stored_version)
+                    .unwrap_or(Version::new(0, 0, 0));
+                let legacy = !matches!(stored_value, Some(Value::String(_)));
+                if let Some(stored) = stored_value.and_then(|value| migrate::below_legacy(value, version)) {
+                    // Left as a float so this is reported until the plugin asks for a version past it.
+                    tracing::warn!(
+                        target: "config",
+                        "profile {} stores an old float version that reads as {}, newer than the requested {}; \
+                         nothing was upgraded. Ask for {}.{}.0 or later.",
+                        name,
+                        stored,
+                        version,
+                        stored.major,
+                        stored.minor + 1
+                    );
+                } else if &stored < version {
+                    let ran = migrate::migrate(name, table, &stored, version, migrations);
+                    migrate::fill_defaults(table, &default_table);
+                    table.insert(PROFILE_VERSION_KEY.to_string(), Value::String(version.to_string()));
+                    tracing::info!(
+                        target: "config",
+                        "upgraded profile {} from {} to {} ({} migrations)",
+                        name,
+                        stored,
+                        version,
+                        ran
+                    );
+                    should_write = true;
+                } else if legacy {
+                    table.insert(PROFILE_VERSION_KEY.to_string(), Value::String(stored.to_string()));
                     should_write = true;
-                    occ.insert(ProfileEntry {
-                        profile_version: version,
-                        config: *defaults,
-                    });
                 }
             }
-            std::collections::btree_map::Entry::Vacant(vac) => {
+            None => {
+                data.ssbusync.insert(name.to_string(), default_table);
                 should_write = true;
-                vac.insert(ProfileEntry {
-                    profile_version: version,
-                    config: *defaults,
-                });
             }
         }
 
-        if should_write || changed {
+        if should_write {
             write_file(&path, &data)?;
         }
 
-        Ok(data
+        let config = data
             .ssbusync
             .get(name)
-            .map(|entry| entry.config)
-            .unwrap_or_else(|| *defaults))
+            .map(|table| profile_config(name, table, defaults))
+            .unwrap_or(*defaults);
+        remember_loaded(name, config);
+        Ok(config)
     }
 }
 
@@ -245,7 +431,7 @@ pub mod disablers {
         let data = match std::fs::read_to_string(path.as_std_path()) {
             Ok(data) => data,
             Err(err) => {
-                println!("[ssbusync][disablers] failed reading {}: {}", path, err);
+                tracing::error!(target: "disablers", "failed reading {}: {}", path, err);
                 return Err(err);
             }
         };
@@ -253,8 +439,9 @@ pub mod disablers {
         match toml::from_str(&data) {
             Ok(parsed) => Ok(parsed),
             Err(err) => {
-                println!(
-                    "[ssbusync][disablers] failed parsing TOML {}: {}",
+                tracing::error!(
+                    target: "disablers",
+                    "failed parsing TOML {}: {}",
                     path, err
                 );
                 Err(io_err(err))
@@ -266,8 +453,9 @@ pub mod disablers {
         let toml = match toml::to_string_pretty(data) {
             Ok(toml) => toml,
             Err(err) => {
-                println!(
-                    "[ssbusync][disablers] failed serializing TOML {}: {}",
+                tracing::error!(
+                    target: "disablers",
+                    "failed serializing TOML {}: {}",
                     path, err
                 );
                 return Err(io_err(err));
@@ -275,7 +463,7 @@ pub mod disablers {
         };
 
         if let Err(err) = std::fs::write(path.as_std_path(), toml) {
-            println!("[ssbusync][disablers] failed writing {}: {}", path, err);
+            tracing::error!(target: "disablers", "failed writing {}: {}", path, err);
             return Err(err);
         }
         Ok(())
@@ -333,8 +521,9 @@ pub mod disablers {
         let data = load_file(&path)?;
         if created {
             write_file(&path, &data)?;
-            println!(
-                "[ssbusync][disablers] created {} with default disablers: hdr, hdr-dev",
+            tracing::info!(
+                target: "disablers",
+                "created {} with default disablers: hdr, hdr-dev",
                 path
             );
         }
@@ -349,8 +538,9 @@ pub mod disablers {
 
         if crate::emulator_status() || !arcropolis_api_available() {
             if let Some(path) = match_disabler_folder(&mods) {
-                println!(
-                    "[ssbusync][disablers] folder check matched disabler '{}'",
+                tracing::info!(
+                    target: "disablers",
+                    "folder check matched disabler '{}'",
                     path
                 );
                 return Ok(Some(path));
@@ -372,16 +562,18 @@ pub mod disablers {
     pub fn check_disabler_mods() -> bool {
         match active_disabler_mod() {
             Ok(Some(mod_path)) => {
-                println!(
-                    "[ssbusync] disablers.toml matched active mod '{}'; disabling ssbusync install",
+                tracing::info!(
+                    target: "disablers",
+                    "disablers.toml matched active mod '{}'; disabling ssbusync install",
                     mod_path
                 );
                 true
             }
             Ok(None) => false,
             Err(err) => {
-                println!(
-                    "[ssbusync] failed reading disablers.toml ({}); continuing install checks",
+                tracing::warn!(
+                    target: "disablers",
+                    "failed reading disablers.toml ({}); continuing install checks",
                     err
                 );
                 false
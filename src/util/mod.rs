@@ -1,3 +1,5 @@
-//pub mod offsets;
+pub mod offsets;
+pub mod sigscan;
 pub mod file;
 pub mod env;
+pub use ncore::migrate;
@@ -1,122 +1,518 @@
+use std::sync::OnceLock;
+
 use symbaker::symbaker_module;
 
-// #[symbaker_module]
-// #[cfg(feature = "nro-entry")]
-// mod offsets_impl {
-//     // Offsets copied from https://github.com/HDR-Development/HewDraw-Remix/blob/pre-release/utils/src/offsets.rs
-//     // 
-//     #[export_name = "offsets_exec_command"]
-//     pub const fn exec_command() -> usize {
-//         0x6bac10
-//     }
-
-//     #[export_name = "offsets_get_command_flag_cat"]
-//     pub const fn get_command_flag_cat() -> usize {
-//         0x6ba9a0
-//     }
-
-//     #[export_name = "offsets_demon_on_link_capture_event"]
-//     pub const fn demon_on_link_capture_event() -> usize {
-//         0x933800
-//     }
-
-//     #[export_name = "offsets_dolly_super_special_check"]
-//     pub const fn dolly_super_special_check() -> usize {
-//         0x970ff0
-//     }
-
-//     #[export_name = "offsets_dolly_super_special_check_param"]
-//     pub const fn dolly_super_special_check_param() -> usize {
-//         0x971250
-//     }
-
-//     #[export_name = "offsets_force_linear_histun"]
-//     pub const fn force_linear_histun() -> usize {
-//         0x62ba74
-//     }
-
-//     #[export_name = "offsets_get_param_int_impl"]
-//     pub const fn get_param_int_impl() -> usize {
-//         0x4e53a0
-//     }
-
-//     #[export_name = "offsets_get_param_float_impl"]
-//     pub const fn get_param_float_impl() -> usize {
-//         0x4e53e0
-//     }
-
-//     #[export_name = "offsets_set_fighter_vtable"]
-//     pub const fn set_fighter_vtable() -> usize {
-//         0x14f4994
-//     }
-
-//     #[export_name = "offsets_set_weapon_vtable"]
-//     pub const fn set_weapon_vtable() -> usize {
-//         0x14f4eac
-//     }
-
-//     #[export_name = "offsets_set_item_vtable"]
-//     pub const fn set_item_vtable() -> usize {
-//         0x14f5144
-//     }
-
-//     #[export_name = "offsets_get_battle_object_from_id"]
-//     pub const fn get_battle_object_from_id() -> usize {
-//         0x3ac560
-//     }
-
-//     #[export_name = "offsets_fighter_handle_damage"]
-//     pub const fn fighter_handle_damage() -> usize {
-//         0x6310c0
-//     }
-
-//     #[export_name = "offsets_p_p_game_state"]
-//     pub const fn p_p_game_state() -> usize {
-//         0x52c2760
-//     }
-
-//     #[export_name = "offsets_map_controls"]
-//     pub const fn map_controls() -> usize {
-//         0x1750f70
-//     }
-
-//     #[export_name = "offsets_once_per_game_frame"]
-//     pub const fn once_per_game_frame() -> usize {
-//         0x135b810
-//     }
-
-//     #[export_name = "offsets_on_rule_select"]
-//     pub const fn on_rule_select() -> usize {
-//         0x1792c60
-//     }
-
-//     #[export_name = "offsets_global_frame_counter"]
-//     pub const fn global_frame_counter() -> usize {
-//         0x52e7b44
-//     }
-
-//     #[export_name = "offsets_get_match_mode"]
-//     pub const fn get_match_mode() -> usize {
-//         0x1743870
-//     }
-  
-//     #[export_name = "offsets_kill_zoom_regular"]
-//     pub const fn kill_zoom_regular() -> usize {
-//         0x633de0
-//     }
-
-//     #[export_name = "offsets_kill_zoom_throw"]
-//     pub const fn kill_zoom_throw() -> usize {
-//         0x6373a4
-//     }
-
-//     #[export_name = "offsets_analog_trigger_l"]
-//     pub const fn analog_trigger_l() -> usize {
-//         0x3666ee0
-//     }
-
-//     #[export_name = "offsets_analog_trigger_r"]
-//     pub const fn analog_trigger_r() -> usize {
-//         0x3666ef4
-//     }
-// }
\ No newline at end of file
+use super::sigscan;
+use crate::patches::{Original, PatchData};
+
+// #[symbaker_module]
+// #[cfg(feature = "nro-entry")]
+// mod offsets_impl {
+//     // Offsets copied from https://github.com/HDR-Development/HewDraw-Remix/blob/pre-release/utils/src/offsets.rs
+//     // 
+//     #[export_name = "offsets_exec_command"]
+//     pub const fn exec_command() -> usize {
+//         0x6bac10
+//     }
+
+//     #[export_name = "offsets_get_command_flag_cat"]
+//     pub const fn get_command_flag_cat() -> usize {
+//         0x6ba9a0
+//     }
+
+//     #[export_name = "offsets_demon_on_link_capture_event"]
+//     pub const fn demon_on_link_capture_event() -> usize {
+//         0x933800
+//     }
+
+//     #[export_name = "offsets_dolly_super_special_check"]
+//     pub const fn dolly_super_special_check() -> usize {
+//         0x970ff0
+//     }
+
+//     #[export_name = "offsets_dolly_super_special_check_param"]
+//     pub const fn dolly_super_special_check_param() -> usize {
+//         0x971250
+//     }
+
+//     #[export_name = "offsets_force_linear_histun"]
+//     pub const fn force_linear_histun() -> usize {
+//         0x62ba74
+//     }
+
+//     #[export_name = "offsets_get_param_int_impl"]
+//     pub const fn get_param_int_impl() -> usize {
+//         0x4e53a0
+//     }
+
+//     #[export_name = "offsets_get_param_float_impl"]
+//     pub const fn get_param_float_impl() -> usize {
+//         0x4e53e0
+//     }
+
+//     #[export_name = "offsets_set_fighter_vtable"]
+//     pub const fn set_fighter_vtable() -> usize {
+//         0x14f4994
+//     }
+
+//     #[export_name = "offsets_set_weapon_vtable"]
+//     pub const fn set_weapon_vtable() -> usize {
+//         0x14f4eac
+//     }
+
+//     #[export_name = "offsets_set_item_vtable"]
+//     pub const fn set_item_vtable() -> usize {
+//         0x14f5144
+//     }
+
+//     #[export_name = "offsets_get_battle_object_from_id"]
+//     pub const fn get_battle_object_from_id() -> usize {
+//         0x3ac560
+//     }
+
+//     #[export_name = "offsets_fighter_handle_damage"]
+//     pub const fn fighter_handle_damage() -> usize {
+//         0x6310c0
+//     }
+
+//     #[export_name = "offsets_p_p_game_state"]
+//     pub const fn p_p_game_state() -> usize {
+//         0x52c2760
+//     }
+
+//     #[export_name = "offsets_map_controls"]
+//     pub const fn map_controls() -> usize {
+//         0x1750f70
+//     }
+
+//     #[export_name = "offsets_once_per_game_frame"]
+//     pub const fn once_per_game_frame() -> usize {
+//         0x135b810
+//     }
+
+//     #[export_name = "offsets_on_rule_select"]
+//     pub const fn on_rule_select() -> usize {
+//         0x1792c60
+//     }
+
+//     #[export_name = "offsets_global_frame_counter"]
+//     pub const fn global_frame_counter() -> usize {
+//         0x52e7b44
+//     }
+
+//     #[export_name = "offsets_get_match_mode"]
+//     pub const fn get_match_mode() -> usize {
+//         0x1743870
+//     }
+  
+//     #[export_name = "offsets_kill_zoom_regular"]
+//     pub const fn kill_zoom_regular() -> usize {
+//         0x633de0
+//     }
+
+//     #[export_name = "offsets_kill_zoom_throw"]
+//     pub const fn kill_zoom_throw() -> usize {
+//         0x6373a4
+//     }
+
+//     #[export_name = "offsets_analog_trigger_l"]
+//     pub const fn analog_trigger_l() -> usize {
+//         0x3666ee0
+//     }
+
+//     #[export_name = "offsets_analog_trigger_r"]
+//     pub const fn analog_trigger_r() -> usize {
+//         0x3666ef4
+//     }
+// }
+
+/// Text/data offsets for every site ssbusync patches, hooks or calls into.
+///
+/// Offsets are relative to the start of the main module's `.text` region, the same base
+/// `skyline::patching::Patch::in_text` and `#[skyline::hook(offset = ...)]` use.
+#[derive(Debug, Clone)]
+pub struct Offsets {
+    // ── swapchain ──
+    pub present_flush_call: usize,
+    pub swapchain_singleton: usize,
+    pub await_submit_dispatches: usize,
+    pub swap_flush_call: usize,
+    pub frame_index_add: usize,
+    pub render_sync_wait: usize,
+    pub set_double_window_textures: usize,
+    pub nvn_window_set_num_active_textures: usize,
+    pub nvn_window_get_num_active_textures: usize,
+    pub nvn_window_get_num_textures: usize,
+
+    // ── pacer ──
+    pub frame_pacer_check: usize,
+    pub pacer_bias: usize,
+
+    // ── off_by_one ──
+    pub update_models: usize,
+    pub initialize_task_worker: usize,
+    pub wait_task_worker: usize,
+    pub post_scene_update: usize,
+    pub battle_object_manager: usize,
+    pub task_worker_queue: usize,
+    pub submit_commands: usize,
+    pub eff_render_commands: usize,
+    pub ui_render_commands: usize,
+    pub fighter_render_commands: usize,
+    pub tls_slot_array: usize,
+    pub render_dispatch_signal: usize,
+    pub update_object_models_vtable: usize,
+    pub fighter_render_command_recording: usize,
+
+    // ── sequencing ──
+    pub scene_manager_calls: [usize; 3],
+    pub run_scene_manager: usize,
+    pub run_scene_manager_call: usize,
+    pub scene_manager: usize,
+    pub frame_end: usize,
+
+    // ── vsync_history ──
+    pub unk_present_call: usize,
+    pub present_texture_call: usize,
+    pub acquire_texture_call: usize,
+    pub queue_wait_sync_call: usize,
+    pub sync_wait_call: usize,
+    pub vsync_update: usize,
+    pub vi_layer_init: usize,
+    pub nvn_window_acquire_texture: usize,
+    pub nvn_queue_present_texture: usize,
+    pub nvn_sync_wait: usize,
+    pub nvn_queue_wait_sync: usize,
+    pub init_renderpasses: usize,
+    pub init_renderpasses_call: usize,
+    pub init_ui_task_worker_call: usize,
+    pub init_vfx_task_worker_call: usize,
+    pub init_battle_task_worker_call: usize,
+    pub unk_task_worker1: usize,
+    pub cmdbuf_reset_begin: usize,
+    pub cmdbuf_reset_end: usize,
+    pub mutex_lock_begin: usize,
+    pub mutex_lock_end: usize,
+    pub looping_begin: usize,
+    pub looping_end: usize,
+    pub ui_update: usize,
+    pub ui_update_call: usize,
+
+    /// What each patch site holds in this build before anything patches it, by patch name.
+    pub originals: &'static [(&'static str, Original)],
+}
+
+/// A game build ssbusync knows how to patch.
+pub struct GameBuild {
+    pub display_version: &'static str,
+    /// The main module's GNU build ID as hex, see [`running_build_id`]. `None` until it has been recorded for this
+    /// build; the build then matches on its display version alone.
+    pub build_id: Option<&'static str>,
+    pub offsets: Offsets,
+}
+
+impl GameBuild {
+    /// Whether this is the running game: the same display version and, when recorded, the same build ID.
+    pub fn matches(&self, display_version: &str, build_id: Option<&str>) -> bool {
+        self.display_version == display_version
+            && self
+                .build_id
+                .is_none_or(|expected| build_id.is_some_and(|running| running.eq_ignore_ascii_case(expected)))
+    }
+}
+
+pub static KNOWN_BUILDS: &[GameBuild] = &[GameBuild {
+    display_version: "13.0.3",
+    build_id: None,
+    offsets: Offsets {
+        present_flush_call: 0x3747b78,
+        swapchain_singleton: 0x5334e90,
+        await_submit_dispatches: 0x384f460,
+        swap_flush_call: 0x37495c8,
+        frame_index_add: 0x386ab4c,
+        render_sync_wait: 0x386fcec,
+        set_double_window_textures: 0x38601f8,
+        nvn_window_set_num_active_textures: 0x593fb80,
+        nvn_window_get_num_active_textures: 0x593fb88,
+        nvn_window_get_num_textures: 0x593fb90,
+
+        frame_pacer_check: 0x374c640,
+        pacer_bias: 0x22deb84,
+
+        update_models: 0x3ab590,
+        initialize_task_worker: 0x3549170,
+        wait_task_worker: 0x354c720,
+        post_scene_update: 0x374c7b4,
+        battle_object_manager: 0x5332120,
+        task_worker_queue: 0x5332558,
+        submit_commands: 0x3548240,
+        eff_render_commands: 0x374d270,
+        ui_render_commands: 0x374d550,
+        fighter_render_commands: 0x374f050,
+        tls_slot_array: 0x5332f58,
+        render_dispatch_signal: 0x374c054,
+        update_object_models_vtable: 0x4f623d0,
+        fighter_render_command_recording: 0x374b554,
+
+        scene_manager_calls: [0x374c624, 0x374c2dc, 0x374c410],
+        run_scene_manager: 0x3724a80,
+        run_scene_manager_call: 0x374b290,
+        scene_manager: 0x593a4c0,
+        frame_end: 0x374c050,
+
+        unk_present_call: 0x3860674,
+        present_texture_call: 0x386fc80,
+        acquire_texture_call: 0x386fca0,
+        queue_wait_sync_call: 0x386fcdc,
+        sync_wait_call: 0x374c118,
+        vsync_update: 0x3810a40,
+        vi_layer_init: 0x3743ca0,
+        nvn_window_acquire_texture: 0x593fb50,
+        nvn_queue_present_texture: 0x593fac8,
+        nvn_sync_wait: 0x5940878,
+        nvn_queue_wait_sync: 0x5940880,
+        init_renderpasses: 0x3864700,
+        init_renderpasses_call: 0x374b11c,
+        init_ui_task_worker_call: 0x374b4f4,
+        init_vfx_task_worker_call: 0x374b524,
+        init_battle_task_worker_call: 0x374b554,
+        unk_task_worker1: 0x374b2b0,
+        cmdbuf_reset_begin: 0x374bd9c,
+        cmdbuf_reset_end: 0x374bfe8,
+        mutex_lock_begin: 0x374b308,
+        mutex_lock_end: 0x374b4b4,
+        looping_begin: 0x374b130,
+        looping_end: 0x374b160,
+        ui_update: 0x3619080,
+        ui_update_call: 0x374b124,
+
+        originals: &[
+            ("unk_present_call", Original::AnyCall),
+            ("acquire_texture_call", Original::AnyCall),
+            ("present_texture_call", Original::AnyCall),
+            ("queue_wait_sync_call", Original::AnyCall),
+            ("vsync_update", Original::Unpatched),
+            ("scene_manager_call_0", Original::AnyCall),
+            ("scene_manager_call_1", Original::AnyCall),
+            ("scene_manager_call_2", Original::AnyCall),
+            ("swap_flush_call", Original::Unpatched),
+            ("swap_flush_call_next", Original::Unpatched),
+            // add w9, w9, #0x2
+            ("frame_index_add", Original::Exact(PatchData::U32(0x11000929))),
+            // blr x8
+            ("render_sync_wait", Original::Exact(PatchData::U32(0xD63F0100))),
+            ("frame_pacer_check", Original::Unpatched),
+            ("pacer_bias", Original::Unpatched),
+            ("render_dispatch_signal", Original::Unpatched),
+            ("update_object_models_vtable", Original::TextPointer),
+            // The same call as init_battle_task_worker_call.
+            ("fighter_render_command_recording", Original::AnyCall),
+        ],
+    },
+}];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OffsetsError {
+    /// The running game's version and build ID match no entry in [`KNOWN_BUILDS`].
+    UnknownBuild {
+        version: String,
+        build_id: Option<String>,
+    },
+    /// The running build isn't in [`KNOWN_BUILDS`] and a signature needed to relocate it didn't resolve.
+    Unrelocated {
+        version: String,
+        error: sigscan::ScanError,
+    },
+}
+
+impl std::fmt::Display for OffsetsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownBuild { version, build_id } => write!(
+                f,
+                "no offsets for game version '{}' (build {})",
+                version,
+                build_id.as_deref().unwrap_or("unknown")
+            ),
+            Self::Unrelocated { version, error } => {
+                write!(f, "game version '{}' is not in the offset table and {}", version, error)
+            }
+        }
+    }
+}
+
+#[repr(C)]
+struct DisplayVersion {
+    name: [u8; 16],
+}
+
+unsafe extern "C" {
+    #[link_name = "_ZN2nn2oe17GetDisplayVersionEPNS0_14DisplayVersionE"]
+    fn get_display_version(version: *mut DisplayVersion);
+}
+
+static ACTIVE: OnceLock<Offsets> = OnceLock::new();
+
+/// Returns the display version of the running game (e.g. `13.0.3`).
+pub fn running_display_version() -> String {
+    let mut version = DisplayVersion { name: [0u8; 16] };
+    unsafe { get_display_version(&mut version) };
+    let len = version.name.iter().position(|b| *b == 0).unwrap_or(version.name.len());
+    String::from_utf8_lossy(&version.name[..len]).into_owned()
+}
+
+/// `NT_GNU_BUILD_ID` note header: a 4 byte name, a 20 byte ID, type 3, `GNU\0`.
+const BUILD_ID_NOTE: &str = "04 00 00 00 14 00 00 00 03 00 00 00 47 4E 55 00";
+const BUILD_ID_LEN: usize = 20;
+
+/// Returns the main module's build ID as lowercase hex, read from the build ID note in its `.rodata`.
+pub fn running_build_id() -> Option<String> {
+    let rodata = unsafe {
+        let start = skyline::hooks::getRegionAddress(skyline::hooks::Region::Rodata) as *const u8;
+        let end = skyline::hooks::getRegionAddress(skyline::hooks::Region::Data) as *const u8;
+        std::slice::from_raw_parts(start, end as usize - start as usize)
+    };
+    build_id_in(rodata)
+}
+
+/// The ID of the first build ID note in `rodata`.
+fn build_id_in(rodata: &[u8]) -> Option<String> {
+    let note = sigscan::Pattern::parse(BUILD_ID_NOTE).ok()?;
+    let at = note.find_all(rodata, 4).into_iter().next()? + note.len();
+    let id = rodata.get(at..at + BUILD_ID_LEN)?;
+    Some(id.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+pub fn find_build(display_version: &str, build_id: Option<&str>) -> Option<&'static GameBuild> {
+    KNOWN_BUILDS
+        .iter()
+        .find(|build| build.matches(display_version, build_id))
+}
+
+impl Offsets {
+    /// What the site patched as `name` holds in an unmodified game.
+    pub fn original(&self, name: &str) -> Option<Original> {
+        match name {
+            "init_renderpasses_call" => Some(Original::CallTo(self.init_renderpasses)),
+            "ui_update_call" => Some(Original::CallTo(self.ui_update)),
+            _ => self
+                .originals
+                .iter()
+                .find(|(site, _)| *site == name)
+                .map(|(_, original)| *original),
+        }
+    }
+
+    /// Maps a site named by a [`sigscan::Signature`] to the field it relocates.
+    fn site_mut(&mut self, name: &str) -> Option<&mut usize> {
+        match name {
+            "present_texture_call" => Some(&mut self.present_texture_call),
+            "acquire_texture_call" => Some(&mut self.acquire_texture_call),
+            "queue_wait_sync_call" => Some(&mut self.queue_wait_sync_call),
+            "render_sync_wait" => Some(&mut self.render_sync_wait),
+            "frame_index_add" => Some(&mut self.frame_index_add),
+            "scene_manager_call_0" => Some(&mut self.scene_manager_calls[0]),
+            "frame_pacer_check" => Some(&mut self.frame_pacer_check),
+            _ => None,
+        }
+    }
+}
+
+/// Moves the sites of every signature found in `text` to the matched block, returning the signatures that didn't
+/// resolve.
+///
+/// All sites of a block are derived from the one match, so they can't end up split between relocated and table
+/// offsets. Blocks whose signature is missing or ambiguous keep their table offsets.
+pub fn relocate(offsets: &mut Offsets, text: &[u8]) -> Vec<sigscan::ScanError> {
+    let mut missed = Vec::new();
+    let found = sigscan::scan_all(text, sigscan::SIGNATURES);
+    for (signature, found) in sigscan::SIGNATURES.iter().zip(found) {
+        let base = match found {
+            Ok(base) => base,
+            Err(err) => {
+                missed.push(err);
+                continue;
+            }
+        };
+        for &(name, distance) in signature.sites {
+            let Some(site) = offsets.site_mut(name) else {
+                tracing::warn!(target: "offsets", "signature '{}' names unknown site '{}'", signature.name, name);
+                continue;
+            };
+            let relocated = base + distance;
+            if relocated != *site {
+                tracing::info!(
+                    target: "offsets",
+                    "relocated {} from 0x{:x} to 0x{:x}",
+                    name, *site, relocated
+                );
+                *site = relocated;
+            }
+        }
+    }
+    missed
+}
+
+/** Resolves the offset table for the running game. Must succeed before anything is patched.
+ *
+ * A known build starts from its own table and relocates what the signatures find, keeping table offsets for the rest.
+ * Any other build starts from the newest table and needs every signature to resolve; it is refused otherwise. Sites
+ * no signature covers keep the newest table's offsets there, and the patch registry still checks each one against
+ * its original bytes before writing, so a site that moved is refused rather than patched.
+ */
+pub fn load() -> Result<&'static Offsets, OffsetsError> {
+    if let Some(offsets) = ACTIVE.get() {
+        return Ok(offsets);
+    }
+    let version = running_display_version();
+    let build_id = running_build_id();
+    tracing::info!(
+        target: "offsets",
+        "game version {}, build {}",
+        version,
+        build_id.as_deref().unwrap_or("unknown")
+    );
+    let offsets = match find_build(&version, build_id.as_deref()) {
+        Some(build) => {
+            tracing::info!(target: "offsets", "using offsets for game version {}", build.display_version);
+            let mut offsets = build.offsets.clone();
+            for err in relocate(&mut offsets, sigscan::text_region()) {
+                tracing::warn!(target: "offsets", "{}; keeping table offsets", err);
+            }
+            offsets
+        }
+        None => {
+            let Some(newest) = KNOWN_BUILDS.last() else {
+                return Err(OffsetsError::UnknownBuild { version, build_id });
+            };
+            let mut offsets = newest.offsets.clone();
+            if let Some(error) = relocate(&mut offsets, sigscan::text_region()).into_iter().next() {
+                return Err(OffsetsError::Unrelocated { version, error });
+            }
+            tracing::warn!(
+                target: "offsets",
+                "game version {} is not in the offset table; relocated every signature, other sites are from {}",
+                version,
+                newest.display_version
+            );
+            offsets
+        }
+    };
+    Ok(ACTIVE.get_or_init(|| offsets))
+}
+
+/// The active offset table, or `None` if [`load`] hasn't succeeded (it refuses builds it can't relocate).
+///
+/// Anything that can patch after install, when it may have been refused, goes through this.
+#[inline]
+pub fn loaded() -> Option<&'static Offsets> {
+    ACTIVE.get()
+}
+
+/// Returns the active offset table.
+///
+/// Only for install paths, which run after [`load`] succeeded; panics otherwise.
+#[inline]
+pub fn offsets() -> &'static Offsets {
+    loaded().expect("offsets::load() must succeed before patching")
+}
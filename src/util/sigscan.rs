@@ -0,0 +1,11 @@
+/** The game's `.text` for the signature scanner in `ncore::sigscan` */
+pub use ncore::sigscan::*;
+
+/// Returns the game's `.text` region as a byte slice.
+pub fn text_region() -> &'static [u8] {
+    unsafe {
+        let start = skyline::hooks::getRegionAddress(skyline::hooks::Region::Text) as *const u8;
+        let end = skyline::hooks::getRegionAddress(skyline::hooks::Region::Rodata) as *const u8;
+        std::slice::from_raw_parts(start, end as usize - start as usize)
+    }
+}
@@ -0,0 +1,2 @@
+/** Config validation, from `ncore::validation` */
+pub use ncore::validation::*;
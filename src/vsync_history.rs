@@ -1,17 +1,12 @@
-use skyline::{hooks::InlineCtx, patching::Patch};
+use skyline::hooks::InlineCtx;
 use symbaker::symbaker;
+use crate::patches::{self, PatchError, PatchSpec, Transaction};
+use crate::util::offsets::offsets;
 use crate::SsbuSyncConfig;
 
 use crate::profiling::OsTick;
 
-#[repr(u64)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, PartialOrd, Ord)]
-pub enum FrameStatus {
-    #[default]
-    Unknown,
-    Enqueued,
-    Presented,
-}
+pub use ncore::frame_history::{FrameInfo, FrameStatus};
 
 #[repr(C, align(16))]
 pub struct SystemEvent([u8; 256]);
@@ -29,16 +24,6 @@ unsafe impl Sync for Display {}
 unsafe impl Send for Layer {}
 unsafe impl Sync for Layer {}
 
-#[repr(C)]
-#[derive(Debug, Copy, Clone, Default)]
-pub struct FrameInfo {
-    pub status: FrameStatus,
-    pub frame_number: u64,
-    pub enqueue_time: i64,
-    pub present_time: i64,
-    pub vblank_time: i64,
-}
-
 unsafe extern "C" {
     #[link_name = "_ZN2nn2vi16ListFrameHistoryEPNS0_9FrameInfoEiPKNS0_5LayerE"]
     fn list_frame_info(frame_info: *mut FrameInfo, count: i32, layer: Layer) -> i32;
@@ -68,13 +53,13 @@ static mut LAST_ENQUEUED: usize = 0;
 static mut LAST_PRESENTED: usize = 0;
 
 #[symbaker]
-#[skyline::hook(offset = 0x386fca0, inline)]
+#[skyline::hook(offset = offsets().acquire_texture_call, inline)]
 unsafe fn call_acquire_texture_wrapper(ctx: &mut InlineCtx) {
     static mut ACQUIRE_TEXTURE_PTR: Option<extern "C" fn(u64, u64, *mut i32) -> u32> = None;
     static mut QUEUE_WAIT_SYNC_PTR: Option<extern "C" fn(u64, u64)> = None;
     if ACQUIRE_TEXTURE_PTR.is_none() {
         let base = skyline::hooks::getRegionAddress(skyline::hooks::Region::Text);
-        ACQUIRE_TEXTURE_PTR = Some(*base.cast::<u8>().add(0x593fb50).cast::<extern "C" fn(
+        ACQUIRE_TEXTURE_PTR = Some(*base.cast::<u8>().add(offsets().nvn_window_acquire_texture).cast::<extern "C" fn(
             u64,
             u64,
             *mut i32,
@@ -82,7 +67,7 @@ unsafe fn call_acquire_texture_wrapper(ctx: &mut InlineCtx) {
         QUEUE_WAIT_SYNC_PTR = Some(
             *base
                 .cast::<u8>()
-                .add(0x5940880)
+                .add(offsets().nvn_queue_wait_sync)
                 .cast::<extern "C" fn(u64, u64)>(),
         );
     }
@@ -115,7 +100,7 @@ unsafe fn call_acquire_texture_wrapper(ctx: &mut InlineCtx) {
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x374c118, inline)]
+#[skyline::hook(offset = offsets().sync_wait_call, inline)]
 unsafe fn profile_sync_wait(ctx: &mut InlineCtx) {
     static mut SYNC_WAIT: Option<extern "C" fn(u64, u64) -> u64> = None;
 
@@ -123,7 +108,7 @@ unsafe fn profile_sync_wait(ctx: &mut InlineCtx) {
         SYNC_WAIT = Some(
             *skyline::hooks::getRegionAddress(skyline::hooks::Region::Text)
                 .cast::<u8>()
-                .add(0x5940878)
+                .add(offsets().nvn_sync_wait)
                 .cast::<extern "C" fn(u64, u64) -> u64>(),
         );
     }
@@ -151,30 +136,60 @@ unsafe fn profile_sync_wait(ctx: &mut InlineCtx) {
 
     // crate::profiling::end_span(OsTick::new(get_system_tick()));
 
-    // let num_frames =
-    //     unsafe { list_frame_info(FRAME_INFOS.as_mut_ptr(), FRAME_INFOS.len() as i32, LAYER) };
-
-    // for frame_info in &FRAME_INFOS[..num_frames as usize] {
-    //     match frame_info.status {
-    //         FrameStatus::Unknown => {}
-    //         FrameStatus::Enqueued => {}
-    //         FrameStatus::Presented => {
-    //             if frame_info.frame_number > LAST_PRESENTED as u64 {
-    //                 crate::profiling::finish_frame(
-    //                     frame_info.frame_number as usize,
-    //                     OsTick::new(frame_info.present_time),
-    //                 );
-    //                 crate::profiling::vblank(OsTick::new(frame_info.vblank_time));
-    //                 LAST_PRESENTED = frame_info.frame_number as usize;
-    //             }
-    //         }
-    //     }
-    // }
-    
+    if crate::SyncEnv::frame_history() {
+        process_frame_history();
+    }
+}
+
+/** Reads the layer's frame history and hands every record to its consumers, oldest first
+ *
+ * The history is a ring the size of the layer's queue, so records we've already seen come back every call.
+ * Consumers are expected to skip frame numbers they have already handled.
+ */
+unsafe fn process_frame_history() {
+    #[allow(static_mut_refs)]
+    if FRAME_INFOS.is_empty() {
+        return;
+    }
+
+    #[allow(static_mut_refs)]
+    let num_frames = list_frame_info(FRAME_INFOS.as_mut_ptr(), FRAME_INFOS.len() as i32, LAYER);
+    if num_frames <= 0 {
+        return;
+    }
+
+    #[allow(static_mut_refs)]
+    let frame_infos = &mut FRAME_INFOS[..num_frames as usize];
+    frame_infos.sort_unstable_by_key(|frame_info| frame_info.frame_number);
+
+    for frame_info in frame_infos.iter() {
+        match frame_info.status {
+            FrameStatus::Unknown => {}
+            FrameStatus::Enqueued => {
+                if frame_info.frame_number > LAST_ENQUEUED as u64 {
+                    LAST_ENQUEUED = frame_info.frame_number as usize;
+                }
+            }
+            FrameStatus::Presented => {
+                crate::watchdog::observe(frame_info);
+                crate::latency::observe(frame_info);
+                if frame_info.frame_number > LAST_PRESENTED as u64 {
+                    LAST_PRESENTED = frame_info.frame_number as usize;
+                    let frame = frame_info.frame_number as usize;
+                    crate::frametracer::frame("Enqueued", frame, frame_info.enqueue_time);
+                    crate::frametracer::frame("Presented", frame, frame_info.present_time);
+                    crate::frametracer::frame("VBlank", frame, frame_info.vblank_time);
+                    crate::frametracer::finish_frame(frame);
+                    crate::profiling::finish_frame(frame, OsTick::new(frame_info.present_time));
+                    crate::profiling::vblank(OsTick::new(frame_info.vblank_time));
+                }
+            }
+        }
+    }
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x386fc80, inline)]
+#[skyline::hook(offset = offsets().present_texture_call, inline)]
 unsafe fn present_texture_wrapper(ctx: &InlineCtx) {
     static mut PRESENT_TEXTURE_PTR: Option<extern "C" fn(u64, u64, i32)> = None;
     if PRESENT_TEXTURE_PTR.is_none() {
@@ -182,22 +197,29 @@ unsafe fn present_texture_wrapper(ctx: &InlineCtx) {
         PRESENT_TEXTURE_PTR = Some(
             *base
                 .cast::<u8>()
-                .add(0x593fac8)
+                .add(offsets().nvn_queue_present_texture)
                 .cast::<extern "C" fn(u64, u64, i32)>(),
         );
     }
 
     let ptr = PRESENT_TEXTURE_PTR.unwrap_unchecked();
 
+    crate::render::buffer_swap::check_swap_finished();
+    crate::overrides::tick();
+    crate::render::policy::tick();
+
     let frame = ctx.registers[2].w() as i32;
-    // let present_tick = get_system_tick();
+    crate::frametracer::marker("PresentTexture", get_system_tick());
+    let present_tick = get_system_tick();
     ptr(ctx.registers[0].x(), ctx.registers[1].x(), frame);
 
-    // let mut frame_number = 0u64;
-    // if get_latest_frame_number(&mut frame_number, LAYER) == 0 {
-    //     crate::profiling::submit_frame(frame_number as usize, OsTick::new(present_tick));
-    //     crate::profiling::start_frame(OsTick::new(present_tick));
-    // }
+    if crate::profiling::is_enabled() {
+        let mut frame_number = 0u64;
+        if get_latest_frame_number(&mut frame_number, LAYER) == 0 {
+            crate::profiling::submit_frame(frame_number as usize, OsTick::new(present_tick));
+            crate::profiling::start_frame(OsTick::new(present_tick));
+        }
+    }
 }
 
 // pub fn vsync_thread(layer: Layer, display: Display) {
@@ -247,7 +269,7 @@ unsafe fn present_texture_wrapper(ctx: &InlineCtx) {
 // }
 
 #[symbaker]
-#[skyline::hook(offset = 0x3743ca0, inline)]
+#[skyline::hook(offset = offsets().vi_layer_init, inline)]
 unsafe fn grab_vi_layer_handle(ctx: &InlineCtx) {
     let p_display_info = ctx.registers[19].x();
 
@@ -266,25 +288,25 @@ unsafe fn grab_vi_layer_handle(ctx: &InlineCtx) {
     FRAME_INFOS = vec![FrameInfo::default(); max_frame_info as usize];
 }
 
-#[skyline::from_offset(0x3864700)]
+#[skyline::from_offset(offsets().init_renderpasses)]
 fn init_renderpasses(arg: u64);
 
 #[symbaker]
-#[skyline::hook(offset = 0x374b11c, inline)]
+#[skyline::hook(offset = offsets().init_renderpasses_call, inline)]
 fn profile_init_renderpass(ctx: &InlineCtx) {
     crate::profiling::start_span(
         "InitRenderpasses",
         OsTick::new(unsafe { get_system_tick() }),
     );
     unsafe { init_renderpasses(ctx.registers[0].x()) };
-    crate::profiling::end_span(OsTick::new(unsafe { get_system_tick() }));
+    crate::profiling::end_span("InitRenderpasses", OsTick::new(unsafe { get_system_tick() }));
 }
 
-#[skyline::from_offset(0x3549170)]
+#[skyline::from_offset(offsets().initialize_task_worker)]
 fn init_task_worker(arg1: u64, arg2: u64, arg3: u32, arg4: u32);
 
 #[symbaker]
-#[skyline::hook(offset = 0x374b4f4, inline)]
+#[skyline::hook(offset = offsets().init_ui_task_worker_call, inline)]
 fn profile_init_ui(ctx: &InlineCtx) {
     crate::profiling::start_span(
         "InitUiTaskWorker",
@@ -298,11 +320,11 @@ fn profile_init_ui(ctx: &InlineCtx) {
             ctx.registers[3].w(),
         )
     };
-    crate::profiling::end_span(OsTick::new(unsafe { get_system_tick() }));
+    crate::profiling::end_span("InitUiTaskWorker", OsTick::new(unsafe { get_system_tick() }));
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x374b524, inline)]
+#[skyline::hook(offset = offsets().init_vfx_task_worker_call, inline)]
 fn profile_init_vfx(ctx: &InlineCtx) {
     crate::profiling::start_span(
         "InitVfxTaskWorker",
@@ -316,11 +338,11 @@ fn profile_init_vfx(ctx: &InlineCtx) {
             ctx.registers[3].w(),
         )
     };
-    crate::profiling::end_span(OsTick::new(unsafe { get_system_tick() }));
+    crate::profiling::end_span("InitVfxTaskWorker", OsTick::new(unsafe { get_system_tick() }));
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x374b554, inline)]
+#[skyline::hook(offset = offsets().init_battle_task_worker_call, inline)]
 fn profile_init_battle(ctx: &InlineCtx) {
     crate::profiling::start_span(
         "InitBattleTaskWorker",
@@ -334,27 +356,27 @@ fn profile_init_battle(ctx: &InlineCtx) {
             ctx.registers[3].w(),
         )
     };
-    crate::profiling::end_span(OsTick::new(unsafe { get_system_tick() }));
+    crate::profiling::end_span("InitBattleTaskWorker", OsTick::new(unsafe { get_system_tick() }));
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x374b2b0)]
+#[skyline::hook(offset = offsets().unk_task_worker1)]
 fn profile_unk_taskworker1(ctx: &InlineCtx) {
     crate::profiling::start_span("UnkTaskWorker1", OsTick::new(unsafe { get_system_tick() }));
 
-    crate::profiling::end_span(OsTick::new(unsafe { get_system_tick() }));
+    crate::profiling::end_span("UnkTaskWorker1", OsTick::new(unsafe { get_system_tick() }));
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x3724a80)]
+#[skyline::hook(offset = offsets().run_scene_manager)]
 fn scene_manager_update(manager: u64) {
     crate::profiling::start_span("RunSceneManager", OsTick::new(unsafe { get_system_tick() }));
     call_original!(manager);
-    crate::profiling::end_span(OsTick::new(unsafe { get_system_tick() }));
+    crate::profiling::end_span("RunSceneManager", OsTick::new(unsafe { get_system_tick() }));
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x374bd9c, inline)]
+#[skyline::hook(offset = offsets().cmdbuf_reset_begin, inline)]
 fn cmdbuf_reset_span_start(_: &InlineCtx) {
     crate::profiling::start_span(
         "CommandBufferReset",
@@ -363,56 +385,68 @@ fn cmdbuf_reset_span_start(_: &InlineCtx) {
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x374bfe8, inline)]
+#[skyline::hook(offset = offsets().cmdbuf_reset_end, inline)]
 fn cmdbuf_reset_span_end(_: &InlineCtx) {
-    crate::profiling::end_span(OsTick::new(unsafe { get_system_tick() }));
+    crate::profiling::end_span("CommandBufferReset", OsTick::new(unsafe { get_system_tick() }));
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x374b308, inline)]
+#[skyline::hook(offset = offsets().mutex_lock_begin, inline)]
 fn mutex_lock_span_begin(_: &InlineCtx) {
     crate::profiling::start_span("MutexLock", OsTick::new(unsafe { get_system_tick() }));
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x374b4b4, inline)]
+#[skyline::hook(offset = offsets().mutex_lock_end, inline)]
 fn mutex_lock_span_end(_: &InlineCtx) {
-    crate::profiling::end_span(OsTick::new(unsafe { get_system_tick() }));
+    crate::profiling::end_span("MutexLock", OsTick::new(unsafe { get_system_tick() }));
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x374b130, inline)]
+#[skyline::hook(offset = offsets().looping_begin, inline)]
 fn looping_span_begin(_: &InlineCtx) {
     crate::profiling::start_span("Looping", OsTick::new(unsafe { get_system_tick() }));
 }
 
 #[symbaker]
-#[skyline::hook(offset = 0x374b160, inline)]
+#[skyline::hook(offset = offsets().looping_end, inline)]
 fn looping_span_end(_: &InlineCtx) {
-    crate::profiling::end_span(OsTick::new(unsafe { get_system_tick() }));
+    crate::profiling::end_span("Looping", OsTick::new(unsafe { get_system_tick() }));
 }
 
-#[skyline::from_offset(0x3619080)]
+#[skyline::from_offset(offsets().ui_update)]
 fn ui_update(arg: u64);
 
 #[symbaker]
-#[skyline::hook(offset = 0x374b124, inline)]
+#[skyline::hook(offset = offsets().ui_update_call, inline)]
 fn call_ui_update(ctx: &InlineCtx) {
     crate::profiling::start_span("UiUpdate", OsTick::new(unsafe { get_system_tick() }));
     unsafe {
         ui_update(ctx.registers[0].x());
     }
-    crate::profiling::end_span(OsTick::new(unsafe { get_system_tick() }));
+    crate::profiling::end_span("UiUpdate", OsTick::new(unsafe { get_system_tick() }));
 }
 
-pub fn install(config: SsbuSyncConfig) {
-    Patch::in_text(0x3860674).nop().unwrap();
-    Patch::in_text(0x386fca0).nop().unwrap();
-    Patch::in_text(0x386fc80).nop().unwrap();
-    Patch::in_text(0x3810a40).data(0xD65F03C0u32).unwrap();
-    Patch::in_text(0x386fcdc).nop().unwrap();
+/// Writes this module's patches; `install` puts the hooks in once every module's patches went in.
+pub fn patch(config: SsbuSyncConfig, transaction: &mut Transaction) -> Result<(), PatchError> {
+    transaction.apply(PatchSpec::nop("unk_present_call", offsets().unk_present_call))?;
+    transaction.apply(PatchSpec::nop("acquire_texture_call", offsets().acquire_texture_call))?;
+    transaction.apply(PatchSpec::nop("present_texture_call", offsets().present_texture_call))?;
+    transaction.apply(PatchSpec::data("vsync_update", offsets().vsync_update, patches::RET))?;
+    transaction.apply(PatchSpec::nop("queue_wait_sync_call", offsets().queue_wait_sync_call))?;
 
-    skyline::install_hooks!(
+    if config.profiling {
+        transaction.apply(PatchSpec::nop(
+            "init_renderpasses_call",
+            offsets().init_renderpasses_call,
+        ))?;
+        transaction.apply(PatchSpec::nop("ui_update_call", offsets().ui_update_call))?;
+    }
+    Ok(())
+}
+
+pub fn install(config: SsbuSyncConfig) {
+    patches::install_hooks!(
         grab_vi_layer_handle,
         present_texture_wrapper,
         call_acquire_texture_wrapper,
@@ -420,9 +454,7 @@ pub fn install(config: SsbuSyncConfig) {
     );
 
     if config.profiling {
-        Patch::in_text(0x374b11c).nop().unwrap();
-        Patch::in_text(0x374b124).nop().unwrap();
-        skyline::install_hooks!(
+        patches::install_hooks!(
             scene_manager_update,
             profile_init_renderpass,
             cmdbuf_reset_span_start,
@@ -0,0 +1,127 @@
+/** Frame-skip watchdog
+ *
+ * Watches the frame history `nn::vi::ListFrameHistory` reports and steps down to safer settings when presented frames
+ * start going missing in bursts. Each burst moves one level down:
+ *
+ * 1. `TripleBuffer`: swap to triple buffering through `control::request_buffer_mode` (fires the buffer-mode
+ *    callbacks like any other swap). Skipped straight to the next level where swaps aren't available: on emulator or
+ *    with `allow_buffer_swap` off
+ * 2. `PacerEnabled`: put the game's frame pacer check back
+ * 3. `Vanilla`: put the render sync wait back and the game's own frame index math as well
+ *
+ * A triple buffer swap that can't start yet (another swap is cooling down or the window isn't ready) is retried on
+ * every presented frame until it goes through.
+ *
+ * Hooks can't be removed at runtime, so `Vanilla` is as close to the unmodified pipeline as we can get without a restart.
+ *
+ * `Watchdog` (in `ncore::watchdog`) decides when to step down; this applies each level.
+ */
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::control::ControlError;
+use crate::render::buffer_swap::BufferMode;
+use crate::swapchain::{set_frame_index_policy, FrameIndexPolicy};
+use crate::vsync_history::FrameInfo;
+use crate::{SsbuSyncConfig, SyncEnv};
+
+pub use ncore::watchdog::{DegradeLevel, Escalation, Watchdog, WatchdogConfig};
+
+static WATCHDOG: Mutex<Option<Watchdog>> = Mutex::new(None);
+
+/// Set while the `TripleBuffer` step is waiting for a swap to be allowed.
+static PENDING_TRIPLE: AtomicBool = AtomicBool::new(false);
+
+/// Feeds a frame history record to the running watchdog and steps down if it asks for it.
+pub fn observe(info: &FrameInfo) {
+    if PENDING_TRIPLE.load(Ordering::Acquire) {
+        swap_to_triple();
+    }
+    let escalation = match WATCHDOG.lock() {
+        Ok(mut watchdog) => watchdog.as_mut().and_then(|watchdog| watchdog.feed(info)),
+        Err(_) => None,
+    };
+    if let Some(escalation) = escalation {
+        tracing::warn!(
+            target: "watchdog",
+            "{} skipped and {} late frames before frame {}; stepping down to {:?}",
+            escalation.skipped, escalation.late, escalation.frame_number, escalation.level
+        );
+        degrade(escalation.level);
+    }
+}
+
+pub fn level() -> DegradeLevel {
+    WATCHDOG
+        .lock()
+        .ok()
+        .and_then(|watchdog| watchdog.as_ref().map(Watchdog::level))
+        .unwrap_or(DegradeLevel::Normal)
+}
+
+fn degrade(level: DegradeLevel) {
+    match level {
+        DegradeLevel::Normal => {}
+        DegradeLevel::TripleBuffer => swap_to_triple(),
+        DegradeLevel::PacerEnabled => match crate::pacer::restore_frame_pacer() {
+            Ok(()) => tracing::info!(target: "watchdog", "frame pacer re-enabled"),
+            Err(err) => tracing::warn!(target: "watchdog", "{}", err),
+        },
+        DegradeLevel::Vanilla => {
+            match crate::swapchain::restore_render_sync_wait() {
+                Ok(()) => tracing::info!(target: "watchdog", "render sync wait restored"),
+                Err(err) => tracing::warn!(target: "watchdog", "{}", err),
+            }
+            set_frame_index_policy(FrameIndexPolicy::Default);
+        }
+    }
+}
+
+/// Starts the triple buffer swap, or leaves it pending for the next presented frame if a swap can't start yet.
+fn swap_to_triple() {
+    let pending = match crate::control::request_buffer_mode(BufferMode::Triple) {
+        Ok(()) => false,
+        Err(ControlError::AlreadyInMode(_)) => {
+            tracing::info!(target: "watchdog", "already triple buffered");
+            false
+        }
+        Err(ControlError::Cooldown | ControlError::WindowNotReady | ControlError::NotInstalled) => {
+            if !PENDING_TRIPLE.load(Ordering::Acquire) {
+                tracing::info!(target: "watchdog", "triple buffer swap can't start yet; retrying every frame");
+            }
+            true
+        }
+        Err(err @ (ControlError::NotAllowedOnEmulator | ControlError::BufferSwapDisabled)) => {
+            PENDING_TRIPLE.store(false, Ordering::Release);
+            tracing::info!(target: "watchdog", "triple buffer swap unavailable ({}); skipping to the next level", err);
+            skip(DegradeLevel::TripleBuffer);
+            return;
+        }
+        Err(err) => {
+            tracing::warn!(target: "watchdog", "could not start triple buffer swap: {}", err);
+            false
+        }
+    };
+    PENDING_TRIPLE.store(pending, Ordering::Release);
+}
+
+/// Moves the watchdog past `level`, which can't be applied here, and applies the next one.
+fn skip(level: DegradeLevel) {
+    let next = match WATCHDOG.lock() {
+        Ok(mut watchdog) => watchdog.as_mut().and_then(|watchdog| watchdog.skip(level)),
+        Err(_) => None,
+    };
+    if let Some(next) = next {
+        degrade(next);
+    }
+}
+
+pub fn install(config: &SsbuSyncConfig) {
+    if !config.watchdog {
+        return;
+    }
+    if let Ok(mut watchdog) = WATCHDOG.lock() {
+        *watchdog = Some(Watchdog::new(WatchdogConfig::from_config(config)));
+    }
+    SyncEnv::set_frame_history(true);
+}